@@ -0,0 +1,48 @@
+//! Statistics over recorded values
+
+/// Percentiles reported by `Statistics`
+pub const PERCENTILES: [f64; 5] = [5.0, 25.0, 50.0, 75.0, 95.0];
+
+/// Summary of a set of values
+#[derive(Debug, Clone)]
+pub struct Statistics {
+	pub count: usize,
+	pub min: f64,
+	pub max: f64,
+	pub mean: f64,
+	/// Values at each of `PERCENTILES`
+	pub percentiles: [f64; 5],
+}
+
+impl Statistics {
+	/// Computes statistics of `values`. Returns `None` if there are none.
+	pub fn compute(values: &[f64]) -> Option<Statistics> {
+		if values.is_empty() {
+			return None;
+		}
+		let mut sorted = values.to_vec();
+		sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal));
+
+		let mut percentiles = [0.0; 5];
+		for (value, p) in percentiles.iter_mut().zip(PERCENTILES.iter()) {
+			*value = percentile(&sorted, *p);
+		}
+
+		Some(Statistics {
+			count: sorted.len(),
+			min: sorted[0],
+			max: sorted[sorted.len() - 1],
+			mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+			percentiles,
+		})
+	}
+}
+
+/// Returns the `p`th percentile of sorted values, interpolating between the
+/// closest ranks
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+	let rank = p / 100.0 * (sorted.len() - 1) as f64;
+	let lower = rank.floor() as usize;
+	let upper = rank.ceil() as usize;
+	sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
@@ -2,9 +2,15 @@ use std::{
 	fs,
 	path::PathBuf,
 	cell::RefCell,
+	rc::Rc,
 };
 
-use crate::error::{Error, Result};
+use crate::{
+    error::{Error, Result},
+    datalink::{self, NamedLink, SavedLink, LinkAlias, LinkConfig},
+    info::EcuInfo,
+    obd,
+};
 use directories::ProjectDirs;
 
 use tuneutils::{
@@ -17,7 +23,11 @@ use tuneutils::{
 pub struct App {
 	pub config_dir: PathBuf,
     pub data_dir: PathBuf,
-    pub avail_links: Vec<Box<link::DataLinkEntry>>,
+    pub avail_links: Vec<NamedLink>,
+    /// Links added by the user. Saved to `links.toml` in the config directory
+    pub saved_links: Vec<SavedLink>,
+    /// Names given to discovered links. Saved to `links.toml` with the links
+    pub aliases: Vec<LinkAlias>,
     pub definitions: Definitions,
     pub roms: rom::RomManager,
     pub tunes: rom::tune::TuneManager,
@@ -27,10 +37,14 @@ impl App {
 	/// Attempts to load LibreTuner. This will create any necessary data or
 	/// configuration directories and loads ROM and tune data.
 	pub fn new() -> Result<App> {
-		// Create config and data directories if they do not exist
 		let proj_dirs = ProjectDirs::from("org", "LibreTuner",  "TuneUtils").ok_or(Error::NoHome)?;
-        let config_dir = proj_dirs.config_dir().to_path_buf();
-        let data_dir = proj_dirs.data_dir().to_path_buf();
+        App::open(proj_dirs.config_dir().to_path_buf(), proj_dirs.data_dir().to_path_buf())
+	}
+
+	/// Loads LibreTuner from the given configuration and data directories,
+	/// creating them if they do not exist
+	pub fn open(config_dir: PathBuf, data_dir: PathBuf) -> Result<App> {
+        // Create config and data directories if they do not exist
         fs::create_dir_all(&config_dir)?;
         fs::create_dir_all(&data_dir)?;
 
@@ -48,34 +62,183 @@ impl App {
         fs::create_dir_all(&tune_dir)?;
         let tunes = rom::tune::TuneManager::load(tune_dir)?;
 
-        Ok(App {
+        // Discovered links are listed first and named once the saved names are known
+        let mut avail_links: Vec<NamedLink> = link::discover_datalinks().into_iter()
+            .map(|entry| NamedLink { name: String::new(), identity: Some(datalink::identity(&*entry)), entry })
+            .collect();
+
+        let links_file = datalink::load_links(&config_dir.join("links.toml"))?;
+        for saved in links_file.link.iter() {
+            // Links unsupported by this build are kept in the file but not listed
+            if let Ok(entry) = saved.config.entry() {
+                avail_links.push(NamedLink { name: saved.name.clone(), entry, identity: None });
+            }
+        }
+
+        let mut app = App {
             config_dir,
             data_dir,
-            avail_links: link::discover_datalinks(),
+            avail_links,
+            saved_links: links_file.link,
+            aliases: links_file.alias,
             definitions,
             roms,
             tunes,
-        })
+        };
+        app.name_discovered_links();
+        Ok(app)
 	}
 
-	/// Loads a datalink by id or returns Error::InvalidDatalink
-	pub fn get_datalink(&self, id: usize) -> Result<Box<link::DataLink>> {
-        println!("Getting link {}", id);
-		if id >= self.avail_links.len() {
-			return Err(Error::InvalidDatalink);
-		}
-        println!("Getting and creating....");
-		Ok(self.avail_links[id].create()?)
+	/// Loads a datalink by name or returns Error::InvalidDatalink
+	pub fn get_datalink(&self, name: &str) -> Result<Box<link::DataLink>> {
+		let link = self.avail_links.iter().find(|link| link.name == name).ok_or(Error::InvalidDatalink)?;
+		Ok(link.entry.create()?)
 	}
 
-    /// Creates a platform link from a datalink ID and platform ID
-    pub fn create_platform_link(&self, datalink: usize, platform: &str) -> Result<PlatformLink> {
+    /// Adds a datalink and saves it to the links file
+    pub fn add_link(&mut self, name: &str, config: LinkConfig) -> Result<()> {
+        if !datalink::valid_name(name) {
+            return Err(Error::InvalidLinkName(name.to_owned()));
+        }
+        if self.link_name_used(name) {
+            return Err(Error::DuplicateLink(name.to_owned()));
+        }
+
+        let entry = config.entry()?;
+        let mut saved_links = self.saved_links.clone();
+        saved_links.push(SavedLink { name: name.to_owned(), config });
+        self.save_links(&saved_links, &self.aliases)?;
+
+        self.saved_links = saved_links;
+        self.avail_links.push(NamedLink { name: name.to_owned(), entry, identity: None });
+        self.name_discovered_links();
+        Ok(())
+    }
+
+    /// Removes a datalink added by the user, or the alias of a discovered
+    /// datalink, which then goes back to its default name
+    pub fn remove_link(&mut self, name: &str) -> Result<()> {
+        if let Some(index) = self.saved_links.iter().position(|link| link.name == name) {
+            let mut saved_links = self.saved_links.clone();
+            saved_links.remove(index);
+            self.save_links(&saved_links, &self.aliases)?;
+            self.saved_links = saved_links;
+            self.avail_links.retain(|link| !(link.saved() && link.name == name));
+        } else if let Some(index) = self.aliases.iter().position(|alias| alias.name == name) {
+            let mut aliases = self.aliases.clone();
+            aliases.remove(index);
+            self.save_links(&self.saved_links, &aliases)?;
+            self.aliases = aliases;
+        } else if self.avail_links.iter().any(|link| link.name == name) {
+            return Err(Error::DiscoveredLink(name.to_owned()));
+        } else {
+            return Err(Error::InvalidDatalink);
+        }
+        self.name_discovered_links();
+        Ok(())
+    }
+
+    /// Renames a datalink. Discovered datalinks are renamed by saving an
+    /// alias for their identity.
+    pub fn rename_link(&mut self, name: &str, new_name: &str) -> Result<()> {
+        if !datalink::valid_name(new_name) {
+            return Err(Error::InvalidLinkName(new_name.to_owned()));
+        }
+        if self.link_name_used(new_name) {
+            return Err(Error::DuplicateLink(new_name.to_owned()));
+        }
+
+        if let Some(index) = self.saved_links.iter().position(|link| link.name == name) {
+            let mut saved_links = self.saved_links.clone();
+            saved_links[index].name = new_name.to_owned();
+            self.save_links(&saved_links, &self.aliases)?;
+            self.saved_links = saved_links;
+            for link in self.avail_links.iter_mut().filter(|link| link.saved() && link.name == name) {
+                link.name = new_name.to_owned();
+            }
+        } else {
+            let identity = self.avail_links.iter()
+                .find(|link| link.name == name)
+                .and_then(|link| link.identity.clone())
+                .ok_or(Error::InvalidDatalink)?;
+            let mut aliases = self.aliases.clone();
+            aliases.retain(|alias| alias.link != identity);
+            aliases.push(LinkAlias { name: new_name.to_owned(), link: identity });
+            self.save_links(&self.saved_links, &aliases)?;
+            self.aliases = aliases;
+        }
+        self.name_discovered_links();
+        Ok(())
+    }
+
+    /// Returns true if a listed link, saved link or alias uses `name`.
+    /// Aliases of links that are not connected still reserve their name.
+    fn link_name_used(&self, name: &str) -> bool {
+        self.avail_links.iter().any(|link| link.name == name)
+            || self.saved_links.iter().any(|link| link.name == name)
+            || self.aliases.iter().any(|alias| alias.name == name)
+    }
+
+    /// Names discovered links by their alias, or by their type and interface.
+    /// Saved links and aliases keep their names, so a discovered link whose
+    /// default name is already taken is numbered instead.
+    fn name_discovered_links(&mut self) {
+        let mut taken: Vec<String> = self.saved_links.iter().map(|link| link.name.clone())
+            .chain(self.aliases.iter().map(|alias| alias.name.clone()))
+            .collect();
+        let mut aliased: Vec<&str> = Vec::new();
+
+        for link in self.avail_links.iter_mut() {
+            let identity = match link.identity {
+                Some(ref identity) => identity,
+                None => continue,
+            };
+            // An alias names only the first of several identical devices
+            let alias = self.aliases.iter()
+                .find(|alias| &alias.link == identity && !aliased.contains(&alias.name.as_str()));
+            link.name = match alias {
+                Some(alias) => {
+                    aliased.push(&alias.name);
+                    alias.name.clone()
+                },
+                None => {
+                    let name = datalink::unique_name(datalink::default_name(&*link.entry), &taken);
+                    taken.push(name.clone());
+                    name
+                },
+            };
+        }
+    }
+
+    /// Writes user-added links and aliases to `links.toml` in the config
+    /// directory. Changes are saved before they are applied, so the app
+    /// matches the file if saving fails.
+    fn save_links(&self, links: &[SavedLink], aliases: &[LinkAlias]) -> Result<()> {
+        datalink::save_links(&self.config_dir.join("links.toml"), links, aliases)
+    }
+
+    /// Creates a platform link from a datalink name and platform ID
+    pub fn create_platform_link(&self, datalink: &str, platform: &str) -> Result<PlatformLink> {
         let datalink = self.get_datalink(datalink);
         let datalink = datalink?;
         let platform = self.definitions.find(platform).ok_or(Error::InvalidPlatform)?;
         Ok(PlatformLink::new(datalink, platform.clone()))
     }
 
+    /// Creates a UDS interface to the ECU of a platform. The generic OBD-II
+    /// platform is used for `obd::PLATFORM_ID` and works with any datalink
+    /// that provides CAN.
+    pub fn create_uds(&self, datalink: &str, platform: &str) -> Result<Rc<UdsInterface>> {
+        if platform == obd::PLATFORM_ID {
+            let datalink = self.get_datalink(datalink)?;
+            let can = datalink.can().ok_or(Error::CanUnsupported)?;
+            // Default options address the engine ECU (0x7E0/0x7E8)
+            let isotp = Rc::new(isotp::IsotpCan::new(can, isotp::Options::default()));
+            return Ok(Rc::new(UdsIsotp::new(isotp)));
+        }
+        self.create_platform_link(datalink, platform)?.uds().ok_or(Error::InvalidDatalink)
+    }
+
     /// Returns a list of all platform definitions in the format (name, id)
     pub fn list_platforms(&self) -> Vec<(&str, &str)> {
         let mut platforms = Vec::new();
@@ -87,15 +250,52 @@ impl App {
         platforms
     }
 
-    pub fn download(&mut self, link: &PlatformLink, id: &str, name: &str, callback: &DownloadCallback) -> Result<()> {
+    /// Downloads a ROM and saves it with the identification of the ECU it
+    /// was read from. Returns the identification if it could be read.
+    pub fn download(&mut self, link: &PlatformLink, id: &str, name: &str, callback: &DownloadCallback) -> Result<Option<EcuInfo>> {
         let downloader = link.downloader().ok_or(Error::DownloadUnsupported)?;
+
+        // Identify the ECU before downloading while it is in the default session.
+        // Fields the ECU does not support are left empty, so an error here means
+        // the ECU cannot be reached and the download would fail as well.
+        let info = match link.uds() {
+            Some(interface) => Some(EcuInfo::read(&*interface)?),
+            None => None,
+        };
+
         let response = downloader.download(callback)?;
 
         let model = link.platform.identify(&response.data).ok_or(Error::UnknownModel)?;
         let rom = self.roms.new_rom(name.to_owned(), id.to_owned(), link.platform.clone(), model.clone(), response.data);
-        self.roms.save_meta().unwrap();
-        rom.save().unwrap();
+        self.roms.save_meta()?;
+        rom.save()?;
 
-        Ok(())
+        if let Some(ref info) = info {
+            info.save(&self.rom_info_path(id))?;
+        }
+        Ok(info)
+    }
+
+    /// Directory recorded logs are saved to
+    pub fn logs_dir(&self) -> PathBuf {
+        self.data_dir.join("logs")
+    }
+
+    /// Path of the ECU identification saved with a downloaded ROM
+    pub fn rom_info_path(&self, id: &str) -> PathBuf {
+        self.data_dir.join("roms").join(format!("{}.info.toml", id))
+    }
+
+    /// Loads the ECU identification saved with a downloaded ROM. Returns
+    /// `None` if the ECU could not be identified when it was downloaded.
+    pub fn rom_info(&self, id: &str) -> Result<Option<EcuInfo>> {
+        if !self.roms.roms.iter().any(|rom| rom.id == id) {
+            return Err(Error::InvalidRom);
+        }
+        let path = self.rom_info_path(id);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(EcuInfo::load(&path)?))
     }
 }
\ No newline at end of file
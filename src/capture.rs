@@ -0,0 +1,134 @@
+//! Writers for captured CAN traffic
+
+use std::{
+	fs::File,
+	io::{self, BufWriter, Write},
+	path::Path,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use tuneutils::protocols::can::Message;
+
+
+
+/// pcap link type of SocketCAN frames
+const LINKTYPE_CAN_SOCKETCAN: u32 = 227;
+/// Flag marking 29 bit ids in SocketCAN frames
+const EFF_FLAG: u32 = 0x8000_0000;
+
+/// Format of a capture file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureFormat {
+	/// Text log as written by `candump -l`
+	Candump,
+	/// pcap with SocketCAN link type, readable by Wireshark
+	Pcap,
+}
+
+impl CaptureFormat {
+	/// Guesses the format from a file extension. Defaults to candump.
+	pub fn from_path(path: &Path) -> CaptureFormat {
+		match path.extension().and_then(|ext| ext.to_str()) {
+			Some("pcap") => CaptureFormat::Pcap,
+			_ => CaptureFormat::Candump,
+		}
+	}
+}
+
+
+
+/// Writes CAN frames to a capture file
+pub struct CaptureWriter {
+	out: BufWriter<File>,
+	format: CaptureFormat,
+	/// Interface name written to candump logs
+	interface: String,
+}
+
+impl CaptureWriter {
+	/// Creates a capture file, replacing any existing file
+	pub fn create(path: &Path, format: CaptureFormat, interface: &str) -> io::Result<CaptureWriter> {
+		let mut out = BufWriter::new(File::create(path)?);
+
+		if format == CaptureFormat::Pcap {
+			// Global header: magic, version 2.4, UTC offset, accuracy, snaplen, link type
+			out.write_all(&0xa1b2_c3d4u32.to_le_bytes())?;
+			out.write_all(&2u16.to_le_bytes())?;
+			out.write_all(&4u16.to_le_bytes())?;
+			out.write_all(&0i32.to_le_bytes())?;
+			out.write_all(&0u32.to_le_bytes())?;
+			out.write_all(&65535u32.to_le_bytes())?;
+			out.write_all(&LINKTYPE_CAN_SOCKETCAN.to_le_bytes())?;
+		}
+
+		Ok(CaptureWriter {
+			out,
+			format,
+			interface: interface.chars().map(|c| if c.is_whitespace() { '_' } else { c }).collect(),
+		})
+	}
+
+	/// Writes a frame received at `time`
+	pub fn write(&mut self, time: SystemTime, frame: &Message) -> io::Result<()> {
+		let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+		let data = &frame.data[..frame.len as usize];
+
+		match self.format {
+			CaptureFormat::Candump => {
+				let id = if frame.id > 0x7FF { format!("{:08X}", frame.id) } else { format!("{:03X}", frame.id) };
+				let data: String = data.iter().map(|b| format!("{:02X}", b)).collect();
+				writeln!(self.out, "({}.{:06}) {} {}#{}", since_epoch.as_secs(), since_epoch.subsec_micros(), self.interface, id, data)
+			},
+			CaptureFormat::Pcap => {
+				// Record header: seconds, microseconds, captured and original length
+				self.out.write_all(&(since_epoch.as_secs() as u32).to_le_bytes())?;
+				self.out.write_all(&since_epoch.subsec_micros().to_le_bytes())?;
+				self.out.write_all(&16u32.to_le_bytes())?;
+				self.out.write_all(&16u32.to_le_bytes())?;
+
+				// SocketCAN frame. The id is big endian for this link type.
+				let id = if frame.id > 0x7FF { frame.id | EFF_FLAG } else { frame.id };
+				self.out.write_all(&id.to_be_bytes())?;
+				self.out.write_all(&[frame.len, 0, 0, 0])?;
+				let mut padded = [0u8; 8];
+				padded[..data.len()].copy_from_slice(data);
+				self.out.write_all(&padded)
+			},
+		}
+	}
+
+	/// Flushes buffered frames to the file
+	pub fn flush(&mut self) -> io::Result<()> {
+		self.out.flush()
+	}
+}
+
+
+
+/// Filter on CAN ids. A frame passes if `id & mask == self.id & mask`, or
+/// if it does not when the filter is inverted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdFilter {
+	pub id: u32,
+	pub mask: u32,
+	pub inverted: bool,
+}
+
+impl IdFilter {
+	/// Parses a filter in candump syntax, "id:mask", "id~mask" for an
+	/// inverted filter, or a single id
+	pub fn parse(s: &str) -> Option<IdFilter> {
+		let inverted = s.contains('~');
+		let mut parts = s.splitn(2, if inverted { '~' } else { ':' });
+		let id = u32::from_str_radix(parts.next()?, 16).ok()?;
+		let mask = match parts.next() {
+			Some(mask) => u32::from_str_radix(mask, 16).ok()?,
+			None => 0x1FFF_FFFF,
+		};
+		Some(IdFilter { id, mask, inverted })
+	}
+
+	pub fn matches(&self, id: u32) -> bool {
+		(id & self.mask == self.id & self.mask) != self.inverted
+	}
+}
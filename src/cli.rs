@@ -1,38 +1,179 @@
 #![cfg(feature = "cli")]
 
-use std::ffi::OsString;
 use std::cell::RefCell;
-use std::cmp::max;
+use std::ffi::OsString;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::rc::Rc;
 
 use tuneutils::{
-	link,
-	definition::Definitions,
+	error::Error as TuneError,
 	download::DownloadCallback,
 	diagnostics::UdsScanner,
+	protocols::uds::UdsInterface,
 };
-#[cfg(feature = "socketcan")]
-use tuneutils::link::SocketCanDataLinkEntry;
 
 use crate::{
 	app::App,
 	error::{Error, Result},
+	output::{Format, Table},
+	datalink::{LinkConfig, net},
+	completion::Completions,
+	capture::{CaptureFormat, CaptureWriter, IdFilter},
+	interrupt,
+	uds,
+	diag,
+	dtc,
+	info::EcuInfo,
+	obd,
+	logger,
+	logfile::{self, Datalog, Recorder},
+	dashboard::{Dashboard, GaugeConfig},
+	analysis::{Statistics, PERCENTILES},
+	expr::Expr,
+	plot::{self, Series},
+	math::MathChannel,
 };
+#[cfg(feature = "serial")]
+use crate::datalink::{elm327, slcan};
 
 use clap::value_t;
+use shlex::Shlex;
+use serde_json::json;
+use serde::Deserialize;
 
 
 
 pub struct CommandContext<'a> {
 	app: &'a mut App,
 	commands: &'a Vec<Command>,
+	settings: &'a mut Settings,
 	args: &'a mut Iterator<Item=String>,
 }
 
 
+/// Settings shared by all commands
+#[derive(Debug, Default)]
+pub struct Settings {
+	/// Format used by listing commands
+	pub format: Format,
+	/// Datalink used when a command's datalink argument is omitted
+	pub datalink: Option<String>,
+	/// Platform used when a command's platform argument is omitted
+	pub platform: Option<String>,
+	/// Canonical paths of the scripts being run by 'source', innermost last
+	pub scripts: Vec<PathBuf>,
+	/// Script requested by 'source' and whether to keep going after a
+	/// failure. It runs once the 'source' callback has returned.
+	pub pending_script: Option<(PathBuf, bool)>,
+	/// Dashboard gauge settings keyed by PID name
+	pub gauges: HashMap<String, GaugeConfig>,
+	/// Math channels keyed by platform id
+	pub math: HashMap<String, Vec<MathChannel>>,
+	/// Log opened by 'log_open'
+	pub log: Option<Datalog>,
+}
+
+impl Settings {
+	/// Creates settings from the user's configuration
+	pub fn from_config(config: &Config) -> Result<Settings> {
+		Ok(Settings {
+			format: match config.format {
+				Some(ref format) => format.parse()?,
+				None => Format::default(),
+			},
+			datalink: config.datalink.clone(),
+			platform: config.platform.clone(),
+			scripts: Vec::new(),
+			pending_script: None,
+			gauges: config.gauges.clone(),
+			math: config.math.clone(),
+			log: None,
+		})
+	}
+}
+
+
+/// Per-user CLI configuration, loaded from `cli.toml` in the config directory
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+	/// Default datalink name
+	pub datalink: Option<String>,
+	/// Default platform id
+	pub platform: Option<String>,
+	/// Output format of listing commands (text, json or csv)
+	pub format: Option<String>,
+	/// Prompt shown by the interactive shell
+	pub prompt: Option<String>,
+	/// Commands run when the interactive shell starts
+	pub startup: Vec<String>,
+	/// Dashboard gauge settings keyed by PID name
+	pub gauges: HashMap<String, GaugeConfig>,
+	/// Math channels keyed by platform id, e.g. `[[math.obd2]]`
+	pub math: HashMap<String, Vec<MathChannel>>,
+}
+
+impl Config {
+	/// Loads the configuration file. Returns the default configuration if
+	/// the file does not exist.
+	pub fn load(path: &Path) -> Result<Config> {
+		if !path.exists() {
+			return Ok(Config::default());
+		}
+		let contents = fs::read_to_string(path)?;
+		Ok(toml::from_str(&contents)?)
+	}
+}
+
+
+/// Datalink types accepted by 'add_link'
+const LINK_TYPES: &[&str] = &["socketcan", "sim", "elm327", "slcan", "net", "replay"];
+
+/// Options of `link_operand_args` that replace the datalink and platform
+/// arguments
+const LINK_OPTIONS: &[(&str, ArgKind)] = &[("-l", ArgKind::Datalink), ("--link", ArgKind::Datalink), ("-p", ArgKind::Platform), ("--platform", ArgKind::Platform)];
+
+/// Options of the log filter arguments shared by 'log_stats' and 'log_plot'
+const LOG_FILTER_OPTIONS: &[(&str, ArgKind)] = &[("--from", ArgKind::Other), ("--to", ArgKind::Other), ("-w", ArgKind::Other), ("--where", ArgKind::Other)];
+
+
+/// Kind of a positional argument, used for tab completion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+	/// Datalink name from `App::avail_links`
+	Datalink,
+	/// Platform id from `App::definitions`
+	Platform,
+	/// ROM id from `App::roms`
+	Rom,
+	/// Tune id from `App::tunes`
+	Tune,
+	/// PID id of the platform given earlier on the line. Completes all
+	/// remaining arguments.
+	Pid,
+	/// Name of a recorded log
+	Log,
+	/// Name of a registered command
+	Command,
+	/// One of a fixed set of values
+	Values(&'static [&'static str]),
+	/// Anything else. Not completed.
+	Other,
+}
+
+
 pub struct Command {
 	pub description: String,
 	pub command: String,
 	pub callback: RefCell<Box<FnMut(CommandContext) -> Result<()>>>,
+	/// Kinds of the positional arguments, in order
+	pub args: Vec<ArgKind>,
+	/// Options that take a value, with the kinds of their values
+	pub options: Vec<(&'static str, ArgKind)>,
 }
 
 
@@ -49,14 +190,31 @@ impl Command {
 			description,
 			command,
 			callback: RefCell::new(Box::new(callback)),
+			args: Vec::new(),
+			options: Vec::new(),
 		}
 	}
+
+	/// Sets the kinds of the positional arguments used for tab completion
+	pub fn with_args(mut self, args: Vec<ArgKind>) -> Command {
+		self.args = args;
+		self
+	}
+
+	/// Sets the options that take a value, by each of their names, e.g.
+	/// `("-n", ArgKind::Other)` and `("--name", ArgKind::Other)`. Their values
+	/// are completed by kind and are not counted as positional arguments.
+	pub fn with_options(mut self, options: Vec<(&'static str, ArgKind)>) -> Command {
+		self.options = options;
+		self
+	}
 }
 
 /// Cli application
 pub struct Cli<'a> {
 	app: &'a mut App,
 	commands: Vec<Command>,
+	pub settings: Settings,
 }
 
 mod commands {
@@ -64,6 +222,101 @@ mod commands {
 
 
 
+	/// Returns the datalink name from `matches` or the configured default
+	fn datalink_name(context: &CommandContext, matches: &clap::ArgMatches) -> Result<String> {
+		match matches.value_of("link").or_else(|| matches.value_of("datalink")) {
+			Some(name) => Ok(name.to_owned()),
+			None => context.settings.datalink.clone().ok_or(Error::NoDefault("datalink")),
+		}
+	}
+
+	/// Returns the platform id from `matches` or the configured default
+	fn platform_id(context: &CommandContext, matches: &clap::ArgMatches) -> Result<String> {
+		match matches.value_of("link-platform").or_else(|| matches.value_of("platform")) {
+			Some(id) => Ok(id.to_owned()),
+			None => context.settings.platform.clone().ok_or(Error::NoDefault("platform")),
+		}
+	}
+
+	/// Creates a UDS interface from the datalink and platform in `matches`
+	/// or the configured defaults
+	fn uds_interface(context: &CommandContext, matches: &clap::ArgMatches) -> Result<Rc<UdsInterface>> {
+		context.app.create_uds(&datalink_name(context, matches)?, &platform_id(context, matches)?)
+	}
+
+	/// Returns the configured math channels of a platform
+	fn math_channels<'a>(context: &'a CommandContext, platform: &str) -> &'a [MathChannel] {
+		context.settings.math.get(platform).map(Vec::as_slice).unwrap_or(&[])
+	}
+
+	/// Optional datalink and platform arguments at positions 1 and 2
+	fn link_args<'a, 'b>() -> Vec<clap::Arg<'a, 'b>> {
+		vec![
+			clap::Arg::with_name("datalink")
+				.help("Name of the datalink to use. Can be found using the 'links' command. Defaults to the configured datalink")
+				.index(1),
+			clap::Arg::with_name("platform")
+				.help("ID of the platform. Can be found using the 'platforms' command. Defaults to the configured platform")
+				.index(2),
+		]
+	}
+
+	/// Returns true if `args` give the datalink or platform by an option
+	/// from `LINK_OPTIONS`
+	fn has_link_option(args: &[String]) -> bool {
+		args.iter().take_while(|arg| *arg != "--").any(|arg| LINK_OPTIONS.iter().any(|&(option, _)| {
+			// Values may be attached, as in --link=sim or -lsim
+			arg == option || arg.starts_with(&format!("{}=", option)) || (!option.starts_with("--") && arg.starts_with(option) && !arg.starts_with("--"))
+		}))
+	}
+
+	/// Arguments of a command that takes a datalink and platform followed by
+	/// its own `operands`, as in `download <datalink> <platform> <id>`. The
+	/// datalink and platform are positional unless `--link` or `--platform`
+	/// is given in `args`. Either of those may then be left out to use the
+	/// configured default.
+	fn link_operand_args<'a, 'b>(args: &[String], operands: Vec<clap::Arg<'a, 'b>>) -> Vec<clap::Arg<'a, 'b>> {
+		let mut all = vec![
+			clap::Arg::with_name("link")
+				.help("Name of the datalink to use instead of the datalink argument. Defaults to the configured datalink if only --platform is given")
+				.short("l")
+				.long("link")
+				.takes_value(true),
+			clap::Arg::with_name("link-platform")
+				.help("ID of the platform to use instead of the platform argument. Defaults to the configured platform if only --link is given")
+				.short("p")
+				.long("platform")
+				.takes_value(true),
+		];
+		let first = if has_link_option(args) {
+			1
+		} else {
+			all.push(clap::Arg::with_name("datalink")
+				.help("Name of the datalink to use. Can be found using the 'links' command. Left out if --link or --platform is given")
+				.index(1)
+				.required(true));
+			all.push(clap::Arg::with_name("platform")
+				.help("ID of the platform. Can be found using the 'platforms' command. Left out if --link or --platform is given")
+				.index(2)
+				.required(true));
+			3
+		};
+		all.extend(operands.into_iter().enumerate().map(|(index, arg)| arg.index(first + index as u64)));
+		all
+	}
+
+	/// Asks the user a yes or no question. Returns false if stdin is closed.
+	fn confirm(question: &str) -> Result<bool> {
+		print!("{} [y/N] ", question);
+		io::stdout().flush()?;
+		let mut answer = String::new();
+		io::stdin().read_line(&mut answer)?;
+		let answer = answer.trim().to_lowercase();
+		Ok(answer == "y" || answer == "yes")
+	}
+
+
+
 	pub fn help<'a, I>(commands: I)
 	where I: Iterator<Item=&'a Command> {
 		for command in commands {
@@ -73,193 +326,390 @@ mod commands {
 
 
 
-	pub fn links<'a>(links: &Vec<Box<link::DataLinkEntry>>) {
-		// Get longest type and description
-		let mut longest_type = "Type".len();
-		// Find the amount of spaces the largest id will take. Minus one beacuse the highest id is len() - 1
-		let id_len = (|| {
-			if links.len() >= 100 {
-				return ((links.len() - 1) as f64).log10() as usize + 1
-			}
-			"Id".len()
-		})();
-
-		for entry in links.iter() {
-			longest_type = max(entry.typename().len(), longest_type);
-		}
-			
-		println!("{1:<0$}   {3:<2$}   {4}", id_len, "Id", longest_type, "Type", "Description");
-		// Print
-		for (id, entry) in links.iter().enumerate() {
-			println!("{1:<0$}   {3:<2$}   {4}", id_len, id.to_string(), longest_type, entry.typename(), entry.description());
+	pub fn links(context: &mut CommandContext) -> Result<()> {
+		let mut table = Table::new(&[("name", "Name"), ("type", "Type"), ("saved", "Saved"), ("description", "Description")]);
+		for link in context.app.avail_links.iter() {
+			table.add_row(vec![json!(link.name), json!(link.entry.typename()), json!(link.saved()), json!(link.entry.description())]);
 		}
+		table.print(context.settings.format)
 	}
 
 
 
+	/// Argument giving the name of a new datalink
+	fn link_name_arg<'a, 'b>() -> clap::Arg<'a, 'b> {
+		clap::Arg::with_name("name")
+			.help("Name used to refer to the datalink. Defaults to the interface or port")
+			.short("n")
+			.long("name")
+			.takes_value(true)
+	}
+
+	/// Returns the default name of a link on a serial port, e.g. ttyUSB0
+	fn port_name(port: &str) -> &str {
+		Path::new(port).file_name().and_then(|name| name.to_str()).unwrap_or(port)
+	}
+
 	pub fn add_link(context: &mut CommandContext) -> Result<()> {
-		let mut matches = clap::App::new("add_link")
+		let matches = clap::App::new("add_link")
 			.about("Adds a datalink")
 			.setting(clap::AppSettings::NoBinaryName)
-			.setting(clap::AppSettings::SubcommandRequired);
-		#[cfg(feature = "socketcan")]
-		{
-			matches = matches.subcommand(clap::SubCommand::with_name("socketcan")
+			.setting(clap::AppSettings::SubcommandRequired)
+			.subcommand(clap::SubCommand::with_name("socketcan")
 				.about("Adds a socketcan interface")
 				.arg(clap::Arg::with_name("interface")
 					.help("SocketCAN interface name")
 					.index(1)
-					.required(true)));
-		}
-		let matches = matches.get_matches_from_safe(context.args.into_iter())?;
+					.required(true))
+				.arg(link_name_arg()))
+			.subcommand(clap::SubCommand::with_name("sim")
+				.about("Adds a simulated ECU")
+				.arg(clap::Arg::with_name("config")
+					.help("Simulation configuration file. Uses a default simulation if omitted")
+					.index(1))
+				.arg(clap::Arg::with_name("name")
+					.help("Name used to refer to the datalink. Defaults to 'sim'")
+					.short("n")
+					.long("name")
+					.takes_value(true)))
+			.subcommand(clap::SubCommand::with_name("elm327")
+				.about("Adds an ELM327 compatible serial adapter")
+				.arg(clap::Arg::with_name("port")
+					.help("Serial port of the adapter, e.g. /dev/ttyUSB0 or COM3")
+					.index(1)
+					.required(true))
+				.arg(clap::Arg::with_name("baud")
+					.help("Baud rate of the adapter. Defaults to 38400")
+					.index(2))
+				.arg(link_name_arg()))
+			.subcommand(clap::SubCommand::with_name("slcan")
+				.about("Adds an SLCAN (Lawicel) serial CAN adapter")
+				.arg(clap::Arg::with_name("port")
+					.help("Serial port of the adapter, e.g. /dev/ttyACM0 or COM3")
+					.index(1)
+					.required(true))
+				.arg(clap::Arg::with_name("bitrate")
+					.help("CAN bitrate in bits per second. Defaults to 500000")
+					.short("b")
+					.long("bitrate")
+					.takes_value(true))
+				.arg(clap::Arg::with_name("baud")
+					.help("Serial baud rate. Defaults to 115200")
+					.long("baud")
+					.takes_value(true))
+				.arg(link_name_arg()))
+			.subcommand(clap::SubCommand::with_name("net")
+				.about("Adds a CAN bus bridged over the network (cannelloni)")
+				.arg(clap::Arg::with_name("remote")
+					.help("Address of the remote peer, e.g. gateway:20000")
+					.index(1)
+					.required(true))
+				.arg(clap::Arg::with_name("protocol")
+					.help("Transport protocol. Defaults to udp")
+					.short("p")
+					.long("protocol")
+					.takes_value(true)
+					.possible_values(&["udp", "tcp"]))
+				.arg(clap::Arg::with_name("local")
+					.help("Local address to receive UDP packets on. Defaults to the remote port on all interfaces")
+					.short("l")
+					.long("local")
+					.takes_value(true))
+				.arg(clap::Arg::with_name("name")
+					.help("Name used to refer to the datalink. Defaults to the remote address")
+					.short("n")
+					.long("name")
+					.takes_value(true)))
+			.subcommand(clap::SubCommand::with_name("replay")
+				.about("Adds a replay of a candump or Vector ASC trace")
+				.arg(clap::Arg::with_name("file")
+					.help("Trace file")
+					.index(1)
+					.required(true))
+				.arg(clap::Arg::with_name("realtime")
+					.help("Replay frames with their recorded timing instead of as fast as possible")
+					.short("r")
+					.long("realtime"))
+				.arg(clap::Arg::with_name("match")
+					.help("Answer each request with the frames recorded after the same request")
+					.short("m")
+					.long("match"))
+				.arg(clap::Arg::with_name("name")
+					.help("Name used to refer to the datalink. Defaults to 'replay'")
+					.short("n")
+					.long("name")
+					.takes_value(true)))
+			.get_matches_from_safe(context.args.into_iter())?;
 
-		#[cfg(feature = "socketcan")]
-		{
-			if let Some(matches) = matches.subcommand_matches("socketcan") {
+		let (name, config) = match matches.subcommand() {
+			("socketcan", Some(matches)) => {
 				let interface = matches.value_of("interface").unwrap();
-				context.app.avail_links.push(Box::new(link::SocketCanDataLinkEntry { interface: interface.to_owned(), }));
-				println!("Added SocketCAN interface \"{}\"", interface);
-				return Ok(());
-			}
-		}
-		// Should never reach this...
+				(matches.value_of("name").unwrap_or(interface), LinkConfig::SocketCan { interface: interface.to_owned() })
+			},
+			("sim", Some(matches)) => {
+				// Store an absolute path so the link works from any directory
+				let config = match matches.value_of("config") {
+					Some(path) => Some(fs::canonicalize(path)?),
+					None => None,
+				};
+				(matches.value_of("name").unwrap_or("sim"), LinkConfig::Sim { config })
+			},
+			#[cfg(feature = "serial")]
+			("elm327", Some(matches)) => {
+				let port = matches.value_of("port").unwrap();
+				let baud = match matches.value_of("baud") {
+					Some(_) => value_t!(matches, "baud", u32)?,
+					None => elm327::DEFAULT_BAUD,
+				};
+				(matches.value_of("name").unwrap_or_else(|| port_name(port)), LinkConfig::Elm327 { port: port.to_owned(), baud })
+			},
+			#[cfg(feature = "serial")]
+			("slcan", Some(matches)) => {
+				let port = matches.value_of("port").unwrap();
+				let bitrate = match matches.value_of("bitrate") {
+					Some(_) => value_t!(matches, "bitrate", u32)?,
+					None => slcan::DEFAULT_BITRATE,
+				};
+				let baud = match matches.value_of("baud") {
+					Some(_) => value_t!(matches, "baud", u32)?,
+					None => slcan::DEFAULT_BAUD,
+				};
+				(matches.value_of("name").unwrap_or_else(|| port_name(port)), LinkConfig::Slcan { port: port.to_owned(), baud, bitrate })
+			},
+			// Serial adapters cannot be opened without the serial feature
+			#[cfg(not(feature = "serial"))]
+			("elm327", Some(_)) => return Err(Error::UnsupportedLink("elm327")),
+			#[cfg(not(feature = "serial"))]
+			("slcan", Some(_)) => return Err(Error::UnsupportedLink("slcan")),
+			("net", Some(matches)) => {
+				let remote = matches.value_of("remote").unwrap();
+				let protocol = match matches.value_of("protocol") {
+					Some("tcp") => net::Protocol::Tcp,
+					_ => net::Protocol::Udp,
+				};
+				let local = matches.value_of("local").map(|local| local.to_owned());
+				(matches.value_of("name").unwrap_or(remote), LinkConfig::Net { remote: remote.to_owned(), local, protocol })
+			},
+			("replay", Some(matches)) => {
+				let file = fs::canonicalize(matches.value_of("file").unwrap())?;
+				let config = LinkConfig::Replay {
+					file,
+					realtime: matches.is_present("realtime"),
+					match_requests: matches.is_present("match"),
+				};
+				(matches.value_of("name").unwrap_or("replay"), config)
+			},
+			// SubcommandRequired is set
+			_ => unreachable!(),
+		};
+
+		context.app.add_link(name, config)?;
+		println!("Added datalink \"{}\"", name);
 		Ok(())
 	}
 
 
 
-	pub fn platforms(definitions: &Definitions) {
-		// Find the longest definition id & name
-		let mut longest_id = "Id".len();
-
-		for definition in definitions.definitions.iter() {
-			longest_id = max(definition.id.len(), longest_id);
-		}
+	pub fn remove_link(context: &mut CommandContext) -> Result<()> {
+		let matches = clap::App::new("remove_link")
+			.about("Removes a datalink added with 'add_link', or the name given to a discovered datalink")
+			.setting(clap::AppSettings::NoBinaryName)
+			.arg(clap::Arg::with_name("datalink")
+				.help("Name of the datalink to remove")
+				.index(1)
+				.required(true))
+			.get_matches_from_safe(context.args.into_iter())?;
 
-		// Print the second loop
-		println!("{1:<0$}   {2}", longest_id, "Id", "Name");
-		for definition in definitions.definitions.iter() {
-			println!("{1:<0$}   {2}", longest_id, definition.id, definition.name);
-		}
+		let name = matches.value_of("datalink").unwrap();
+		context.app.remove_link(name)?;
+		println!("Removed datalink \"{}\"", name);
+		Ok(())
 	}
 
 
 
-	pub fn download(context: &mut CommandContext) -> Result<()> {
-		let matches = clap::App::new("download")
-			.about("Downloads a rom from a platform link")
+	pub fn rename_link(context: &mut CommandContext) -> Result<()> {
+		let matches = clap::App::new("rename_link")
+			.about("Renames a datalink. Names of discovered datalinks are saved as aliases")
 			.setting(clap::AppSettings::NoBinaryName)
 			.arg(clap::Arg::with_name("datalink")
-				.help("ID of the datalink to use. Can be found using the 'links' command")
+				.help("Current name of the datalink")
 				.index(1)
 				.required(true))
-			.arg(clap::Arg::with_name("platform")
-				.help("ID of the platform to download from. Can be found using the 'platforms' command")
+			.arg(clap::Arg::with_name("name")
+				.help("New name of the datalink")
 				.index(2)
 				.required(true))
-			.arg(clap::Arg::with_name("id")
-				.help("Identifier given to the ROM when saving")
-				.index(3)
-				.required(true))
-			.arg(clap::Arg::with_name("name")
-				.help("Name given to the ROM when saving. Defaults to the id")
-				.index(4))
 			.get_matches_from_safe(context.args.into_iter())?;
 
-		let datalink = context.app.get_datalink(value_t!(matches, "datalink", usize)?)?;
+		let name = matches.value_of("datalink").unwrap();
+		let new_name = matches.value_of("name").unwrap();
+		context.app.rename_link(name, new_name)?;
+		println!("Renamed datalink \"{}\" to \"{}\"", name, new_name);
+		Ok(())
+	}
+
 
-		let platform = context.app.definitions.find(matches.value_of("platform").unwrap()).ok_or(Error::InvalidPlatform)?;
-		let downloader = link::PlatformLink::new(datalink, platform.clone()).downloader().ok_or(Error::DownloadUnsupported)?;
+
+	pub fn platforms(context: &mut CommandContext) -> Result<()> {
+		let mut table = Table::new(&[("id", "Id"), ("name", "Name")]);
+		table.add_row(vec![json!(obd::PLATFORM_ID), json!(obd::PLATFORM_NAME)]);
+		for definition in context.app.definitions.definitions.iter() {
+			table.add_row(vec![json!(definition.id), json!(definition.name)]);
+		}
+		table.print(context.settings.format)
+	}
+
+
+
+	pub fn download(context: &mut CommandContext) -> Result<()> {
+		let args: Vec<String> = context.args.collect();
+		let matches = clap::App::new("download")
+			.about("Downloads a rom from a platform link")
+			.setting(clap::AppSettings::NoBinaryName)
+			.args(&link_operand_args(&args, vec![
+				clap::Arg::with_name("id")
+					.help("Identifier given to the ROM when saving")
+					.required(true),
+				clap::Arg::with_name("name")
+					.help("Name given to the ROM when saving. Defaults to the id"),
+			]))
+			.get_matches_from_safe(args)?;
+
+		let link = context.app.create_platform_link(&datalink_name(context, &matches)?, &platform_id(context, &matches)?)?;
 
 		let id = matches.value_of("id").unwrap();
 		let name = matches.value_of("name").unwrap_or(id);
 
 		// Begin downloading
-		let response = downloader.download(&DownloadCallback::with(|progress| {
+		let info = context.app.download(&link, id, name, &DownloadCallback::with(|progress| {
 			println!("Progress: {:.2}%", progress * 100.0);
 		}))?;
 
-		let model = platform.identify(&response.data).ok_or(Error::UnknownModel)?;
-		let rom = context.app.roms.new_rom(name.to_owned(), id.to_owned(), platform.clone(), model.clone(), response.data);
-        context.app.roms.save_meta().unwrap();
-        rom.save().unwrap();
+		println!("Saved ROM as \"{}\"", id);
+		match info {
+			Some(info) => {
+				for (field, value) in info.fields() {
+					println!("{}: {}", field, value);
+				}
+			},
+			None => println!("The ECU could not be identified. No identification was saved with the ROM"),
+		}
+		Ok(())
+	}
+
 
-        println!("Saved ROM as \"{}\"", id);
 
-		Ok(())
+	pub fn info(context: &mut CommandContext) -> Result<()> {
+		let matches = clap::App::new("info")
+			.about("Reads the VIN, calibration IDs, CVNs and ECU part numbers")
+			.setting(clap::AppSettings::NoBinaryName)
+			.args(&link_args())
+			.arg(clap::Arg::with_name("rom")
+				.help("Shows the identification saved with a downloaded ROM instead of reading the ECU")
+				.short("r")
+				.long("rom")
+				.takes_value(true))
+			.get_matches_from_safe(context.args.into_iter())?;
+
+		let info = match matches.value_of("rom") {
+			Some(id) => match context.app.rom_info(id)? {
+				Some(info) => info,
+				None => {
+					println!("No identification was saved with ROM \"{}\"", id);
+					return Ok(());
+				},
+			},
+			None => EcuInfo::read(&*uds_interface(context, &matches)?)?,
+		};
+
+		let mut table = Table::new(&[("field", "Field"), ("value", "Value")]);
+		for (field, value) in info.fields() {
+			table.add_row(vec![json!(field), json!(value)]);
+		}
+		table.print(context.settings.format)
 	}
 
 
 
 	pub fn pids(context: &mut CommandContext) -> Result<()> {
-		let matches = clap::App::new("download")
-			.about("Downloads a rom from a platform link")
+		let matches = clap::App::new("pids")
+			.about("Lists PIDs for a platform")
 			.setting(clap::AppSettings::NoBinaryName)
 			.arg(clap::Arg::with_name("platform")
-				.help("ID of the platform. Can be found using the 'links' command")
-				.index(1)
-				.required(true))
+				.help("ID of the platform. Can be found using the 'platforms' command. Defaults to the configured platform")
+				.index(1))
+			.arg(clap::Arg::with_name("datalink")
+				.help("Only list the PIDs the vehicle on this datalink supports. Only used by the generic OBD-II platform")
+				.short("l")
+				.long("datalink")
+				.takes_value(true))
 			.get_matches_from_safe(context.args.into_iter())?;
 
-		let platform = context.app.definitions.find(matches.value_of("platform").unwrap()).ok_or(Error::InvalidPlatform)?;
-
-		// Find longest id and name
-		let mut longest_name = "Name".len();
-		let mut longest_id = "Id".len();
-
-		for pid in platform.pids.iter() {
-			if pid.id >= 100 {
-				longest_id = max((pid.id as f64).log10() as usize + 1, longest_id);
+		let platform_id = platform_id(context, &matches)?;
+		let mut table = Table::new(&[("id", "Id"), ("name", "Name"), ("description", "Description"), ("virtual", "Virtual")]);
+
+		if platform_id == obd::PLATFORM_ID {
+			let supported = match matches.value_of("datalink") {
+				Some(datalink) => Some(obd::supported_pids(&*context.app.create_uds(datalink, obd::PLATFORM_ID)?, 0x01)?),
+				None => None,
+			};
+			for pid in obd::pids() {
+				if supported.as_ref().map_or(true, |supported| supported.contains(&pid.pid)) {
+					table.add_row(vec![json!(format!("0x{:02X}", pid.pid)), json!(pid.name), json!(format!("Mode 01 PID 0x{:02X} ({})", pid.pid, pid.unit)), json!(false)]);
+				}
+			}
+		} else {
+			let platform = context.app.definitions.find(&platform_id).ok_or(Error::InvalidPlatform)?;
+			for pid in platform.pids.iter() {
+				table.add_row(vec![json!(pid.id), json!(pid.name), json!(pid.description), json!(false)]);
 			}
-			longest_name = max(pid.name.len(), longest_name);
 		}
 
-		println!("{1:<0$}   {3:<2$}   {4}", longest_id, "Id", longest_name, "Name", "Description");
-        for pid in platform.pids.iter() {
-            println!("{1:<0$}   {3:<2$}   {4}", longest_id, pid.id.to_string(), longest_name, pid.name, pid.description);
-        }
-
-		Ok(())
+		// Math channels have no id and are logged by name
+		for channel in math_channels(context, &platform_id) {
+			table.add_row(vec![json!(null), json!(channel.name), json!(format!("{} ({})", channel.expression, channel.unit)), json!(true)]);
+		}
+		table.print(context.settings.format)
 	}
 
 
 
 	pub fn roms(context: &mut CommandContext) -> Result<()> {
-		let mut longest_id = "Id".len();
-		let mut longest_name = "Name".len();
-		let mut longest_platform = "Platform".len();
-
+		let mut table = Table::new(&[("id", "Id"), ("name", "Name"), ("platform", "Platform"), ("model", "Model")]);
 		for rom in context.app.roms.roms.iter() {
-			longest_id = max(rom.id.len(), longest_id);
-			longest_name = max(rom.name.len(), longest_name);
-			longest_platform = max(rom.platform.name.len(), longest_platform);
+			table.add_row(vec![json!(rom.id), json!(rom.name), json!(rom.platform.name), json!(rom.model.name)]);
 		}
-
-		println!("{1:<0$}   {3:<2$}   {5:<4$}   {6}", longest_id, "Id", longest_name, "Name", longest_platform, "Platform", "Model");
-        for rom in context.app.roms.roms.iter() {
-            println!("{1:<0$}   {3:<2$}   {5:<4$}   {6}", longest_id, rom.id, longest_name, rom.name, longest_platform, rom.platform.name, rom.model.name);
-        }
-
-        Ok(())
+		table.print(context.settings.format)
 	}
 
 
 
 	pub fn tunes(context: &mut CommandContext) -> Result<()> {
-		let mut longest_id = "Id".len();
-		let mut longest_name = "Name".len();
-
+		let mut table = Table::new(&[("id", "Id"), ("name", "Name"), ("rom", "ROM Id")]);
 		for tune in context.app.tunes.tunes.iter() {
-			longest_id = max(tune.id.len(), longest_id);
-			longest_name = max(tune.name.len(), longest_name);
+			table.add_row(vec![json!(tune.id), json!(tune.name), json!(tune.rom_id)]);
 		}
+		table.print(context.settings.format)
+	}
 
-		println!("{1:<0$}   {3:<2$}   {4}", longest_id, "Id", longest_name, "Name", "ROM Id");
-        for tune in context.app.tunes.tunes.iter() {
-            println!("{1:<0$}   {3:<2$}   {4}", longest_id, tune.id, longest_name, tune.name, tune.rom_id);
-        }
 
-        Ok(())
+
+	pub fn format(context: &mut CommandContext) -> Result<()> {
+		let matches = clap::App::new("format")
+			.about("Sets the output format of listing commands")
+			.setting(clap::AppSettings::NoBinaryName)
+			.arg(clap::Arg::with_name("format")
+				.help("Output format. Prints the current format if omitted")
+				.possible_values(&["text", "json", "csv"])
+				.index(1))
+			.get_matches_from_safe(context.args.into_iter())?;
+
+		match matches.value_of("format") {
+			Some(format) => context.settings.format = format.parse()?,
+			None => println!("{}", context.settings.format),
+		}
+		Ok(())
 	}
 
 
@@ -300,27 +750,630 @@ mod commands {
 		let matches = clap::App::new("scan")
 			.about("Scans OBD-II trouble codes")
 			.setting(clap::AppSettings::NoBinaryName)
+			.args(&link_args())
+			.arg(clap::Arg::with_name("detail")
+				.help("Also read pending and permanent codes, status, occurrence counters and freeze frames")
+				.short("d")
+				.long("detail"))
+			.arg(clap::Arg::with_name("report")
+				.help("Write a detailed report to a file. Files ending in .json are written as JSON, others as text")
+				.short("r")
+				.long("report")
+				.takes_value(true))
+			.get_matches_from_safe(context.args.into_iter())?;
+
+		let datalink = datalink_name(context, &matches)?;
+		let platform = platform_id(context, &matches)?;
+		let interface = context.app.create_uds(&datalink, &platform)?;
+
+		let descriptions = dtc_descriptions(context, &platform);
+
+		if !matches.is_present("detail") && !matches.is_present("report") {
+			let scanner = UdsScanner::new(interface);
+			let codes = scanner.scan()?;
+			for code in codes {
+				println!("{}", descriptions.label(&code.to_string()));
+			}
+			return Ok(());
+		}
+
+		// The generic platform has no manufacturer PIDs
+		let definition = context.app.definitions.find(&platform);
+		let pid_name = |id: u16| definition
+			.and_then(|definition| definition.pids.iter().find(|pid| pid.id as u32 == u32::from(id)))
+			.map(|pid| pid.name.clone());
+		let mut codes = diag::read_codes(&*interface, pid_name)?;
+		for code in codes.iter_mut() {
+			code.description = descriptions.describe(&code.code).map(str::to_owned);
+		}
+		let report = diag::Report {
+			time: SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0),
+			datalink,
+			platform,
+			codes,
+		};
+
+		if matches.is_present("detail") {
+			report.write_text(&mut io::stdout())?;
+		}
+		if let Some(path) = matches.value_of("report") {
+			let path = Path::new(path);
+			let mut file = io::BufWriter::new(fs::File::create(path)?);
+			if path.extension().and_then(|ext| ext.to_str()) == Some("json") {
+				serde_json::to_writer_pretty(&mut file, &report).map_err(io::Error::from)?;
+			} else {
+				report.write_text(&mut file)?;
+			}
+			file.flush()?;
+			println!("Wrote report to {}", path.display());
+		}
+		Ok(())
+	}
+
+
+
+	/// Loads the trouble code descriptions of a platform. A malformed
+	/// `dtc.toml` is reported and the generic descriptions are used instead.
+	fn dtc_descriptions(context: &CommandContext, platform: &str) -> dtc::Descriptions {
+		dtc::Descriptions::load(&context.app.config_dir.join("definitions"), &context.app.definitions, platform).unwrap_or_else(|err| {
+			eprintln!("Warning: ignoring trouble code descriptions of {}: {}", platform, err);
+			dtc::Descriptions::default()
+		})
+	}
+
+
+
+	pub fn readiness(context: &mut CommandContext) -> Result<()> {
+		let matches = clap::App::new("readiness")
+			.about("Shows emissions readiness monitors and the MIL state")
+			.setting(clap::AppSettings::NoBinaryName)
+			.args(&link_args())
+			.get_matches_from_safe(context.args.into_iter())?;
+
+		let interface = uds_interface(context, &matches)?;
+
+		// Status since codes were cleared
+		let response = uds::request(&*interface, 0x01, &[0x01])?;
+		let status = response.get(1..).and_then(obd::MonitorStatus::decode).ok_or(Error::InvalidResponse)?;
+		// Status this drive cycle. Not all ECUs support it.
+		let cycle = match uds::request(&*interface, 0x01, &[0x41]) {
+			Ok(response) => response.get(1..).and_then(|data| obd::MonitorStatus::decode_cycle(data, &status)),
+			Err(Error::NegativeResponse { .. }) => None,
+			Err(err) => return Err(err),
+		};
+
+		let readiness = |complete: bool| if complete { "complete" } else { "incomplete" };
+		let mut table = Table::new(&[("monitor", "Monitor"), ("since_clear", "Since codes cleared"), ("this_cycle", "This drive cycle")]);
+		let mil = if status.mil { format!("on ({} code(s))", status.dtc_count) } else { "off".to_owned() };
+		table.add_row(vec![json!("MIL"), json!(mil), json!("")]);
+		for monitor in status.monitors.iter() {
+			let this_cycle = cycle.as_ref()
+				.and_then(|cycle| cycle.monitors.iter().find(|other| other.name == monitor.name))
+				.map(|other| readiness(other.complete))
+				.unwrap_or("");
+			table.add_row(vec![json!(monitor.name), json!(readiness(monitor.complete)), json!(this_cycle)]);
+		}
+		table.print(context.settings.format)
+	}
+
+
+
+	pub fn clear_codes(context: &mut CommandContext) -> Result<()> {
+		let matches = clap::App::new("clear_codes")
+			.about("Clears trouble codes and freeze frames, then scans again")
+			.setting(clap::AppSettings::NoBinaryName)
+			.args(&link_args())
+			.arg(clap::Arg::with_name("yes")
+				.help("Do not ask for confirmation")
+				.short("y")
+				.long("yes"))
+			.arg(clap::Arg::with_name("obd")
+				.help("Only use OBD-II mode 04 instead of trying UDS ClearDiagnosticInformation first")
+				.long("obd"))
+			.get_matches_from_safe(context.args.into_iter())?;
+
+		let platform = platform_id(context, &matches)?;
+		let interface = context.app.create_uds(&datalink_name(context, &matches)?, &platform)?;
+		let descriptions = dtc_descriptions(context, &platform);
+
+		let codes = UdsScanner::new(interface.clone()).scan()?;
+		if codes.is_empty() {
+			println!("No trouble codes are stored");
+		} else {
+			println!("Stored codes:");
+			for code in codes.iter() {
+				println!("{}", descriptions.label(&code.to_string()));
+			}
+		}
+
+		if !matches.is_present("yes") && !confirm("Clear all trouble codes? This also resets emissions readiness monitors")? {
+			println!("Cancelled");
+			return Ok(());
+		}
+
+		if matches.is_present("obd") {
+			uds::request(&*interface, 0x04, &[])?;
+		} else {
+			// Group of all DTCs. Fall back to mode 04 for ECUs without the UDS service.
+			match uds::request(&*interface, 0x14, &[0xFF, 0xFF, 0xFF]) {
+				Err(Error::NegativeResponse { nrc: 0x11, .. }) | Err(Error::NegativeResponse { nrc: 0x7F, .. }) => {
+					uds::request(&*interface, 0x04, &[])?;
+				},
+				result => { result?; },
+			}
+		}
+
+		// Scan again to find codes that are still active
+		let remaining = UdsScanner::new(interface).scan()?;
+		if remaining.is_empty() {
+			println!("All trouble codes cleared");
+			return Ok(());
+		}
+
+		println!("Codes that did not clear. The fault may still be present:");
+		for code in remaining.iter() {
+			println!("{}", descriptions.label(&code.to_string()));
+		}
+		Err(Error::CodesNotCleared(remaining.len()))
+	}
+
+
+
+	pub fn sniff(context: &mut CommandContext) -> Result<()> {
+		let matches = clap::App::new("sniff")
+			.about("Prints CAN frames received on a datalink until Ctrl-C is pressed")
+			.setting(clap::AppSettings::NoBinaryName)
 			.arg(clap::Arg::with_name("datalink")
-				.help("ID of the datalink to use. Can be found using the 'links' command")
+				.help("Name of the datalink to use. Can be found using the 'links' command. Defaults to the configured datalink")
+				.index(1))
+			.arg(clap::Arg::with_name("filter")
+				.help("Only show frames matching id:mask, or not matching id~mask (hex), like candump. May be given multiple times")
+				.short("f")
+				.long("filter")
+				.takes_value(true)
+				.multiple(true)
+				.number_of_values(1))
+			.arg(clap::Arg::with_name("output")
+				.help("Also record frames to a file. Files ending in .pcap are written as pcap, others as candump logs")
+				.short("o")
+				.long("output")
+				.takes_value(true))
+			.arg(clap::Arg::with_name("quiet")
+				.help("Do not print frames. Useful when recording")
+				.short("q")
+				.long("quiet"))
+			.get_matches_from_safe(context.args.into_iter())?;
+
+		let name = datalink_name(context, &matches)?;
+		let datalink = context.app.get_datalink(&name)?;
+		let can = datalink.can().ok_or(Error::CanUnsupported)?;
+
+		let mut filters = Vec::new();
+		if let Some(values) = matches.values_of("filter") {
+			for value in values {
+				filters.push(IdFilter::parse(value).ok_or_else(|| Error::InvalidFilter(value.to_owned()))?);
+			}
+		}
+
+		let mut writer = match matches.value_of("output") {
+			Some(path) => {
+				let path = Path::new(path);
+				Some(CaptureWriter::create(path, CaptureFormat::from_path(path), &name)?)
+			},
+			None => None,
+		};
+		let quiet = matches.is_present("quiet");
+
+		println!("Listening on \"{}\". Press Ctrl-C to stop", name);
+		let guard = interrupt::Guard::new();
+		let start = Instant::now();
+		let mut count = 0usize;
+
+		while !guard.interrupted() {
+			let frame = match can.recv(Duration::from_millis(100)) {
+				Ok(frame) => frame,
+				Err(TuneError::Timeout) => continue,
+				Err(err) => return Err(err.into()),
+			};
+			if !filters.is_empty() && !filters.iter().any(|filter| filter.matches(frame.id)) {
+				continue;
+			}
+
+			count += 1;
+			if let Some(ref mut writer) = writer {
+				writer.write(SystemTime::now(), &frame)?;
+			}
+			if !quiet {
+				let elapsed = start.elapsed();
+				println!("{:>5}.{:06}  {:>8X}   [{}]  {}", elapsed.as_secs(), elapsed.subsec_micros(), frame.id, frame.len, hex(&frame.data[..frame.len as usize]));
+			}
+		}
+
+		if let Some(ref mut writer) = writer {
+			writer.flush()?;
+		}
+		println!("Received {} frame(s)", count);
+		Ok(())
+	}
+
+
+
+	pub fn log(context: &mut CommandContext) -> Result<()> {
+		let args: Vec<String> = context.args.collect();
+		let matches = clap::App::new("log")
+			.about("Prints live PID values until Ctrl-C is pressed")
+			.setting(clap::AppSettings::NoBinaryName)
+			.args(&link_operand_args(&args, vec![
+				clap::Arg::with_name("pids")
+					.help("IDs or names of the PIDs or math channels to log, separated by spaces or commas. Can be found using the 'pids' command. Generic OBD-II PIDs are in hex, e.g. 0x0C")
+					.multiple(true)
+					.use_delimiter(true)
+					.required(true),
+			]))
+			.arg(clap::Arg::with_name("interval")
+				.help("Minimum time between samples in milliseconds. Defaults to 100")
+				.short("i")
+				.long("interval")
+				.takes_value(true))
+			.arg(clap::Arg::with_name("record")
+				.help("Also record samples to <name>.csv and <name>.mf4 in the logs directory")
+				.short("r")
+				.long("record")
+				.value_name("name")
+				.takes_value(true))
+			.arg(clap::Arg::with_name("dashboard")
+				.help("Shows gauges, sparklines and statistics instead of printing samples. Gauges are configured in the [gauges] table of cli.toml")
+				.short("d")
+				.long("dashboard"))
+			.get_matches_from_safe(args)?;
+
+		let datalink = datalink_name(context, &matches)?;
+		let platform = platform_id(context, &matches)?;
+		let selectors: Vec<&str> = matches.values_of("pids").unwrap().collect();
+		let interval = Duration::from_millis(match matches.value_of("interval") {
+			Some(_) => value_t!(matches, "interval", u64)?,
+			None => 100,
+		});
+
+		let channels = logger::select(&context.app.definitions, &platform, &selectors, math_channels(context, &platform))?;
+		let mut recorder = match matches.value_of("record") {
+			Some(name) => Some(Recorder::create(&context.app.logs_dir(), name, &channels)?),
+			None => None,
+		};
+		let link = if platform == obd::PLATFORM_ID {
+			logger::Link::Obd(context.app.create_uds(&datalink, &platform)?)
+		} else {
+			logger::Link::Platform(context.app.create_platform_link(&datalink, &platform)?)
+		};
+		let sampler = logger::Sampler::new(link, channels);
+
+		let mut dashboard = if matches.is_present("dashboard") {
+			Some(Dashboard::new(&sampler.channels, &context.settings.gauges)?)
+		} else {
+			println!("Logging {} PID(s) on \"{}\". Press Ctrl-C to stop", sampler.channels.len(), datalink);
+			None
+		};
+		let guard = interrupt::Guard::new();
+		let mut count = 0usize;
+
+		let result = sampler.run(interval, |sample| {
+			count += 1;
+			if let Some(ref mut recorder) = recorder {
+				recorder.write(&sample)?;
+			}
+
+			if let Some(ref mut dashboard) = dashboard {
+				dashboard.update(&sample);
+				dashboard.draw(&format!("{} on \"{}\"  {:.1}s  {} sample(s)  Ctrl-C to stop", platform, datalink, sample.time, count))?;
+			} else {
+				let values: Vec<String> = sampler.channels.iter().zip(sample.values.iter())
+					.map(|(channel, value)| match *value {
+						Some(value) => format!("{}: {:.2} {}", channel.name, value, channel.unit),
+						None => format!("{}: -", channel.name),
+					})
+					.collect();
+				println!("{:>9.3}s  {}", sample.time, values.join("  |  "));
+			}
+			Ok(!guard.interrupted())
+		});
+
+		// Restore the terminal before printing the summary
+		drop(dashboard);
+		if let Some(recorder) = recorder {
+			// Complete the files even if logging failed, so the samples
+			// recorded so far can be opened. The logging error comes first.
+			let finished = recorder.finish();
+			result?;
+			finished?;
+			println!("Recorded {} sample(s) to \"{}\"", count, matches.value_of("record").unwrap());
+		} else {
+			result?;
+			println!("Logged {} sample(s)", count);
+		}
+		Ok(())
+	}
+
+
+
+	pub fn logs(context: &mut CommandContext) -> Result<()> {
+		let mut table = Table::new(&[("name", "Name"), ("formats", "Formats"), ("size", "Size"), ("modified", "Modified")]);
+		for log in logfile::list(&context.app.logs_dir())? {
+			let modified = log.modified
+				.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+				.map(|time| time.as_secs());
+			table.add_row(vec![json!(log.name), json!(log.formats.join(", ")), json!(log.size), json!(modified)]);
+		}
+		table.print(context.settings.format)
+	}
+
+
+
+	pub fn log_open(context: &mut CommandContext) -> Result<()> {
+		let matches = clap::App::new("log_open")
+			.about("Opens a recorded log for 'log_stats' and 'log_plot'")
+			.setting(clap::AppSettings::NoBinaryName)
+			.arg(clap::Arg::with_name("log")
+				.help("Name of the log, as listed by 'logs', or path to a CSV or MDF (.mf4) log. The CSV file of a log is used if it has both")
 				.index(1)
 				.required(true))
 			.arg(clap::Arg::with_name("platform")
-				.help("ID of the platform to download from. Can be found using the 'platforms' command")
-				.index(2)
-				.required(true))
+				.help("Platform whose math channels are computed from the log. Defaults to the configured platform")
+				.short("p")
+				.long("platform")
+				.takes_value(true))
 			.get_matches_from_safe(context.args.into_iter())?;
 
-		let datalink = context.app.get_datalink(value_t!(matches, "datalink", usize)?)?;
+		let mut log = Datalog::load(&logfile::log_file(&context.app.logs_dir(), matches.value_of("log").unwrap()))?;
+		// Math channels are optional when opening a log
+		if let Ok(platform) = platform_id(context, &matches) {
+			log.add_math(math_channels(context, &platform))?;
+		}
+		println!("Opened \"{}\": {} sample(s) over {:.1}s", log.name, log.samples.len(), log.duration());
+
+		let mut table = Table::new(&[("name", "Name"), ("unit", "Unit"), ("samples", "Samples"), ("virtual", "Virtual")]);
+		for (index, channel) in log.channels.iter().enumerate() {
+			let samples = log.samples.iter().filter(|sample| sample.values[index].is_some()).count();
+			table.add_row(vec![json!(channel.name), json!(channel.unit), json!(samples), json!(channel.computed)]);
+		}
+		context.settings.log = Some(log);
+		table.print(context.settings.format)
+	}
 
-		let platform = context.app.definitions.find(matches.value_of("platform").unwrap()).ok_or(Error::InvalidPlatform)?;
-		let interface = link::PlatformLink::new(datalink, platform.clone()).uds().ok_or(Error::InvalidDatalink)?;
 
-		let scanner = UdsScanner::new(interface);
-		let codes = scanner.scan()?;
-		for code in codes {
-			println!("{}", code);
+
+	/// Arguments selecting PIDs and samples of the open log
+	fn log_filter_args<'a, 'b>() -> Vec<clap::Arg<'a, 'b>> {
+		vec![
+			clap::Arg::with_name("pids")
+				.help("Names of the PIDs to include. Defaults to all PIDs of the log")
+				.index(1)
+				.multiple(true),
+			clap::Arg::with_name("from")
+				.help("Skips samples before this time in seconds")
+				.long("from")
+				.takes_value(true),
+			clap::Arg::with_name("to")
+				.help("Skips samples after this time in seconds")
+				.long("to")
+				.takes_value(true),
+			clap::Arg::with_name("where")
+				.help("Only includes samples for which the expression is true, e.g. \"[Engine speed] > 4000 && [Throttle position] > 90\"")
+				.short("w")
+				.long("where")
+				.takes_value(true),
+		]
+	}
+
+	/// Returns the channel indices and samples of `log` selected by the
+	/// arguments from `log_filter_args`
+	fn log_selection<'a>(log: &'a Datalog, matches: &clap::ArgMatches) -> Result<(Vec<usize>, Vec<&'a logger::Sample>)> {
+		let channels = match matches.values_of("pids") {
+			Some(names) => names.map(|name| log.channel(name)).collect::<Result<Vec<usize>>>()?,
+			None => (0..log.channels.len()).collect(),
+		};
+		let from = match matches.value_of("from") {
+			Some(_) => Some(value_t!(matches, "from", f64)?),
+			None => None,
+		};
+		let to = match matches.value_of("to") {
+			Some(_) => Some(value_t!(matches, "to", f64)?),
+			None => None,
+		};
+		let condition = match matches.value_of("where") {
+			Some(condition) => Some(Expr::parse(condition)?),
+			None => None,
+		};
+		let samples = log.select(from, to, condition.as_ref())?;
+		Ok((channels, samples))
+	}
+
+
+
+	pub fn log_stats(context: &mut CommandContext) -> Result<()> {
+		let matches = clap::App::new("log_stats")
+			.about("Prints statistics of the PIDs of the open log")
+			.setting(clap::AppSettings::NoBinaryName)
+			.args(&log_filter_args())
+			.get_matches_from_safe(context.args.into_iter())?;
+
+		let log = context.settings.log.as_ref().ok_or(Error::NoLog)?;
+		let (channels, samples) = log_selection(log, &matches)?;
+
+		let mut table = Table::new(&[("name", "Name"), ("unit", "Unit"), ("count", "Count"), ("min", "Min"), ("max", "Max"), ("mean", "Mean"),
+			("p5", "P5"), ("p25", "P25"), ("p50", "P50"), ("p75", "P75"), ("p95", "P95")]);
+		for index in channels {
+			let channel = &log.channels[index];
+			let values: Vec<f64> = samples.iter().filter_map(|sample| sample.values[index]).collect();
+			let mut row = vec![json!(channel.name), json!(channel.unit)];
+			match Statistics::compute(&values) {
+				Some(stats) => {
+					row.extend(vec![json!(stats.count), json!(round(stats.min)), json!(round(stats.max)), json!(round(stats.mean))]);
+					row.extend(stats.percentiles.iter().map(|value| json!(round(*value))));
+				},
+				None => {
+					row.push(json!(0));
+					row.extend((0..3 + PERCENTILES.len()).map(|_| json!(null)));
+				},
+			}
+			table.add_row(row);
+		}
+		table.print(context.settings.format)
+	}
+
+	/// Rounds a statistic to the precision shown by 'log_stats'
+	fn round(value: f64) -> f64 {
+		(value * 1000.0).round() / 1000.0
+	}
+
+
+
+	pub fn log_plot(context: &mut CommandContext) -> Result<()> {
+		let matches = clap::App::new("log_plot")
+			.about("Plots PIDs of the open log in the terminal or to an SVG or PNG image")
+			.setting(clap::AppSettings::NoBinaryName)
+			.args(&log_filter_args())
+			.arg(clap::Arg::with_name("output")
+				.help("Writes the chart to an image instead of printing it. The format is taken from the extension, .svg or .png")
+				.short("o")
+				.long("output")
+				.value_name("file")
+				.takes_value(true))
+			.arg(clap::Arg::with_name("width")
+				.help("Width in characters, or in pixels for images. Defaults to 72 or 1000")
+				.long("width")
+				.takes_value(true))
+			.arg(clap::Arg::with_name("height")
+				.help("Height of each chart in lines, or of the image in pixels. Defaults to 12 or 250 per PID")
+				.long("height")
+				.takes_value(true))
+			.get_matches_from_safe(context.args.into_iter())?;
+
+		let log = context.settings.log.as_ref().ok_or(Error::NoLog)?;
+		let (channels, samples) = log_selection(log, &matches)?;
+		let series: Vec<Series> = channels.iter().map(|&index| Series {
+			name: log.channels[index].name.clone(),
+			unit: log.channels[index].unit.clone(),
+			points: samples.iter().filter_map(|sample| sample.values[index].map(|value| (sample.time, value))).collect(),
+		}).collect();
+
+		match matches.value_of("output") {
+			Some(path) => {
+				let extension = Path::new(path).extension().and_then(|extension| extension.to_str()).map(|extension| extension.to_ascii_lowercase());
+				let draw: fn(&mut Write, &[Series], u32, u32) -> io::Result<()> = match extension.as_ref().map(String::as_str) {
+					Some("svg") => plot::svg,
+					Some("png") => plot::png,
+					_ => return Err(Error::UnsupportedImage(path.to_owned())),
+				};
+				let width = match matches.value_of("width") {
+					Some(_) => value_t!(matches, "width", u32)?,
+					None => 1000,
+				};
+				let height = match matches.value_of("height") {
+					Some(_) => value_t!(matches, "height", u32)?,
+					None => 250 * series.len().max(1) as u32,
+				};
+				let mut out = io::BufWriter::new(fs::File::create(path)?);
+				draw(&mut out, &series, width, height)?;
+				out.flush()?;
+				println!("Saved plot of {} sample(s) to \"{}\"", samples.len(), path);
+			},
+			None => {
+				let width = match matches.value_of("width") {
+					Some(_) => value_t!(matches, "width", usize)?,
+					None => 72,
+				};
+				let height = match matches.value_of("height") {
+					Some(_) => value_t!(matches, "height", usize)?,
+					None => 12,
+				};
+				let stdout = io::stdout();
+				plot::text(&mut stdout.lock(), &series, width, height)?;
+			},
+		}
+		Ok(())
+	}
+
+
+
+	/// Parses a byte written in hex, with or without a 0x prefix
+	fn parse_byte(s: &str) -> Option<u8> {
+		let s = s.trim_start_matches("0x").trim_start_matches("0X");
+		u8::from_str_radix(s, 16).ok()
+	}
+
+	/// Parses hex bytes from words such as "F1 90" or "F190"
+	fn parse_hex<'a, I>(words: I) -> Option<Vec<u8>>
+	where I: Iterator<Item=&'a str> {
+		let digits: String = words.map(|word| word.trim_start_matches("0x").trim_start_matches("0X")).collect();
+		if digits.len() % 2 != 0 {
+			return None;
 		}
+		(0..digits.len()).step_by(2).map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok()).collect()
+	}
+
+	/// Formats bytes as space separated hex
+	fn hex(data: &[u8]) -> String {
+		data.iter().map(|b| format!("{:02X}", b)).collect::<Vec<String>>().join(" ")
+	}
+
+	pub fn uds(context: &mut CommandContext) -> Result<()> {
+		let args: Vec<String> = context.args.collect();
+		let matches = clap::App::new("uds")
+			.about("Sends a UDS request and prints the response")
+			.setting(clap::AppSettings::NoBinaryName)
+			.args(&link_operand_args(&args, vec![
+				clap::Arg::with_name("sid")
+					.help("Service ID in hex, e.g. 22 for ReadDataByIdentifier")
+					.required(true),
+				clap::Arg::with_name("data")
+					.help("Request data in hex, e.g. F190 or F1 90")
+					.multiple(true),
+			]))
+			.get_matches_from_safe(args)?;
+
+		let sid_arg = matches.value_of("sid").unwrap();
+		let sid = parse_byte(sid_arg).ok_or_else(|| Error::InvalidHex(sid_arg.to_owned()))?;
+		let data = match matches.values_of("data") {
+			Some(words) => {
+				let words: Vec<&str> = words.collect();
+				parse_hex(words.iter().cloned()).ok_or_else(|| Error::InvalidHex(words.join(" ")))?
+			},
+			None => Vec::new(),
+		};
+
+		let interface = uds_interface(context, &matches)?;
+
+		println!("Request:  {:02X} {}", sid, hex(&data));
+		let response = uds::request(&*interface, sid, &data)?;
+		println!("Response: {:02X} {}", sid | 0x40, hex(&response));
+
+		// Show printable responses such as a VIN or part number as text
+		if !response.is_empty() && response.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+			println!("ASCII:    {}", String::from_utf8_lossy(&response));
+		}
+		Ok(())
+	}
+
+
+
+	pub fn source(context: &mut CommandContext) -> Result<()> {
+		let matches = clap::App::new("source")
+			.about("Runs each line of a file as a command")
+			.setting(clap::AppSettings::NoBinaryName)
+			.arg(clap::Arg::with_name("file")
+				.help("Path of the script to run")
+				.index(1)
+				.required(true))
+			.arg(clap::Arg::with_name("keep-going")
+				.help("Continue running the script after a command fails")
+				.short("k")
+				.long("keep-going"))
+			.get_matches_from_safe(context.args.into_iter())?;
 
+		// Run by process_command after this callback returns, so that the
+		// script can call 'source' again
+		let path = matches.value_of("file").unwrap();
+		context.settings.pending_script = Some((PathBuf::from(path), matches.is_present("keep-going")));
 		Ok(())
 	}
 }
@@ -332,6 +1385,7 @@ impl<'a> Cli<'a> {
 		Cli {
 			app,
 			commands: Vec::new(),
+			settings: Settings::default(),
 		}
 	}
 
@@ -348,24 +1402,39 @@ impl<'a> Cli<'a> {
 
 		// Lists available datalinks
 		self.commands.push(Command::new("links".to_owned(), "Lists available datalinks".to_owned(), 
-			|context| {
-				commands::links(&context.app.avail_links);
-				Ok(())
+			|mut context| {
+				commands::links(&mut context)
 			},
-		));
+		).with_options(vec![("-n", ArgKind::Other), ("--name", ArgKind::Other)]));
 
 		// Adds a datalink
 		self.commands.push(Command::new("add_link".to_owned(), "Adds a datalink".to_owned(), 
 			|mut context| {
 				commands::add_link(&mut context)
 			},
-		));
+		).with_args(vec![ArgKind::Values(LINK_TYPES)]).with_options(vec![
+			("-n", ArgKind::Other), ("--name", ArgKind::Other), ("-b", ArgKind::Other), ("--bitrate", ArgKind::Other), ("--baud", ArgKind::Other),
+			("-p", ArgKind::Values(&["udp", "tcp"])), ("--protocol", ArgKind::Values(&["udp", "tcp"])), ("-l", ArgKind::Other), ("--local", ArgKind::Other),
+		]));
+
+		// Removes a datalink
+		self.commands.push(Command::new("remove_link".to_owned(), "Removes a saved datalink or alias".to_owned(),
+			|mut context| {
+				commands::remove_link(&mut context)
+			},
+		).with_args(vec![ArgKind::Datalink]));
+
+		// Renames a datalink
+		self.commands.push(Command::new("rename_link".to_owned(), "Renames a datalink".to_owned(),
+			|mut context| {
+				commands::rename_link(&mut context)
+			},
+		).with_args(vec![ArgKind::Datalink, ArgKind::Other]));
 
 		// Lists installed platform definitions
 		self.commands.push(Command::new("platforms".to_owned(), "Lists all installed platform definitions".to_owned(), 
-			|context| {
-				commands::platforms(&context.app.definitions);
-				Ok(())
+			|mut context| {
+				commands::platforms(&mut context)
 			}
 		));
 
@@ -374,14 +1443,14 @@ impl<'a> Cli<'a> {
 			|mut context| {
 				commands::download(&mut context)
 			}
-		));
+		).with_args(vec![ArgKind::Datalink, ArgKind::Platform, ArgKind::Other, ArgKind::Other]).with_options(LINK_OPTIONS.to_vec()));
 
 		// Lists PIDs for a platform
 		self.commands.push(Command::new("pids".to_owned(), "Lists PIDs for a platform".to_owned(),
 			|mut context| {
 				commands::pids(&mut context)
 			}
-		));
+		).with_args(vec![ArgKind::Platform]).with_options(vec![("-l", ArgKind::Datalink), ("--datalink", ArgKind::Datalink)]));
 
 		// Lists ROMs
 		self.commands.push(Command::new("roms".to_owned(), "Lists downloaded ROMs".to_owned(),
@@ -400,12 +1469,84 @@ impl<'a> Cli<'a> {
 			|mut context| {
 				commands::create_tune(&mut context)
 			}
-		));
+		).with_args(vec![ArgKind::Rom, ArgKind::Other, ArgKind::Other]));
 
 		self.commands.push(Command::new("scan".to_owned(), "Scans OBD-II trouble codes".to_owned(),
 			|mut context| {
 				commands::scan(&mut context)
 			}
+		).with_args(vec![ArgKind::Datalink, ArgKind::Platform]).with_options(vec![("-r", ArgKind::Other), ("--report", ArgKind::Other)]));
+
+		self.commands.push(Command::new("sniff".to_owned(), "Prints and records raw CAN traffic".to_owned(),
+			|mut context| {
+				commands::sniff(&mut context)
+			}
+		).with_args(vec![ArgKind::Datalink]).with_options(vec![("-f", ArgKind::Other), ("--filter", ArgKind::Other), ("-o", ArgKind::Other), ("--output", ArgKind::Other)]));
+
+		self.commands.push(Command::new("uds".to_owned(), "Sends a raw UDS request".to_owned(),
+			|mut context| {
+				commands::uds(&mut context)
+			}
+		).with_args(vec![ArgKind::Datalink, ArgKind::Platform, ArgKind::Other]).with_options(LINK_OPTIONS.to_vec()));
+
+		self.commands.push(Command::new("format".to_owned(), "Sets the output format of listings (text, json or csv)".to_owned(),
+			|mut context| {
+				commands::format(&mut context)
+			}
+		).with_args(vec![ArgKind::Values(&["text", "json", "csv"])]));
+
+		self.commands.push(Command::new("clear_codes".to_owned(), "Clears OBD-II trouble codes".to_owned(),
+			|mut context| {
+				commands::clear_codes(&mut context)
+			}
+		).with_args(vec![ArgKind::Datalink, ArgKind::Platform]));
+
+		self.commands.push(Command::new("log".to_owned(), "Prints live PID values".to_owned(),
+			|mut context| {
+				commands::log(&mut context)
+			}
+		).with_args(vec![ArgKind::Datalink, ArgKind::Platform, ArgKind::Pid]).with_options([LINK_OPTIONS, &[("-i", ArgKind::Other), ("--interval", ArgKind::Other), ("-r", ArgKind::Other), ("--record", ArgKind::Other)]].concat()));
+
+		self.commands.push(Command::new("logs".to_owned(), "Lists recorded logs".to_owned(),
+			|mut context| {
+				commands::logs(&mut context)
+			}
+		));
+
+		self.commands.push(Command::new("log_open".to_owned(), "Opens a recorded log".to_owned(),
+			|mut context| {
+				commands::log_open(&mut context)
+			}
+		).with_args(vec![ArgKind::Log]).with_options(vec![("-p", ArgKind::Platform), ("--platform", ArgKind::Platform)]));
+
+		self.commands.push(Command::new("log_stats".to_owned(), "Prints statistics of the open log".to_owned(),
+			|mut context| {
+				commands::log_stats(&mut context)
+			}
+		).with_options(LOG_FILTER_OPTIONS.to_vec()));
+
+		self.commands.push(Command::new("log_plot".to_owned(), "Plots PIDs of the open log".to_owned(),
+			|mut context| {
+				commands::log_plot(&mut context)
+			}
+		).with_options([LOG_FILTER_OPTIONS, &[("-o", ArgKind::Other), ("--output", ArgKind::Other), ("--width", ArgKind::Other), ("--height", ArgKind::Other)]].concat()));
+
+		self.commands.push(Command::new("readiness".to_owned(), "Shows emissions readiness monitors".to_owned(),
+			|mut context| {
+				commands::readiness(&mut context)
+			}
+		).with_args(vec![ArgKind::Datalink, ArgKind::Platform]));
+
+		self.commands.push(Command::new("info".to_owned(), "Reads vehicle and ECU identification".to_owned(),
+			|mut context| {
+				commands::info(&mut context)
+			}
+		).with_args(vec![ArgKind::Datalink, ArgKind::Platform]).with_options(vec![("-r", ArgKind::Rom), ("--rom", ArgKind::Rom)]));
+
+		self.commands.push(Command::new("source".to_owned(), "Runs commands from a script file".to_owned(),
+			|mut context| {
+				commands::source(&mut context)
+			}
 		));
 	}
 
@@ -413,29 +1554,148 @@ impl<'a> Cli<'a> {
 		self.commands.push(command);
 	}
 
-	/// Processes an iterator as a command.
+	/// Processes an iterator as a command. Errors returned by the command
+	/// are passed to the caller; use `print_error` to display them.
 	pub fn process<I>(&mut self, itr: I) -> Result<()>
 	where I: Iterator<Item=String>,
 	{
-		let mut it = itr.into_iter();
-		let cmd = it.next().ok_or(Error::InvalidCommand)?;
+		process_command(self.app, &self.commands, &mut self.settings, itr)
+	}
 
-		let command = self.commands.iter().find(|ref x| x.command == cmd).ok_or(Error::InvalidCommand)?;
+	/// Returns a snapshot of the command names and ids used for tab completion
+	pub fn completions(&self) -> Completions {
+		Completions::new(self.app, &self.commands, &self.settings)
+	}
+
+	/// Splits a line into words and runs it as a command. Blank lines and
+	/// comments are ignored.
+	pub fn process_line(&mut self, line: &str) -> Result<()> {
+		process_line(self.app, &self.commands, &mut self.settings, line)
+	}
 
-		// Command exists
+	/// Runs each line of a script file as a command. See `run_script`.
+	pub fn run_script(&mut self, path: &Path, keep_going: bool) -> Result<()> {
+		run_script(self.app, &self.commands, &mut self.settings, path, keep_going)
+	}
+}
+
+
+
+/// Finds and runs the command named by the first item of `itr`.
+fn process_command<I>(app: &mut App, commands: &Vec<Command>, settings: &mut Settings, itr: I) -> Result<()>
+where I: Iterator<Item=String>,
+{
+	let mut it = itr.into_iter();
+	let cmd = it.next().ok_or(Error::InvalidCommand)?;
+
+	let command = commands.iter().find(|ref x| x.command == cmd).ok_or(Error::InvalidCommand)?;
+
+	let result = {
 		let closure = &mut *command.callback.borrow_mut();
-		let result = (closure)(CommandContext {
-			app: self.app,
-			commands: &self.commands,
+		closure(CommandContext {
+			app,
+			commands,
+			settings,
 			args: &mut it,
-		});
-		if let Err(err) = result {
-			match err {
-				Error::Clap(err) => println!("{}", err),
-				_ => println!("Error: {}", err),
+		})
+	};
+	let result = match (result, settings.pending_script.take()) {
+		(Ok(()), Some((path, keep_going))) => run_script(app, commands, settings, &path, keep_going),
+		(result, _) => result,
+	};
+
+	match result {
+		// Clap reports --help and --version as errors. They are not failures.
+		Err(Error::Clap(ref err)) if err.kind == clap::ErrorKind::HelpDisplayed || err.kind == clap::ErrorKind::VersionDisplayed => {
+			println!("{}", err);
+			Ok(())
+		},
+		result => result,
+	}
+}
+
+
+
+/// Splits a line into words and runs it as a command. Blank lines and lines
+/// beginning with `#` are ignored.
+fn process_line(app: &mut App, commands: &Vec<Command>, settings: &mut Settings, line: &str) -> Result<()> {
+	let line = line.trim();
+	if line.is_empty() || line.starts_with('#') {
+		return Ok(());
+	}
+
+	let mut lexer = Shlex::new(line);
+	let parts: Vec<String> = lexer.by_ref().collect();
+	if lexer.had_error {
+		return Err(Error::InvalidSyntax);
+	}
+	process_command(app, commands, settings, parts.into_iter())
+}
+
+
+
+/// Runs each line of a script file as a command. Blank lines and lines
+/// beginning with `#` are skipped.
+///
+/// # Arguments
+/// `path` - Path of the script
+/// `keep_going` - If true, failed commands are reported and the script
+/// continues. Otherwise the script stops at the first failure.
+fn run_script(app: &mut App, commands: &Vec<Command>, settings: &mut Settings, path: &Path, keep_going: bool) -> Result<()> {
+	let script = fs::read_to_string(path)?;
+	// Scripts may source other scripts, but not one that is already running
+	let canonical = fs::canonicalize(path)?;
+	if settings.scripts.contains(&canonical) {
+		return Err(Error::RecursiveScript(path.to_path_buf()));
+	}
+
+	settings.scripts.push(canonical);
+	let result = run_lines(app, commands, settings, path, &script, keep_going);
+	settings.scripts.pop();
+	result
+}
+
+/// Runs the lines of a script. See `run_script`.
+fn run_lines(app: &mut App, commands: &Vec<Command>, settings: &mut Settings, path: &Path, script: &str, keep_going: bool) -> Result<()> {
+	let mut failed = 0;
+
+	for (number, line) in script.lines().enumerate() {
+		let number = number + 1;
+		if let Err(err) = process_line(app, commands, settings, line) {
+			if !keep_going {
+				return Err(Error::Script { path: path.to_path_buf(), line: number, err: Box::new(err) });
 			}
+			eprint!("{}:{}: ", path.display(), number);
+			print_error(&err);
+			failed += 1;
 		}
+	}
 
-		Ok(())
+	if failed > 0 {
+		return Err(Error::ScriptFailed(failed));
+	}
+	Ok(())
+}
+
+
+
+/// Prints an error returned from `Cli::process` to stderr
+pub fn print_error(err: &Error) {
+	match *err {
+		Error::Clap(ref err) => eprintln!("{}", err),
+		Error::Script { ref path, line, ref err } => {
+			eprint!("{}:{}: ", path.display(), line);
+			print_error(err);
+		},
+		Error::NegativeResponse { nrc, .. } => {
+			eprintln!("Error: {}", err);
+			if let Some(info) = uds::nrc(nrc) {
+				eprintln!("  {}", info.description);
+				if let Some(hint) = info.hint {
+					eprintln!("  Hint: {}", hint);
+				}
+			}
+		},
+		_ => eprintln!("Error: {}", err),
 	}
 }
\ No newline at end of file
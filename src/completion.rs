@@ -0,0 +1,199 @@
+#![cfg(feature = "cli")]
+
+use std::collections::HashMap;
+
+use rustyline::{
+	completion::Completer,
+	hint::Hinter,
+	highlight::Highlighter,
+	Helper,
+};
+
+use crate::{
+	app::App,
+	cli::{ArgKind, Command, Settings},
+	obd,
+	logfile,
+};
+
+
+
+/// Arguments of a command, used to complete its words
+#[derive(Debug, Clone)]
+pub struct CommandArgs {
+	pub name: String,
+	/// Kinds of the positional arguments, in order
+	pub args: Vec<ArgKind>,
+	/// Options that take a value, with the kinds of their values
+	pub options: Vec<(&'static str, ArgKind)>,
+}
+
+impl CommandArgs {
+	/// Returns the kind of the value of `option`, or `None` if it is a flag
+	fn option(&self, option: &str) -> Option<ArgKind> {
+		self.options.iter().find(|&&(name, _)| name == option).map(|&(_, kind)| kind)
+	}
+}
+
+/// Snapshot of the names and ids that can be completed. `App` is mutably
+/// borrowed by the `Cli`, so the REPL refreshes this after every command.
+#[derive(Debug, Default, Clone)]
+pub struct Completions {
+	pub commands: Vec<CommandArgs>,
+	pub datalinks: Vec<String>,
+	pub platforms: Vec<String>,
+	pub roms: Vec<String>,
+	pub tunes: Vec<String>,
+	pub logs: Vec<String>,
+	/// PID ids keyed by platform id
+	pub pids: HashMap<String, Vec<String>>,
+	/// Configured platform, used for PIDs when no platform is on the line
+	pub platform: Option<String>,
+}
+
+impl Completions {
+	/// Takes a snapshot of the current application state
+	pub fn new(app: &App, commands: &[Command], settings: &Settings) -> Completions {
+		let mut pids = HashMap::new();
+		pids.insert(obd::PLATFORM_ID.to_owned(), obd::pids().iter().map(|pid| format!("0x{:02X}", pid.pid)).collect());
+		for platform in app.definitions.definitions.iter() {
+			pids.insert(platform.id.clone(), platform.pids.iter().map(|pid| pid.id.to_string()).collect());
+		}
+
+		Completions {
+			commands: commands.iter().map(|command| CommandArgs {
+				name: command.command.clone(),
+				args: command.args.clone(),
+				options: command.options.clone(),
+			}).collect(),
+			datalinks: app.avail_links.iter().map(|link| link.name.clone()).collect(),
+			platforms: ::std::iter::once(obd::PLATFORM_ID.to_owned())
+				.chain(app.definitions.definitions.iter().map(|platform| platform.id.clone()))
+				.collect(),
+			roms: app.roms.roms.iter().map(|rom| rom.id.clone()).collect(),
+			tunes: app.tunes.tunes.iter().map(|tune| tune.id.clone()).collect(),
+			logs: logfile::list(&app.logs_dir()).map(|logs| logs.into_iter().map(|log| log.name).collect()).unwrap_or_default(),
+			pids,
+			platform: settings.platform.clone(),
+		}
+	}
+
+	/// Returns the candidates for the word being typed at the end of `line`
+	pub fn candidates(&self, line: &str) -> Vec<String> {
+		let mut words: Vec<&str> = line.split_whitespace().collect();
+		// The last word is still being typed unless the line ends in whitespace
+		let prefix = if line.ends_with(char::is_whitespace) { "" } else { words.pop().unwrap_or("") };
+
+		if words.is_empty() {
+			return filter(self.commands.iter().map(|command| command.name.as_str()), prefix);
+		}
+
+		let command = match self.commands.iter().find(|command| command.name == words[0]) {
+			Some(command) => command,
+			None => return Vec::new(),
+		};
+
+		// Values of options are not positional arguments. `option` is the
+		// kind of the value expected next, if any.
+		let mut positionals: Vec<&str> = Vec::new();
+		let mut values: Vec<(ArgKind, &str)> = Vec::new();
+		let mut option = None;
+		for &word in words[1..].iter() {
+			if let Some(kind) = option.take() {
+				values.push((kind, word));
+			} else if word.starts_with('-') {
+				option = command.option(word);
+			} else {
+				positionals.push(word);
+			}
+		}
+
+		// A datalink or platform given by option replaces both positionals,
+		// as in 'download --link sim rom1'
+		let linked = values.iter().any(|&(kind, _)| kind == ArgKind::Datalink || kind == ArgKind::Platform);
+		let args: Vec<ArgKind> = if linked && command.args.contains(&ArgKind::Datalink) {
+			command.args.iter().cloned().filter(|kind| *kind != ArgKind::Datalink && *kind != ArgKind::Platform).collect()
+		} else {
+			command.args.clone()
+		};
+
+		let kind = match option {
+			Some(kind) => kind,
+			None if prefix.starts_with('-') => return Vec::new(),
+			// PID arguments repeat until the end of the line
+			None => match args.get(positionals.len()) {
+				Some(kind) => *kind,
+				None if args.last() == Some(&ArgKind::Pid) => ArgKind::Pid,
+				None => return Vec::new(),
+			},
+		};
+
+		match kind {
+			ArgKind::Datalink => filter(self.datalinks.iter().map(String::as_str), prefix),
+			ArgKind::Platform => filter(self.platforms.iter().map(String::as_str), prefix),
+			ArgKind::Rom => filter(self.roms.iter().map(String::as_str), prefix),
+			ArgKind::Tune => filter(self.tunes.iter().map(String::as_str), prefix),
+			ArgKind::Log => filter(self.logs.iter().map(String::as_str), prefix),
+			ArgKind::Command => filter(self.commands.iter().map(|command| command.name.as_str()), prefix),
+			ArgKind::Values(values) => filter(values.iter().cloned(), prefix),
+			ArgKind::Pid => {
+				// Complete from the platform given on the line, or else the
+				// configured platform
+				let platform = values.iter().rev().find(|&&(kind, _)| kind == ArgKind::Platform).map(|&(_, value)| value)
+					.or_else(|| args.iter().position(|kind| *kind == ArgKind::Platform)
+						.and_then(|index| positionals.get(index).cloned()))
+					.or_else(|| self.platform.as_ref().map(String::as_str));
+				match platform.and_then(|platform| self.pids.get(platform)) {
+					Some(pids) => filter(pids.iter().map(String::as_str), prefix),
+					None => Vec::new(),
+				}
+			},
+			ArgKind::Other => Vec::new(),
+		}
+	}
+}
+
+/// Returns all values beginning with `prefix`
+fn filter<'a, I>(values: I, prefix: &str) -> Vec<String>
+where I: Iterator<Item=&'a str> {
+	let mut matches: Vec<String> = values.filter(|value| value.starts_with(prefix)).map(|value| value.to_owned()).collect();
+	matches.sort();
+	matches.dedup();
+	matches
+}
+
+
+
+/// rustyline helper that completes command names and ids
+pub struct CliHelper {
+	pub completions: Completions,
+}
+
+impl CliHelper {
+	pub fn new(completions: Completions) -> CliHelper {
+		CliHelper {
+			completions,
+		}
+	}
+}
+
+impl Completer for CliHelper {
+	type Candidate = String;
+
+	fn complete(&self, line: &str, pos: usize) -> rustyline::Result<(usize, Vec<String>)> {
+		let line = &line[..pos];
+		// Replace from the start of the current word
+		let start = line.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+		Ok((start, self.completions.candidates(line)))
+	}
+}
+
+impl Hinter for CliHelper {
+	fn hint(&self, _line: &str, _pos: usize) -> Option<String> {
+		None
+	}
+}
+
+impl Highlighter for CliHelper {}
+
+impl Helper for CliHelper {}
@@ -0,0 +1,224 @@
+#![cfg(feature = "cli")]
+
+//! Live data dashboard drawn in the terminal with ANSI escape codes
+
+use std::{
+	collections::{HashMap, VecDeque},
+	env,
+	io::{self, Write},
+};
+
+use serde::Deserialize;
+
+use crate::logger::{Channel, Sample};
+
+
+
+/// Number of samples shown in a sparkline
+const HISTORY: usize = 40;
+/// Sparkline characters from lowest to highest
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+/// Width of the terminal if it cannot be queried or found from $COLUMNS
+const DEFAULT_WIDTH: usize = 80;
+/// Width of the name, value and unit columns before the gauge bar
+const LABEL_WIDTH: usize = 44;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const RED: &str = "\x1b[31m";
+
+
+
+/// Scale and warning thresholds of a PID's gauge, set in the `[gauges]`
+/// table of `cli.toml` under the PID name. A gauge without a scale grows to
+/// fit the values seen.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct GaugeConfig {
+	pub min: Option<f64>,
+	pub max: Option<f64>,
+	/// Values at or above this are shown in yellow
+	pub warning: Option<f64>,
+	/// Values at or above this are shown in red
+	pub critical: Option<f64>,
+}
+
+impl GaugeConfig {
+	fn color(&self, value: f64) -> &'static str {
+		match (self.warning, self.critical) {
+			(_, Some(critical)) if value >= critical => RED,
+			(Some(warning), _) if value >= warning => YELLOW,
+			_ => GREEN,
+		}
+	}
+}
+
+
+
+/// A channel's current value and statistics
+struct Gauge {
+	name: String,
+	unit: String,
+	config: GaugeConfig,
+	current: Option<f64>,
+	/// Most recent values, oldest first. `None` marks missed samples.
+	history: VecDeque<Option<f64>>,
+	min: f64,
+	max: f64,
+	sum: f64,
+	count: usize,
+}
+
+impl Gauge {
+	fn update(&mut self, value: Option<f64>) {
+		self.current = value;
+		if self.history.len() == HISTORY {
+			self.history.pop_front();
+		}
+		self.history.push_back(value);
+
+		if let Some(value) = value {
+			self.min = self.min.min(value);
+			self.max = self.max.max(value);
+			self.sum += value;
+			self.count += 1;
+		}
+	}
+
+	/// Returns the lower and upper ends of the scale
+	fn range(&self) -> (f64, f64) {
+		let low = self.config.min.unwrap_or_else(|| if self.count == 0 { 0.0 } else { self.min.min(0.0) });
+		let high = self.config.max.unwrap_or_else(|| if self.count == 0 { 1.0 } else { self.max });
+		if high > low { (low, high) } else { (low, low + 1.0) }
+	}
+
+	/// Returns the position of `value` on the scale from 0 to 1
+	fn position(&self, value: f64) -> f64 {
+		let (low, high) = self.range();
+		((value - low) / (high - low)).max(0.0).min(1.0)
+	}
+
+	fn bar(&self, width: usize) -> String {
+		let filled = match self.current {
+			Some(value) => (self.position(value) * width as f64).round() as usize,
+			None => 0,
+		};
+		let color = self.current.map(|value| self.config.color(value)).unwrap_or(DIM);
+		format!("{}{}{}{}{}", color, "█".repeat(filled), DIM, "·".repeat(width - filled), RESET)
+	}
+
+	fn sparkline(&self) -> String {
+		let mut line = String::new();
+		for value in self.history.iter() {
+			match *value {
+				Some(value) => {
+					let level = (self.position(value) * (SPARKS.len() - 1) as f64).round() as usize;
+					line.push_str(self.config.color(value));
+					line.push(SPARKS[level]);
+				},
+				None => line.push(' '),
+			}
+		}
+		line.push_str(RESET);
+		// Keep the statistics aligned while the history fills
+		line.push_str(&" ".repeat(HISTORY - self.history.len()));
+		line
+	}
+
+	fn statistics(&self) -> String {
+		if self.count == 0 {
+			return format!("{}no data{}", DIM, RESET);
+		}
+		format!("min {:.2}  max {}{:.2}{}  avg {:.2}",
+			self.min, self.config.color(self.max), self.max, RESET, self.sum / self.count as f64)
+	}
+}
+
+
+
+/// Full-screen view of live PID values. The terminal is restored when the
+/// dashboard is dropped.
+pub struct Dashboard {
+	gauges: Vec<Gauge>,
+	out: io::Stdout,
+}
+
+impl Dashboard {
+	/// Switches the terminal to the dashboard.
+	///
+	/// # Arguments
+	/// `config` - Gauge settings keyed by PID name, ignoring case
+	pub fn new(channels: &[Channel], config: &HashMap<String, GaugeConfig>) -> io::Result<Dashboard> {
+		let gauges = channels.iter().map(|channel| Gauge {
+			name: channel.name.clone(),
+			unit: channel.unit.clone(),
+			config: config.iter()
+				.find(|&(name, _)| name.eq_ignore_ascii_case(&channel.name))
+				.map(|(_, config)| config.clone())
+				.unwrap_or_default(),
+			current: None,
+			history: VecDeque::with_capacity(HISTORY),
+			min: ::std::f64::INFINITY,
+			max: ::std::f64::NEG_INFINITY,
+			sum: 0.0,
+			count: 0,
+		}).collect();
+
+		let out = io::stdout();
+		// Use the alternate screen and hide the cursor
+		write!(out.lock(), "\x1b[?1049h\x1b[?25l\x1b[2J")?;
+		Ok(Dashboard {
+			gauges,
+			out,
+		})
+	}
+
+	pub fn update(&mut self, sample: &Sample) {
+		for (gauge, value) in self.gauges.iter_mut().zip(sample.values.iter()) {
+			gauge.update(*value);
+		}
+	}
+
+	/// Redraws the screen below a title line
+	pub fn draw(&self, title: &str) -> io::Result<()> {
+		let width = terminal_width().unwrap_or(DEFAULT_WIDTH);
+		let bar_width = width.saturating_sub(LABEL_WIDTH + 1).max(10);
+
+		let mut out = self.out.lock();
+		// Overwrite the previous frame in place to avoid flicker
+		writeln!(out, "\x1b[H{}{}{}\x1b[K\n\x1b[K", BOLD, title, RESET)?;
+		for gauge in self.gauges.iter() {
+			let value = match gauge.current {
+				Some(value) => format!("{}{:>12.2}{}", gauge.config.color(value), value, RESET),
+				None => format!("{:>12}", "-"),
+			};
+			writeln!(out, "{}{:<22.22}{} {} {:<6.6} [{}]\x1b[K", BOLD, gauge.name, RESET, value, gauge.unit, gauge.bar(bar_width))?;
+			writeln!(out, "{:<22} {}  {}\x1b[K\n\x1b[K", "", gauge.sparkline(), gauge.statistics())?;
+		}
+		write!(out, "\x1b[J")?;
+		out.flush()
+	}
+}
+
+impl Drop for Dashboard {
+	fn drop(&mut self) {
+		// Show the cursor and leave the alternate screen
+		let mut out = self.out.lock();
+		let _ = write!(out, "\x1b[?25h\x1b[?1049l");
+		let _ = out.flush();
+	}
+}
+
+
+
+/// Returns the width of the terminal in columns. Queried each frame so the
+/// dashboard follows when the terminal is resized.
+fn terminal_width() -> Option<usize> {
+	match terminal_size::terminal_size() {
+		Some((terminal_size::Width(columns), _)) if columns > 0 => Some(usize::from(columns)),
+		_ => env::var("COLUMNS").ok().and_then(|columns| columns.parse::<usize>().ok()),
+	}
+}
@@ -0,0 +1,298 @@
+//! ELM327 and compatible (STN11xx, OBDLink) serial adapters. The adapter is
+//! put in raw CAN mode so ISO-TP and UDS are handled by tuneutils.
+
+use std::{
+	cell::RefCell,
+	collections::VecDeque,
+	io::{self, Read, Write},
+	rc::Rc,
+	str,
+	time::{Duration, Instant},
+};
+
+use serialport::SerialPort;
+
+use tuneutils::{
+	error::{Error as TuneError, Result as TuneResult},
+	link::{DataLink, DataLinkEntry},
+	protocols::can::{CanInterface, Message},
+};
+
+use crate::datalink::{message, open_serial};
+
+
+
+/// Default baud rate of ELM327 adapters
+pub const DEFAULT_BAUD: u32 = 38400;
+
+/// Time to wait for the adapter to answer an AT command
+const COMMAND_TIMEOUT: Duration = Duration::from_millis(2000);
+/// Time to wait for the adapter after sending a CAN frame. The adapter
+/// collects responses until its own timeout (ATST) expires.
+const FRAME_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Commands sent when the adapter is opened
+const INIT_COMMANDS: &[&str] = &[
+	// Echo off
+	"ATE0",
+	// Linefeeds off
+	"ATL0",
+	// Spaces off
+	"ATS0",
+	// Print CAN ids
+	"ATH1",
+	// ISO 15765-4 CAN, 11 bit ids, 500 kbaud. Switched to ATSP7 (29 bit
+	// ids) when a frame is sent to an extended id.
+	"ATSP6",
+	// No CAN auto formatting. ISO-TP framing is done by the host.
+	"ATCAF0",
+	// No automatic flow control. The host sends flow control frames.
+	"ATCFC0",
+	// Response timeout of 50 * 4ms
+	"ATST32",
+];
+
+
+
+/// Entry for an ELM327 serial adapter
+pub struct Elm327DataLinkEntry {
+	/// Serial device, e.g. /dev/ttyUSB0 or COM3
+	pub port: String,
+	pub baud: u32,
+}
+
+impl DataLinkEntry for Elm327DataLinkEntry {
+	fn create(&self) -> TuneResult<Box<DataLink>> {
+		let port = open_serial(&self.port, self.baud)?;
+		Ok(Box::new(Elm327DataLink {
+			can: Rc::new(Elm327Can::open(port)?),
+		}))
+	}
+
+	fn description(&self) -> String {
+		format!("ELM327 on {} ({} baud)", self.port, self.baud)
+	}
+
+	fn typename(&self) -> String {
+		"ELM327".to_owned()
+	}
+}
+
+
+
+pub struct Elm327DataLink {
+	can: Rc<Elm327Can>,
+}
+
+impl DataLink for Elm327DataLink {
+	fn can(&self) -> Option<Rc<CanInterface>> {
+		Some(self.can.clone())
+	}
+}
+
+
+
+struct Elm327State {
+	port: Box<SerialPort>,
+	/// True if the adapter uses 29 bit ids (ATSP7)
+	extended: bool,
+	/// Id set with ATSH
+	header: Option<u32>,
+	/// Frames received but not yet returned by `recv`
+	frames: VecDeque<Message>,
+}
+
+/// CAN interface over an ELM327 adapter
+pub struct Elm327Can {
+	state: RefCell<Elm327State>,
+}
+
+impl Elm327Can {
+	/// Resets and configures the adapter
+	pub fn open(port: Box<SerialPort>) -> TuneResult<Elm327Can> {
+		let mut state = Elm327State {
+			port,
+			extended: false,
+			header: None,
+			frames: VecDeque::new(),
+		};
+
+		// Reset. The response includes the version string.
+		state.command("ATZ")?;
+		for command in INIT_COMMANDS {
+			state.configure(command)?;
+		}
+
+		Ok(Elm327Can {
+			state: RefCell::new(state),
+		})
+	}
+}
+
+impl Elm327State {
+	/// Sends a line and returns the response lines up to the prompt
+	fn command(&mut self, line: &str) -> TuneResult<Vec<String>> {
+		self.send_line(line)?;
+		self.read_until_prompt(COMMAND_TIMEOUT)
+	}
+
+	/// Sends a command that the adapter answers with OK
+	fn configure(&mut self, command: &str) -> TuneResult<()> {
+		let response = self.command(command)?;
+		if !response.iter().any(|line| line == "OK") {
+			return Err(adapter_error(&format!("adapter rejected {}: {}", command, response.join(" "))));
+		}
+		Ok(())
+	}
+
+	/// Selects 11 or 29 bit ids and the id frames are sent to
+	fn set_header(&mut self, id: u32) -> TuneResult<()> {
+		let extended = id > 0x7FF;
+		if extended != self.extended {
+			self.configure(if extended { "ATSP7" } else { "ATSP6" })?;
+			self.extended = extended;
+			self.header = None;
+		}
+		if self.header == Some(id) {
+			return Ok(());
+		}
+
+		if extended {
+			// ATSH sets the low 24 bits. The top 5 bits are the priority set with ATCP.
+			self.configure(&format!("ATCP{:02X}", id >> 24))?;
+			self.configure(&format!("ATSH{:06X}", id & 0xFF_FFFF))?;
+		} else {
+			self.configure(&format!("ATSH{:03X}", id))?;
+		}
+		self.header = Some(id);
+		Ok(())
+	}
+
+	fn send_line(&mut self, line: &str) -> TuneResult<()> {
+		self.port.write_all(line.as_bytes())?;
+		self.port.write_all(b"\r")?;
+		self.port.flush()?;
+		Ok(())
+	}
+
+	/// Reads until the '>' prompt and returns the non-empty lines
+	fn read_until_prompt(&mut self, timeout: Duration) -> TuneResult<Vec<String>> {
+		let start = Instant::now();
+		let mut buffer = Vec::new();
+		let mut byte = [0u8; 1];
+
+		loop {
+			if start.elapsed() > timeout {
+				return Err(TuneError::Timeout);
+			}
+			match self.port.read(&mut byte) {
+				Ok(0) => continue,
+				Ok(_) => {
+					if byte[0] == b'>' {
+						break;
+					}
+					buffer.push(byte[0]);
+				},
+				Err(ref err) if err.kind() == io::ErrorKind::TimedOut => continue,
+				Err(err) => return Err(err.into()),
+			}
+		}
+
+		let text = String::from_utf8_lossy(&buffer);
+		Ok(text.split(|c| c == '\r' || c == '\n')
+			.map(|line| line.trim().to_owned())
+			.filter(|line| !line.is_empty())
+			.collect())
+	}
+
+	/// Parses response lines into CAN frames
+	fn parse_frames(&mut self, lines: &[String]) -> TuneResult<()> {
+		for line in lines {
+			match line.as_str() {
+				"NO DATA" | "OK" | "STOPPED" | "SEARCHING..." => continue,
+				"CAN ERROR" | "BUFFER FULL" | "BUS ERROR" | "DATA ERROR" | "?" => {
+					return Err(adapter_error(line));
+				},
+				_ => (),
+			}
+			if let Some(frame) = parse_frame(line) {
+				self.frames.push_back(frame);
+			}
+		}
+		Ok(())
+	}
+}
+
+impl CanInterface for Elm327Can {
+	fn send(&self, id: u32, message: &[u8]) -> TuneResult<()> {
+		let mut state = self.state.borrow_mut();
+		state.set_header(id)?;
+
+		let data: String = message.iter().map(|b| format!("{:02X}", b)).collect();
+		state.send_line(&data)?;
+		// The adapter prints every frame received until its timeout
+		let lines = state.read_until_prompt(FRAME_TIMEOUT)?;
+		state.parse_frames(&lines)
+	}
+
+	fn recv(&self, timeout: Duration) -> TuneResult<Message> {
+		let mut state = self.state.borrow_mut();
+		if let Some(frame) = state.frames.pop_front() {
+			return Ok(frame);
+		}
+
+		// Wait for frames the adapter prints on its own
+		let start = Instant::now();
+		let mut buffer = String::new();
+		let mut byte = [0u8; 1];
+		while start.elapsed() < timeout {
+			match state.port.read(&mut byte) {
+				Ok(1) => {
+					match byte[0] {
+						b'\r' | b'\n' | b'>' => {
+							if let Some(frame) = parse_frame(buffer.trim()) {
+								return Ok(frame);
+							}
+							buffer.clear();
+						},
+						b => buffer.push(b as char),
+					}
+				},
+				Ok(_) => (),
+				Err(ref err) if err.kind() == io::ErrorKind::TimedOut => (),
+				Err(err) => return Err(err.into()),
+			}
+		}
+		Err(TuneError::Timeout)
+	}
+}
+
+
+
+/// Parses a line such as "7E80641000000000000" (11 bit id, spaces off)
+/// or "18DAF1100641..." (29 bit id)
+fn parse_frame(line: &str) -> Option<Message> {
+	let line: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+	if !line.chars().all(|c| c.is_ascii_hexdigit()) {
+		return None;
+	}
+
+	// An 11 bit id has 3 digits so the length is odd. A 29 bit id has 8.
+	let id_len = if line.len() % 2 == 1 { 3 } else { 8 };
+	if line.len() < id_len {
+		return None;
+	}
+	let id = u32::from_str_radix(&line[..id_len], 16).ok()?;
+	let data: Vec<u8> = line.as_bytes()[id_len..].chunks(2)
+		.filter_map(|pair| u8::from_str_radix(str::from_utf8(pair).ok()?, 16).ok())
+		.collect();
+	if data.len() > 8 {
+		return None;
+	}
+	Some(message(id, &data))
+}
+
+/// Creates an error for an unexpected adapter response
+fn adapter_error(response: &str) -> TuneError {
+	io::Error::new(io::ErrorKind::Other, format!("ELM327 error: {}", response)).into()
+}
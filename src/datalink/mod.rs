@@ -0,0 +1,239 @@
+pub mod sim;
+pub mod net;
+pub mod replay;
+#[cfg(feature = "serial")]
+pub mod elm327;
+#[cfg(feature = "serial")]
+pub mod slcan;
+
+use std::{
+	fs,
+	path::{Path, PathBuf},
+};
+#[cfg(feature = "serial")]
+use std::{io, time::Duration};
+
+use serde::{Serialize, Deserialize};
+
+use tuneutils::{
+	link::DataLinkEntry,
+	protocols::can,
+};
+#[cfg(feature = "socketcan")]
+use tuneutils::link::SocketCanDataLinkEntry;
+
+use crate::error::{Error, Result};
+
+
+
+/// Configuration of a user-added datalink
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum LinkConfig {
+	/// SocketCAN interface. Requires the `socketcan` feature.
+	SocketCan {
+		interface: String,
+	},
+	/// Simulated ECU. Uses the default simulation if `config` is not set.
+	Sim {
+		config: Option<PathBuf>,
+	},
+	/// ELM327 compatible serial adapter. Requires the `serial` feature.
+	Elm327 {
+		port: String,
+		baud: u32,
+	},
+	/// CAN bridged over the network with the cannelloni protocol
+	Net {
+		remote: String,
+		local: Option<String>,
+		#[serde(default)]
+		protocol: net::Protocol,
+	},
+	/// Replay of a candump or Vector ASC trace
+	Replay {
+		file: PathBuf,
+		#[serde(default)]
+		realtime: bool,
+		#[serde(default)]
+		match_requests: bool,
+	},
+	/// SLCAN serial CAN adapter. Requires the `serial` feature.
+	Slcan {
+		port: String,
+		baud: u32,
+		/// CAN bitrate in bits per second
+		bitrate: u32,
+	},
+}
+
+impl LinkConfig {
+	/// Creates the datalink entry described by this configuration
+	pub fn entry(&self) -> Result<Box<DataLinkEntry>> {
+		match *self {
+			#[cfg(feature = "socketcan")]
+			LinkConfig::SocketCan { ref interface } => Ok(Box::new(SocketCanDataLinkEntry { interface: interface.clone(), })),
+			#[cfg(not(feature = "socketcan"))]
+			LinkConfig::SocketCan { .. } => Err(Error::UnsupportedLink("socketcan")),
+			LinkConfig::Sim { ref config } => Ok(Box::new(sim::SimDataLinkEntry::new(config.clone())?)),
+			LinkConfig::Net { ref remote, ref local, protocol } => Ok(Box::new(net::NetDataLinkEntry { remote: remote.clone(), local: local.clone(), protocol })),
+			LinkConfig::Replay { ref file, realtime, match_requests } => Ok(Box::new(replay::ReplayDataLinkEntry::new(file.clone(), realtime, match_requests)?)),
+			#[cfg(feature = "serial")]
+			LinkConfig::Elm327 { ref port, baud } => Ok(Box::new(elm327::Elm327DataLinkEntry { port: port.clone(), baud })),
+			#[cfg(not(feature = "serial"))]
+			LinkConfig::Elm327 { .. } => Err(Error::UnsupportedLink("elm327")),
+			#[cfg(feature = "serial")]
+			LinkConfig::Slcan { ref port, baud, bitrate } => {
+				if !slcan::valid_bitrate(bitrate) {
+					return Err(Error::InvalidBitrate(bitrate));
+				}
+				Ok(Box::new(slcan::SlcanDataLinkEntry { port: port.clone(), baud, bitrate }))
+			},
+			#[cfg(not(feature = "serial"))]
+			LinkConfig::Slcan { .. } => Err(Error::UnsupportedLink("slcan")),
+		}
+	}
+}
+
+
+
+/// Creates a CAN message. Data longer than 8 bytes is truncated.
+pub fn message(id: u32, data: &[u8]) -> can::Message {
+	let len = data.len().min(8);
+	let mut buffer = [0u8; 8];
+	buffer[..len].copy_from_slice(&data[..len]);
+	can::Message {
+		id,
+		data: buffer,
+		len: len as u8,
+	}
+}
+
+
+
+/// Opens a serial port in raw mode with a short read timeout
+#[cfg(feature = "serial")]
+pub fn open_serial(port: &str, baud: u32) -> tuneutils::error::Result<Box<serialport::SerialPort>> {
+	let settings = serialport::SerialPortSettings {
+		baud_rate: baud,
+		timeout: Duration::from_millis(10),
+		..Default::default()
+	};
+	Ok(serialport::open_with_settings(port, &settings).map_err(io::Error::from)?)
+}
+
+
+
+/// A datalink added by the user and saved to the links file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedLink {
+	/// Name used to refer to the link
+	pub name: String,
+	#[serde(flatten)]
+	pub config: LinkConfig,
+}
+
+/// A name given by the user to a discovered datalink
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkAlias {
+	/// Name used to refer to the link
+	pub name: String,
+	/// Identity of the discovered link, see `identity`
+	pub link: String,
+}
+
+/// Contents of the links file. Empty lists are left out because toml
+/// cannot write an empty array after the tables of the other list.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LinksFile {
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub link: Vec<SavedLink>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub alias: Vec<LinkAlias>,
+}
+
+/// Loads saved datalinks and aliases. Returns an empty file if it does not
+/// exist.
+pub fn load_links(path: &Path) -> Result<LinksFile> {
+	if !path.exists() {
+		return Ok(LinksFile::default());
+	}
+	let contents = fs::read_to_string(path)?;
+	Ok(toml::from_str(&contents)?)
+}
+
+/// Saves datalinks and aliases, replacing the file
+pub fn save_links(path: &Path, links: &[SavedLink], aliases: &[LinkAlias]) -> Result<()> {
+	let file = LinksFile {
+		link: links.to_vec(),
+		alias: aliases.to_vec(),
+	};
+	fs::write(path, toml::to_string(&file)?)?;
+	Ok(())
+}
+
+
+
+/// A datalink that can be referred to by name
+pub struct NamedLink {
+	pub name: String,
+	pub entry: Box<DataLinkEntry>,
+	/// Identity of a discovered link. `None` if the link was added by the user.
+	pub identity: Option<String>,
+}
+
+impl NamedLink {
+	/// Returns true if the link was added by the user rather than discovered
+	pub fn saved(&self) -> bool {
+		self.identity.is_none()
+	}
+}
+
+/// Returns true if `name` can be used as a datalink name
+pub fn valid_name(name: &str) -> bool {
+	!name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.' || c == ':')
+}
+
+/// Returns the identity of a discovered datalink. It is made from the type
+/// and the description, which names the interface or device, so it does not
+/// depend on the order links are discovered in.
+pub fn identity(entry: &DataLinkEntry) -> String {
+	format!("{}:{}", entry.typename(), entry.description())
+}
+
+/// Derives the name of a discovered datalink from its type and interface or
+/// device, e.g. "socketcan-can0"
+pub fn default_name(entry: &DataLinkEntry) -> String {
+	let typename = slug(&entry.typename());
+	let description = slug(&entry.description());
+	if description.is_empty() {
+		typename
+	} else {
+		format!("{}-{}", typename, description)
+	}
+}
+
+/// Returns `name` if it is not in `taken`, otherwise `name` followed by the
+/// lowest number from 2 that is not taken
+pub fn unique_name(name: String, taken: &[String]) -> String {
+	if !taken.contains(&name) {
+		return name;
+	}
+	(2..).map(|number| format!("{}-{}", name, number))
+		.find(|numbered| !taken.contains(numbered))
+		.unwrap()
+}
+
+/// Lowercases text and replaces each run of characters that cannot be used
+/// in a name with an underscore
+fn slug(text: &str) -> String {
+	let mut slug = String::new();
+	for c in text.to_lowercase().chars() {
+		if c.is_alphanumeric() {
+			slug.push(c);
+		} else if !slug.is_empty() && !slug.ends_with('_') {
+			slug.push('_');
+		}
+	}
+	slug.trim_end_matches('_').to_owned()
+}
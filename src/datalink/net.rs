@@ -0,0 +1,260 @@
+//! CAN frames tunneled over the network using the cannelloni protocol, over
+//! UDP or TCP. Allows using a bus attached to a remote gateway.
+
+use std::{
+	cell::RefCell,
+	collections::VecDeque,
+	io::{self, Read, Write},
+	net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+	rc::Rc,
+	time::Duration,
+};
+
+use serde::{Serialize, Deserialize};
+
+use tuneutils::{
+	error::{Error as TuneError, Result as TuneResult},
+	link::{DataLink, DataLinkEntry},
+	protocols::can::{CanInterface, Message},
+};
+
+use crate::datalink::message;
+
+
+
+/// Protocol version in the header of UDP packets
+const VERSION: u8 = 2;
+/// Operation code of data packets
+const OP_DATA: u8 = 0;
+/// Greeting exchanged when a TCP connection is opened
+const TCP_HANDSHAKE: &[u8] = b"CANNELLONIv1";
+/// Flag marking 29 bit ids, as in SocketCAN
+const EFF_FLAG: u32 = 0x8000_0000;
+/// Flag marking remote frames, which carry no data
+const RTR_FLAG: u32 = 0x4000_0000;
+/// Flag in the length byte marking CAN FD frames, which have a flags byte
+/// after the length
+const FD_FLAG: u8 = 0x80;
+/// Time to wait for the TCP connection and handshake
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+
+/// Transport used to reach the remote bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+	Udp,
+	Tcp,
+}
+
+impl Default for Protocol {
+	fn default() -> Protocol {
+		Protocol::Udp
+	}
+}
+
+
+
+/// Entry for a network CAN bridge
+pub struct NetDataLinkEntry {
+	/// Address of the remote peer, e.g. gateway:20000
+	pub remote: String,
+	/// Local address to receive UDP packets on. Defaults to the remote port
+	/// on all interfaces.
+	pub local: Option<String>,
+	pub protocol: Protocol,
+}
+
+impl DataLinkEntry for NetDataLinkEntry {
+	fn create(&self) -> TuneResult<Box<DataLink>> {
+		let remote = resolve(&self.remote)?;
+		let transport = match self.protocol {
+			Protocol::Udp => {
+				let local = match self.local {
+					Some(ref local) => resolve(local)?,
+					None => SocketAddr::new([0, 0, 0, 0].into(), remote.port()),
+				};
+				Transport::Udp(UdpSocket::bind(local)?, remote)
+			},
+			Protocol::Tcp => {
+				let mut stream = TcpStream::connect_timeout(&remote, CONNECT_TIMEOUT)?;
+				stream.set_nodelay(true)?;
+				stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+				stream.write_all(TCP_HANDSHAKE)?;
+				let mut greeting = [0u8; 12];
+				stream.read_exact(&mut greeting)?;
+				if &greeting[..] != TCP_HANDSHAKE {
+					return Err(io::Error::new(io::ErrorKind::InvalidData, "peer is not a cannelloni server").into());
+				}
+				Transport::Tcp(stream)
+			},
+		};
+
+		Ok(Box::new(NetDataLink {
+			can: Rc::new(NetCan {
+				state: RefCell::new(NetState {
+					transport,
+					sequence: 0,
+					buffer: Vec::new(),
+					frames: VecDeque::new(),
+				}),
+			}),
+		}))
+	}
+
+	fn description(&self) -> String {
+		let protocol = match self.protocol {
+			Protocol::Udp => "UDP",
+			Protocol::Tcp => "TCP",
+		};
+		format!("CAN over {} to {}", protocol, self.remote)
+	}
+
+	fn typename(&self) -> String {
+		"Network".to_owned()
+	}
+}
+
+fn resolve(address: &str) -> io::Result<SocketAddr> {
+	address.to_socket_addrs()?.next().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("could not resolve {}", address)))
+}
+
+
+
+pub struct NetDataLink {
+	can: Rc<NetCan>,
+}
+
+impl DataLink for NetDataLink {
+	fn can(&self) -> Option<Rc<CanInterface>> {
+		Some(self.can.clone())
+	}
+}
+
+
+
+enum Transport {
+	Udp(UdpSocket, SocketAddr),
+	Tcp(TcpStream),
+}
+
+struct NetState {
+	transport: Transport,
+	/// Sequence number of the next UDP packet
+	sequence: u8,
+	/// Bytes of a partially received TCP frame
+	buffer: Vec<u8>,
+	/// Frames received but not yet returned by `recv`
+	frames: VecDeque<Message>,
+}
+
+/// CAN interface over a cannelloni peer
+pub struct NetCan {
+	state: RefCell<NetState>,
+}
+
+impl CanInterface for NetCan {
+	fn send(&self, id: u32, message: &[u8]) -> TuneResult<()> {
+		if message.len() > 8 {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "frames are limited to 8 bytes").into());
+		}
+
+		let mut guard = self.state.borrow_mut();
+		let state = &mut *guard;
+		let mut frame = Vec::with_capacity(13);
+		encode_frame(&mut frame, id, message);
+
+		match state.transport {
+			Transport::Udp(ref socket, remote) => {
+				let mut packet = vec![VERSION, OP_DATA, state.sequence, 0, 1];
+				packet.extend(frame);
+				socket.send_to(&packet, remote)?;
+			},
+			Transport::Tcp(ref mut stream) => stream.write_all(&frame)?,
+		}
+		state.sequence = state.sequence.wrapping_add(1);
+		Ok(())
+	}
+
+	fn recv(&self, timeout: Duration) -> TuneResult<Message> {
+		let mut state = self.state.borrow_mut();
+		if let Some(frame) = state.frames.pop_front() {
+			return Ok(frame);
+		}
+
+		// A zero timeout means blocking forever to the socket API
+		let timeout = Some(timeout.max(Duration::from_millis(1)));
+		let mut packet = [0u8; 1500];
+		let NetState { ref mut transport, ref mut buffer, ref mut frames, .. } = *state;
+
+		match *transport {
+			Transport::Udp(ref socket, _) => {
+				socket.set_read_timeout(timeout)?;
+				let len = match socket.recv_from(&mut packet) {
+					Ok((len, _)) => len,
+					Err(ref err) if is_timeout(err) => return Err(TuneError::Timeout),
+					Err(err) => return Err(err.into()),
+				};
+				decode_packet(&packet[..len], frames);
+			},
+			Transport::Tcp(ref mut stream) => {
+				stream.set_read_timeout(timeout)?;
+				let len = match stream.read(&mut packet) {
+					Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed").into()),
+					Ok(len) => len,
+					Err(ref err) if is_timeout(err) => return Err(TuneError::Timeout),
+					Err(err) => return Err(err.into()),
+				};
+				buffer.extend_from_slice(&packet[..len]);
+				let used = decode_frames(buffer, frames);
+				buffer.drain(..used);
+			},
+		}
+
+		frames.pop_front().ok_or(TuneError::Timeout)
+	}
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+	err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut
+}
+
+
+
+/// Appends a frame in cannelloni encoding
+fn encode_frame(out: &mut Vec<u8>, id: u32, data: &[u8]) {
+	let id = if id > 0x7FF { id | EFF_FLAG } else { id };
+	out.extend_from_slice(&[(id >> 24) as u8, (id >> 16) as u8, (id >> 8) as u8, id as u8]);
+	out.push(data.len() as u8);
+	out.extend_from_slice(data);
+}
+
+/// Decodes the frames of a UDP packet
+fn decode_packet(packet: &[u8], frames: &mut VecDeque<Message>) {
+	if packet.len() < 5 || packet[0] != VERSION || packet[1] != OP_DATA {
+		return;
+	}
+	decode_frames(&packet[5..], frames);
+}
+
+/// Decodes complete frames. Returns the number of bytes used.
+fn decode_frames(data: &[u8], frames: &mut VecDeque<Message>) -> usize {
+	let mut offset = 0;
+	while data.len() >= offset + 5 {
+		let id = data[offset..offset + 4].iter().fold(0u32, |acc, b| (acc << 8) | u32::from(*b));
+		let fd = data[offset + 4] & FD_FLAG != 0;
+		let header = if fd { 6 } else { 5 };
+		// Remote frames give the requested length but no data follows
+		let len = if id & RTR_FLAG != 0 { 0 } else { (data[offset + 4] & !FD_FLAG) as usize };
+		if data.len() < offset + header + len {
+			break;
+		}
+		// CAN FD frames do not fit in a classic CAN message. Remote frames are not received.
+		if !fd && id & RTR_FLAG == 0 && len <= 8 {
+			frames.push_back(message(id & !EFF_FLAG & 0x1FFF_FFFF, &data[offset + header..offset + header + len]));
+		}
+		offset += header + len;
+	}
+	offset
+}
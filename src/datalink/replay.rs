@@ -0,0 +1,347 @@
+//! Replays a recorded candump or Vector ASC trace as a CAN interface so
+//! sessions can be reproduced without the vehicle.
+
+use std::{
+	cell::RefCell,
+	fs,
+	io,
+	path::{Path, PathBuf},
+	rc::Rc,
+	thread,
+	time::{Duration, Instant},
+};
+
+use tuneutils::{
+	error::{Error as TuneError, Result as TuneResult},
+	link::{DataLink, DataLinkEntry},
+	protocols::can::{CanInterface, Message},
+};
+
+use crate::{
+	datalink::message,
+	error::{Error, Result},
+};
+
+
+
+/// A frame of a recorded trace
+#[derive(Debug, Clone)]
+pub struct TraceFrame {
+	/// Seconds since the start of the trace
+	pub time: f64,
+	pub id: u32,
+	pub data: Vec<u8>,
+}
+
+/// Loads a trace. The format is detected from the contents. Frames that
+/// cannot be replayed as classic CAN frames, such as CAN FD frames, are
+/// skipped.
+pub fn load_trace(path: &Path) -> Result<Vec<TraceFrame>> {
+	let contents = fs::read_to_string(path)?;
+	let mut frames: Vec<TraceFrame> = Vec::new();
+
+	for (number, line) in contents.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() {
+			continue;
+		}
+		if line.starts_with('(') {
+			match parse_candump(line) {
+				Some(Some(frame)) => frames.push(frame),
+				Some(None) => (),
+				None => return Err(Error::InvalidTrace(number + 1)),
+			}
+		} else if let Some(frame) = parse_asc(line) {
+			// ASC files have headers and events that are not frames
+			frames.push(frame);
+		}
+	}
+
+	// Make times relative to the first frame
+	if let Some(start) = frames.first().map(|frame| frame.time) {
+		for frame in frames.iter_mut() {
+			frame.time -= start;
+		}
+	}
+	Ok(frames)
+}
+
+/// Parses a candump log line such as "(1436509052.249713) can0 7E8#0641000000000000".
+/// Returns `None` if the line is not a frame and `Some(None)` for frames
+/// that cannot be replayed.
+fn parse_candump(line: &str) -> Option<Option<TraceFrame>> {
+	let mut parts = line.split_whitespace();
+	let time = parts.next()?.trim_matches(|c| c == '(' || c == ')').parse::<f64>().ok()?;
+	let _interface = parts.next()?;
+	let frame = parts.next()?;
+
+	let mut split = frame.splitn(2, '#');
+	let id = u32::from_str_radix(split.next()?, 16).ok()?;
+	let data = split.next()?;
+	// Remote frames carry no data
+	if data.starts_with('R') {
+		return Some(Some(TraceFrame { time, id, data: Vec::new() }));
+	}
+	// CAN FD frames ("##"), odd-length data and more than 8 bytes do not fit
+	// in a classic CAN message
+	if data.starts_with('#') || data.len() % 2 != 0 || data.len() > 16 {
+		return Some(None);
+	}
+	let data = (0..data.len()).step_by(2)
+		.map(|i| u8::from_str_radix(&data[i..i + 2], 16).ok())
+		.collect::<Option<Vec<u8>>>()?;
+	Some(Some(TraceFrame { time, id, data }))
+}
+
+/// Parses a Vector ASC line such as "0.002000 1  7E8  Rx   d 8 06 41 00 00 00 00 00 00".
+/// Extended ids end with 'x'.
+fn parse_asc(line: &str) -> Option<TraceFrame> {
+	let parts: Vec<&str> = line.split_whitespace().collect();
+	if parts.len() < 6 {
+		return None;
+	}
+	let time = parts[0].parse::<f64>().ok()?;
+	// parts[1] is the channel
+	parts[1].parse::<u32>().ok()?;
+	let id = u32::from_str_radix(parts[2].trim_end_matches(|c| c == 'x' || c == 'X'), 16).ok()?;
+	// parts[3] is Rx or Tx
+	if !parts[4].eq_ignore_ascii_case("d") {
+		return None;
+	}
+	let len = parts[5].parse::<usize>().ok()?;
+	if len > 8 || parts.len() < 6 + len {
+		return None;
+	}
+	let data = parts[6..6 + len].iter()
+		.map(|byte| u8::from_str_radix(byte, 16).ok())
+		.collect::<Option<Vec<u8>>>()?;
+	Some(TraceFrame { time, id, data })
+}
+
+
+
+/// Entry for a trace replay datalink
+pub struct ReplayDataLinkEntry {
+	pub file: PathBuf,
+	/// Wait between frames as recorded instead of replaying immediately
+	pub realtime: bool,
+	/// Answer each request with the frames recorded after the same request
+	pub match_requests: bool,
+	frames: Rc<Vec<TraceFrame>>,
+}
+
+impl ReplayDataLinkEntry {
+	/// Loads the trace
+	pub fn new(file: PathBuf, realtime: bool, match_requests: bool) -> Result<ReplayDataLinkEntry> {
+		let frames = Rc::new(load_trace(&file)?);
+		Ok(ReplayDataLinkEntry {
+			file,
+			realtime,
+			match_requests,
+			frames,
+		})
+	}
+}
+
+impl DataLinkEntry for ReplayDataLinkEntry {
+	fn create(&self) -> TuneResult<Box<DataLink>> {
+		Ok(Box::new(ReplayDataLink {
+			can: Rc::new(ReplayCan {
+				frames: self.frames.clone(),
+				realtime: self.realtime,
+				match_requests: self.match_requests,
+				state: RefCell::new(ReplayState {
+					cursor: 0,
+					request_id: None,
+					remaining: 0,
+					start: Instant::now(),
+					start_time: 0.0,
+				}),
+			}),
+		}))
+	}
+
+	fn description(&self) -> String {
+		let pacing = if self.realtime { "realtime" } else { "fast" };
+		let matching = if self.match_requests { ", matching requests" } else { "" };
+		format!("Replay of {} ({}{})", self.file.display(), pacing, matching)
+	}
+
+	fn typename(&self) -> String {
+		"Replay".to_owned()
+	}
+}
+
+
+
+pub struct ReplayDataLink {
+	can: Rc<ReplayCan>,
+}
+
+impl DataLink for ReplayDataLink {
+	fn can(&self) -> Option<Rc<CanInterface>> {
+		Some(self.can.clone())
+	}
+}
+
+
+
+struct ReplayState {
+	/// Index of the next frame to replay
+	cursor: usize,
+	/// Id of the last matched request. Frames with this id are requests
+	/// and are not replayed.
+	request_id: Option<u32>,
+	/// Payload bytes left in a multi-frame request
+	remaining: usize,
+	/// Time replay started or the last request was matched
+	start: Instant,
+	/// Trace time corresponding to `start`
+	start_time: f64,
+}
+
+/// CAN interface that replays a trace
+pub struct ReplayCan {
+	frames: Rc<Vec<TraceFrame>>,
+	realtime: bool,
+	match_requests: bool,
+	state: RefCell<ReplayState>,
+}
+
+impl CanInterface for ReplayCan {
+	fn send(&self, id: u32, message: &[u8]) -> TuneResult<()> {
+		if !self.match_requests {
+			return Ok(());
+		}
+
+		let mut state = self.state.borrow_mut();
+		let position = match frame_type(message) {
+			Some(FLOW_CONTROL) => {
+				// The tester's flow control depends on the tester, not the
+				// trace. Skip the recorded one if it is next.
+				if let Some(frame) = self.frames.get(state.cursor) {
+					if frame.id == id && frame_type(&frame.data) == Some(FLOW_CONTROL) {
+						state.cursor += 1;
+					}
+				}
+				return Ok(());
+			},
+			Some(CONSECUTIVE_FRAME) => {
+				// Continues the last matched request
+				let remaining = state.remaining;
+				self.frames[state.cursor..].iter().position(|frame| frame.id == id).map(|i| i + state.cursor)
+					.filter(|&index| same_payload(&self.frames[index].data, message, remaining))
+			},
+			_ => {
+				// Search forward from the cursor, then from the start of the trace
+				let matches = |frame: &TraceFrame| frame.id == id && same_payload(&frame.data, message, 0);
+				self.frames[state.cursor..].iter().position(&matches).map(|i| i + state.cursor)
+					.or_else(|| self.frames.iter().position(&matches))
+			},
+		};
+
+		match position {
+			Some(index) => {
+				state.remaining = match frame_type(message) {
+					Some(FIRST_FRAME) => first_frame_length(message).saturating_sub(6),
+					Some(CONSECUTIVE_FRAME) => state.remaining.saturating_sub(7),
+					_ => 0,
+				};
+				state.cursor = index + 1;
+				state.request_id = Some(id);
+				state.start = Instant::now();
+				state.start_time = self.frames[index].time;
+				Ok(())
+			},
+			None => Err(io::Error::new(io::ErrorKind::NotFound, format!("request {:03X}#{} is not in the trace", id, hex(message))).into()),
+		}
+	}
+
+	fn recv(&self, timeout: Duration) -> TuneResult<Message> {
+		let mut state = self.state.borrow_mut();
+
+		if self.match_requests {
+			// Recorded flow control from the tester is not part of the response
+			while let Some(frame) = self.frames.get(state.cursor) {
+				if Some(frame.id) != state.request_id || frame_type(&frame.data) != Some(FLOW_CONTROL) {
+					break;
+				}
+				state.cursor += 1;
+			}
+		}
+
+		let frame = match self.frames.get(state.cursor) {
+			// The next request ends the recorded response
+			Some(frame) if self.match_requests && Some(frame.id) == state.request_id => None,
+			frame => frame,
+		};
+		let frame = match frame {
+			Some(frame) => frame,
+			None => {
+				thread::sleep(timeout);
+				return Err(TuneError::Timeout);
+			},
+		};
+
+		if self.realtime {
+			let due = Duration::from_millis(((frame.time - state.start_time).max(0.0) * 1000.0) as u64);
+			let elapsed = state.start.elapsed();
+			if due > elapsed {
+				let wait = due - elapsed;
+				if wait > timeout {
+					thread::sleep(timeout);
+					return Err(TuneError::Timeout);
+				}
+				thread::sleep(wait);
+			}
+		}
+
+		state.cursor += 1;
+		Ok(message(frame.id, &frame.data))
+	}
+}
+
+const FIRST_FRAME: u8 = 1;
+const CONSECUTIVE_FRAME: u8 = 2;
+const FLOW_CONTROL: u8 = 3;
+
+/// Returns the ISO-TP frame type from the protocol control information
+fn frame_type(data: &[u8]) -> Option<u8> {
+	data.first().map(|pci| pci >> 4)
+}
+
+/// Returns the message length announced by an ISO-TP first frame
+fn first_frame_length(data: &[u8]) -> usize {
+	match (data.first(), data.get(1)) {
+		(Some(&high), Some(&low)) => ((high as usize & 0x0F) << 8) | low as usize,
+		_ => 0,
+	}
+}
+
+/// Returns the ISO-TP payload of a frame without padding. `remaining` is the
+/// number of bytes left in the message for consecutive frames. Returns `None`
+/// if the frame is not a single, first or consecutive frame.
+fn payload(data: &[u8], remaining: usize) -> Option<&[u8]> {
+	match frame_type(data)? {
+		0 => data.get(1..1 + (data[0] & 0x0F) as usize),
+		// First frames are never padded
+		FIRST_FRAME => Some(data),
+		CONSECUTIVE_FRAME => data.get(..1 + remaining.min(7)),
+		_ => None,
+	}
+}
+
+/// Compares a recorded frame with a sent frame by their ISO-TP payload, so
+/// that differences in padding are ignored. Other frames must be identical.
+fn same_payload(recorded: &[u8], sent: &[u8], remaining: usize) -> bool {
+	match (payload(recorded, remaining), payload(sent, remaining)) {
+		(Some(payload_recorded), Some(payload_sent)) => {
+			frame_type(recorded) == frame_type(sent) && payload_recorded == payload_sent
+		},
+		_ => recorded == sent,
+	}
+}
+
+fn hex(data: &[u8]) -> String {
+	data.iter().map(|b| format!("{:02X}", b)).collect()
+}
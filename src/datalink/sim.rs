@@ -0,0 +1,644 @@
+//! In-process simulated ECU. Answers ISO-TP framed UDS and OBD-II requests
+//! so commands can be tried without a vehicle.
+
+use std::{
+	cell::RefCell,
+	collections::VecDeque,
+	f64::consts::PI,
+	fs,
+	path::{Path, PathBuf},
+	rc::Rc,
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+
+use tuneutils::{
+	error::Error as TuneError,
+	link::{DataLink, DataLinkEntry},
+	protocols::can::{CanInterface, Message},
+};
+
+use crate::{
+	datalink::message,
+	diag::parse_dtc,
+	error::Result,
+};
+
+
+
+/// Functional (broadcast) OBD-II request id
+const FUNCTIONAL_ID: u32 = 0x7DF;
+/// First and last physical request ids answered by the simulated ECU.
+/// Responses are sent on the request id + 8.
+const PHYSICAL_FIRST: u32 = 0x7E0;
+const PHYSICAL_LAST: u32 = 0x7E7;
+/// Longest response that fits the 12-bit length of an ISO-TP first frame
+const MAX_RESPONSE: usize = 0xFFF;
+
+
+
+/// Generates PID values over time
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Generator {
+	Constant {
+		value: f64,
+	},
+	/// Sine wave between `min` and `max`. `period` is in seconds.
+	Sine {
+		min: f64,
+		max: f64,
+		period: f64,
+	},
+	/// Sawtooth ramp from `min` to `max`. `period` is in seconds.
+	Ramp {
+		min: f64,
+		max: f64,
+		period: f64,
+	},
+	/// Uniformly distributed noise between `min` and `max`
+	Random {
+		min: f64,
+		max: f64,
+	},
+}
+
+impl Generator {
+	fn value(&self, elapsed: f64, seed: &mut u64) -> f64 {
+		match *self {
+			Generator::Constant { value } => value,
+			Generator::Sine { min, max, period } => {
+				min + (max - min) * (1.0 + (2.0 * PI * elapsed / period).sin()) / 2.0
+			},
+			Generator::Ramp { min, max, period } => {
+				min + (max - min) * (elapsed % period) / period
+			},
+			Generator::Random { min, max } => {
+				// xorshift64
+				*seed ^= *seed << 13;
+				*seed ^= *seed >> 7;
+				*seed ^= *seed << 17;
+				min + (max - min) * (*seed as f64 / u64::max_value() as f64)
+			},
+		}
+	}
+}
+
+
+
+/// Service a simulated PID is read through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PidService {
+	/// OBD-II mode 01
+	Obd,
+	/// UDS ReadDataByIdentifier (0x22)
+	Uds,
+}
+
+/// A PID answered by the simulated ECU
+#[derive(Debug, Clone, Deserialize)]
+pub struct SimPid {
+	pub service: PidService,
+	/// PID number for OBD-II or data identifier for UDS
+	pub id: u16,
+	/// Size of the raw value in bytes, from 1 to 4
+	#[serde(default = "default_pid_bytes")]
+	pub bytes: u8,
+	/// Generator of the raw value
+	pub generator: Generator,
+}
+
+fn default_pid_bytes() -> u8 {
+	2
+}
+
+
+
+/// Configuration of a simulated ECU. Loaded from a TOML file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SimConfig {
+	/// ROM image served through ReadMemoryByAddress
+	pub rom: Option<PathBuf>,
+	/// Address of the first byte of the ROM image
+	pub rom_base: u32,
+	/// Size of the zero-filled ROM used when `rom` is not set
+	pub rom_size: usize,
+	/// Vehicle identification number returned by OBD-II mode 09
+	pub vin: String,
+	/// Stored trouble codes, e.g. "P0301"
+	pub dtcs: Vec<String>,
+	#[serde(rename = "pid")]
+	pub pids: Vec<SimPid>,
+}
+
+impl Default for SimConfig {
+	fn default() -> SimConfig {
+		SimConfig {
+			rom: None,
+			rom_base: 0,
+			rom_size: 1024 * 1024,
+			vin: "LIBRETUNERSIM0001".to_owned(),
+			dtcs: vec!["P0301".to_owned(), "P0420".to_owned()],
+			pids: vec![
+				// Engine speed, 1/4 rpm
+				SimPid { service: PidService::Obd, id: 0x0C, bytes: 2, generator: Generator::Sine { min: 3200.0, max: 24000.0, period: 20.0 } },
+				// Coolant temperature, offset by 40
+				SimPid { service: PidService::Obd, id: 0x05, bytes: 1, generator: Generator::Ramp { min: 60.0, max: 130.0, period: 120.0 } },
+				// Vehicle speed, km/h
+				SimPid { service: PidService::Obd, id: 0x0D, bytes: 1, generator: Generator::Sine { min: 0.0, max: 120.0, period: 30.0 } },
+				// Throttle position, 100/255 %
+				SimPid { service: PidService::Obd, id: 0x11, bytes: 1, generator: Generator::Random { min: 20.0, max: 240.0 } },
+			],
+		}
+	}
+}
+
+impl SimConfig {
+	/// Loads a configuration file
+	pub fn load(path: &Path) -> Result<SimConfig> {
+		let contents = fs::read_to_string(path)?;
+		Ok(toml::from_str(&contents)?)
+	}
+}
+
+
+
+/// Entry for a simulated ECU datalink
+pub struct SimDataLinkEntry {
+	/// Configuration file. Defaults are used if `None`.
+	pub config_path: Option<PathBuf>,
+	config: SimConfig,
+	rom: Vec<u8>,
+}
+
+impl SimDataLinkEntry {
+	/// Loads the configuration and ROM image of a simulated ECU
+	pub fn new(config_path: Option<PathBuf>) -> Result<SimDataLinkEntry> {
+		let config = match config_path {
+			Some(ref path) => SimConfig::load(path)?,
+			None => SimConfig::default(),
+		};
+		let rom = match config.rom {
+			Some(ref path) => fs::read(path)?,
+			None => vec![0; config.rom_size],
+		};
+
+		Ok(SimDataLinkEntry {
+			config_path,
+			config,
+			rom,
+		})
+	}
+}
+
+impl DataLinkEntry for SimDataLinkEntry {
+	fn create(&self) -> tuneutils::error::Result<Box<DataLink>> {
+		Ok(Box::new(SimDataLink {
+			can: Rc::new(SimCan::new(Ecu::new(self.config.clone(), self.rom.clone()))),
+		}))
+	}
+
+	fn description(&self) -> String {
+		match self.config_path {
+			Some(ref path) => format!("Simulated ECU ({})", path.display()),
+			None => "Simulated ECU".to_owned(),
+		}
+	}
+
+	fn typename(&self) -> String {
+		"Simulated".to_owned()
+	}
+}
+
+
+
+pub struct SimDataLink {
+	can: Rc<SimCan>,
+}
+
+impl DataLink for SimDataLink {
+	fn can(&self) -> Option<Rc<CanInterface>> {
+		Some(self.can.clone())
+	}
+}
+
+
+
+/// Simulated ECU state
+pub struct Ecu {
+	config: SimConfig,
+	rom: Vec<u8>,
+	dtcs: Vec<u16>,
+	start: Instant,
+	seed: u64,
+}
+
+impl Ecu {
+	pub fn new(config: SimConfig, rom: Vec<u8>) -> Ecu {
+		let dtcs = config.dtcs.iter().filter_map(|code| parse_dtc(code)).collect();
+		let seed = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) | 1;
+
+		Ecu {
+			config,
+			rom,
+			dtcs,
+			start: Instant::now(),
+			seed,
+		}
+	}
+
+	/// Handles a UDS or OBD-II request. Returns the response.
+	pub fn handle(&mut self, request: &[u8]) -> Vec<u8> {
+		let sid = match request.first() {
+			Some(sid) => *sid,
+			None => return vec![0x7F, 0x00, 0x13],
+		};
+		let data = &request[1..];
+
+		let result = match sid {
+			0x01 => self.obd_current_data(data),
+			0x02 => self.obd_freeze_frame(data),
+			0x03 => Ok(self.obd_codes(0x43)),
+			0x04 => {
+				self.dtcs.clear();
+				Ok(vec![0x44])
+			},
+			// Nothing is pending. Stored codes are permanent until repaired.
+			0x07 => Ok(vec![0x47, 0x00]),
+			0x09 => self.obd_vehicle_info(data),
+			0x0A => Ok(self.obd_codes(0x4A)),
+			0x10 => {
+				let session = data.first().cloned().unwrap_or(0x01);
+				Ok(vec![0x50, session, 0x00, 0x32, 0x01, 0xF4])
+			},
+			0x14 => {
+				self.dtcs.clear();
+				Ok(vec![0x54])
+			},
+			0x19 => self.read_dtc_information(data),
+			0x22 => self.read_data_by_identifier(data),
+			0x23 => self.read_memory_by_address(data),
+			0x27 => self.security_access(data),
+			0x3E => Ok(vec![0x7E, data.first().cloned().unwrap_or(0) & 0x7F]),
+			// serviceNotSupported
+			_ => Err(0x11),
+		};
+
+		match result {
+			// responseTooLong
+			Ok(ref response) if response.len() > MAX_RESPONSE => vec![0x7F, sid, 0x14],
+			Ok(response) => response,
+			Err(nrc) => vec![0x7F, sid, nrc],
+		}
+	}
+
+	/// Returns the raw value of a PID
+	fn pid_value(&mut self, service: PidService, id: u16) -> Option<Vec<u8>> {
+		let elapsed = self.start.elapsed();
+		let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+		let seed = &mut self.seed;
+		let pid = self.config.pids.iter().find(|pid| pid.service == service && pid.id == id)?;
+
+		let bytes = pid.bytes.max(1).min(4) as usize;
+		let max = (1u64 << (bytes * 8)) - 1;
+		let raw = pid.generator.value(elapsed, seed).round().max(0.0).min(max as f64) as u64;
+		Some((0..bytes).rev().map(|i| (raw >> (i * 8)) as u8).collect())
+	}
+
+	fn obd_current_data(&mut self, data: &[u8]) -> ::std::result::Result<Vec<u8>, u8> {
+		let pid = *data.first().ok_or(0x13u8)?;
+		let mut response = vec![0x41, pid];
+
+		if pid % 0x20 == 0 {
+			let mask = self.supported_mask(pid);
+			response.extend_from_slice(&[(mask >> 24) as u8, (mask >> 16) as u8, (mask >> 8) as u8, mask as u8]);
+			return Ok(response);
+		}
+
+		if pid == 0x01 {
+			// Monitor status: MIL on if codes are stored, all monitors complete
+			let mil = if self.dtcs.is_empty() { 0 } else { 0x80 };
+			response.extend_from_slice(&[mil | self.dtcs.len().min(0x7F) as u8, 0x07, 0xFF, 0x00]);
+			return Ok(response);
+		}
+
+		// requestOutOfRange
+		let value = self.pid_value(PidService::Obd, u16::from(pid)).ok_or(0x31u8)?;
+		response.extend(value);
+		Ok(response)
+	}
+
+	/// Supported PID bitmask for PIDs pid+1 to pid+0x20
+	fn supported_mask(&self, pid: u8) -> u32 {
+		let mut mask = 0u32;
+		for sim in self.config.pids.iter().filter(|sim| sim.service == PidService::Obd) {
+			let id = sim.id as u32;
+			if id > pid as u32 && id <= pid as u32 + 0x20 {
+				mask |= 1 << (0x20 - (id - pid as u32));
+			}
+		}
+		// Report the next range as supported if any PIDs are in it
+		if self.config.pids.iter().any(|sim| sim.service == PidService::Obd && sim.id as u32 > pid as u32 + 0x20) {
+			mask |= 1;
+		}
+		mask
+	}
+
+	/// Freeze frame 0 holds the current PID values and the first stored code
+	fn obd_freeze_frame(&mut self, data: &[u8]) -> ::std::result::Result<Vec<u8>, u8> {
+		if data.len() < 2 {
+			return Err(0x13);
+		}
+		let (pid, frame) = (data[0], data[1]);
+		let first = *self.dtcs.first().ok_or(0x31u8)?;
+		if frame != 0 {
+			return Err(0x31);
+		}
+
+		let mut response = vec![0x42, pid, frame];
+		if pid % 0x20 == 0 {
+			let mut mask = self.supported_mask(pid);
+			if pid == 0x00 {
+				// PID 02 is the code that stored the frame
+				mask |= 1 << (0x20 - 0x02);
+			}
+			response.extend_from_slice(&[(mask >> 24) as u8, (mask >> 16) as u8, (mask >> 8) as u8, mask as u8]);
+		} else if pid == 0x02 {
+			response.extend_from_slice(&[(first >> 8) as u8, first as u8]);
+		} else {
+			response.extend(self.pid_value(PidService::Obd, u16::from(pid)).ok_or(0x31u8)?);
+		}
+		Ok(response)
+	}
+
+	fn obd_codes(&self, response_sid: u8) -> Vec<u8> {
+		let mut response = vec![response_sid, self.dtcs.len() as u8];
+		for dtc in self.dtcs.iter() {
+			response.push((dtc >> 8) as u8);
+			response.push(*dtc as u8);
+		}
+		response
+	}
+
+	fn obd_vehicle_info(&self, data: &[u8]) -> ::std::result::Result<Vec<u8>, u8> {
+		match data.first() {
+			Some(&0x00) => Ok(vec![0x49, 0x00, 0x40, 0x00, 0x00, 0x00]),
+			Some(&0x02) => {
+				let mut response = vec![0x49, 0x02, 0x01];
+				response.extend(self.config.vin.bytes());
+				Ok(response)
+			},
+			Some(_) => Err(0x31),
+			None => Err(0x13),
+		}
+	}
+
+	fn read_dtc_information(&mut self, data: &[u8]) -> ::std::result::Result<Vec<u8>, u8> {
+		match data.first() {
+			// reportDTCByStatusMask
+			Some(&0x02) => {
+				let mut response = vec![0x59, 0x02, 0xFF];
+				for dtc in self.dtcs.iter() {
+					// testFailed | confirmedDTC
+					response.extend_from_slice(&[(dtc >> 8) as u8, *dtc as u8, 0x00, 0x09]);
+				}
+				Ok(response)
+			},
+			// reportDTCSnapshotRecordByDTCNumber, reportDTCExtDataRecordByDTCNumber
+			Some(&sub) if sub == 0x04 || sub == 0x06 => {
+				if data.len() != 5 {
+					return Err(0x13);
+				}
+				let code = (u16::from(data[1]) << 8) | u16::from(data[2]);
+				if data[3] != 0 || !self.dtcs.contains(&code) {
+					return Err(0x31);
+				}
+				let mut response = vec![0x59, sub, data[1], data[2], data[3], 0x09];
+				if sub == 0x04 {
+					// One snapshot with the OBD-II PIDs as data identifiers 0xF400 + PID
+					let pids: Vec<u16> = self.config.pids.iter()
+						.filter(|sim| sim.service == PidService::Obd)
+						.map(|sim| sim.id)
+						.collect();
+					response.extend_from_slice(&[0x01, pids.len() as u8]);
+					for pid in pids {
+						response.extend_from_slice(&[0xF4, pid as u8]);
+						response.extend(self.pid_value(PidService::Obd, pid).unwrap_or_default());
+					}
+				} else {
+					// Record 01: occurrence counter
+					response.extend_from_slice(&[0x01, 0x01]);
+				}
+				Ok(response)
+			},
+			// subFunctionNotSupported
+			Some(_) => Err(0x12),
+			None => Err(0x13),
+		}
+	}
+
+	fn read_data_by_identifier(&mut self, data: &[u8]) -> ::std::result::Result<Vec<u8>, u8> {
+		if data.is_empty() || data.len() % 2 != 0 {
+			return Err(0x13);
+		}
+
+		let mut response = vec![0x62];
+		for did in data.chunks(2) {
+			let id = (u16::from(did[0]) << 8) | u16::from(did[1]);
+			let value = match id {
+				// VIN
+				0xF190 => self.config.vin.as_bytes().to_vec(),
+				_ => self.pid_value(PidService::Uds, id).ok_or(0x31u8)?,
+			};
+			response.extend_from_slice(did);
+			response.extend(value);
+		}
+		Ok(response)
+	}
+
+	fn read_memory_by_address(&self, data: &[u8]) -> ::std::result::Result<Vec<u8>, u8> {
+		let format = *data.first().ok_or(0x13u8)?;
+		let size_len = (format >> 4) as usize;
+		let address_len = (format & 0x0F) as usize;
+		if size_len == 0 || size_len > 4 || address_len == 0 || address_len > 4 || data.len() != 1 + address_len + size_len {
+			return Err(0x13);
+		}
+
+		let be = |bytes: &[u8]| bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize);
+		let address = be(&data[1..1 + address_len]);
+		let size = be(&data[1 + address_len..]);
+
+		let start = address.checked_sub(self.config.rom_base as usize).ok_or(0x31u8)?;
+		let end = start.checked_add(size).ok_or(0x31u8)?;
+		if end > self.rom.len() {
+			return Err(0x31);
+		}
+
+		let mut response = vec![0x63];
+		response.extend_from_slice(&self.rom[start..end]);
+		Ok(response)
+	}
+
+	fn security_access(&self, data: &[u8]) -> ::std::result::Result<Vec<u8>, u8> {
+		let level = *data.first().ok_or(0x13u8)?;
+		if level % 2 == 1 {
+			// Request seed
+			Ok(vec![0x67, level, 0x12, 0x34, 0x56])
+		} else {
+			// Any key is accepted
+			Ok(vec![0x67, level])
+		}
+	}
+}
+
+
+
+/// Multi-frame ISO-TP response waiting for a flow control frame
+struct PendingResponse {
+	id: u32,
+	data: Vec<u8>,
+	offset: usize,
+}
+
+/// Multi-frame ISO-TP request being received
+struct PendingRequest {
+	id: u32,
+	size: usize,
+	data: Vec<u8>,
+}
+
+struct SimState {
+	ecu: Ecu,
+	outgoing: VecDeque<Message>,
+	request: Option<PendingRequest>,
+	response: Option<PendingResponse>,
+}
+
+/// CAN interface backed by a simulated ECU. Frames sent to the ECU are
+/// reassembled as ISO-TP and responses are queued for `recv`.
+pub struct SimCan {
+	state: RefCell<SimState>,
+}
+
+impl SimCan {
+	pub fn new(ecu: Ecu) -> SimCan {
+		SimCan {
+			state: RefCell::new(SimState {
+				ecu,
+				outgoing: VecDeque::new(),
+				request: None,
+				response: None,
+			}),
+		}
+	}
+}
+
+impl SimState {
+	/// Handles a complete request and queues the response
+	fn respond(&mut self, id: u32, request: &[u8]) {
+		let response_id = if id == FUNCTIONAL_ID { 0x7E8 } else { id + 8 };
+		let response = self.ecu.handle(request);
+
+		if response.len() <= 7 {
+			let mut frame = vec![response.len() as u8];
+			frame.extend_from_slice(&response);
+			self.outgoing.push_back(message(response_id, &frame));
+			return;
+		}
+
+		// First frame, then wait for flow control
+		let mut frame = vec![0x10 | ((response.len() >> 8) & 0x0F) as u8, response.len() as u8];
+		frame.extend_from_slice(&response[..6]);
+		self.outgoing.push_back(message(response_id, &frame));
+		self.response = Some(PendingResponse {
+			id: response_id,
+			data: response,
+			offset: 6,
+		});
+	}
+
+	fn receive(&mut self, id: u32, data: &[u8]) {
+		if id != FUNCTIONAL_ID && (id < PHYSICAL_FIRST || id > PHYSICAL_LAST) {
+			return;
+		}
+		let pci = match data.first() {
+			Some(pci) => *pci,
+			None => return,
+		};
+
+		match pci >> 4 {
+			// Single frame
+			0x0 => {
+				let len = (pci & 0x0F) as usize;
+				if len == 0 || len >= data.len() {
+					return;
+				}
+				self.respond(id, &data[1..=len]);
+			},
+			// First frame
+			0x1 => {
+				if data.len() < 8 {
+					return;
+				}
+				let size = (((pci & 0x0F) as usize) << 8) | data[1] as usize;
+				self.request = Some(PendingRequest {
+					id,
+					size,
+					data: data[2..].to_vec(),
+				});
+				// Continue to send, no separation time
+				self.outgoing.push_back(message(id + 8, &[0x30, 0x00, 0x00]));
+			},
+			// Consecutive frame
+			0x2 => {
+				let complete = match self.request {
+					Some(ref mut request) if request.id == id => {
+						request.data.extend_from_slice(&data[1..]);
+						request.data.len() >= request.size
+					},
+					_ => return,
+				};
+				if complete {
+					let mut request = self.request.take().unwrap();
+					request.data.truncate(request.size);
+					self.respond(id, &request.data);
+				}
+			},
+			// Flow control from the tester
+			0x3 => {
+				if let Some(mut response) = self.response.take() {
+					let mut index = 1u8;
+					while response.offset < response.data.len() {
+						let end = (response.offset + 7).min(response.data.len());
+						let mut frame = vec![0x20 | (index & 0x0F)];
+						frame.extend_from_slice(&response.data[response.offset..end]);
+						self.outgoing.push_back(message(response.id, &frame));
+						response.offset = end;
+						index = index.wrapping_add(1);
+					}
+				}
+			},
+			_ => (),
+		}
+	}
+}
+
+impl CanInterface for SimCan {
+	fn send(&self, id: u32, message: &[u8]) -> tuneutils::error::Result<()> {
+		self.state.borrow_mut().receive(id, message);
+		Ok(())
+	}
+
+	fn recv(&self, timeout: Duration) -> tuneutils::error::Result<Message> {
+		if let Some(message) = self.state.borrow_mut().outgoing.pop_front() {
+			return Ok(message);
+		}
+		// Nothing will arrive until the tester sends another frame
+		::std::thread::sleep(timeout.min(Duration::from_millis(10)));
+		Err(TuneError::Timeout)
+	}
+}
+
@@ -0,0 +1,278 @@
+//! SLCAN (Lawicel) serial CAN adapters such as the CANable or CANtact,
+//! driven directly from userspace instead of through slcand.
+
+use std::{
+	cell::RefCell,
+	collections::VecDeque,
+	io::{self, Read, Write},
+	rc::Rc,
+	str,
+	time::{Duration, Instant},
+};
+
+use serialport::SerialPort;
+
+use tuneutils::{
+	error::{Error as TuneError, Result as TuneResult},
+	link::{DataLink, DataLinkEntry},
+	protocols::can::{CanInterface, Message},
+};
+
+use crate::datalink::{message, open_serial};
+
+
+
+/// Default serial baud rate. USB adapters ignore it.
+pub const DEFAULT_BAUD: u32 = 115_200;
+/// Default CAN bitrate
+pub const DEFAULT_BITRATE: u32 = 500_000;
+
+/// Time to wait for the adapter to acknowledge a command
+const COMMAND_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Returns the setup command for a standard CAN bitrate
+fn bitrate_command(bitrate: u32) -> Option<&'static str> {
+	Some(match bitrate {
+		10_000 => "S0",
+		20_000 => "S1",
+		50_000 => "S2",
+		100_000 => "S3",
+		125_000 => "S4",
+		250_000 => "S5",
+		500_000 => "S6",
+		800_000 => "S7",
+		1_000_000 => "S8",
+		_ => return None,
+	})
+}
+
+/// Returns true if `bitrate` is supported by SLCAN adapters
+pub fn valid_bitrate(bitrate: u32) -> bool {
+	bitrate_command(bitrate).is_some()
+}
+
+
+
+/// Entry for an SLCAN adapter
+pub struct SlcanDataLinkEntry {
+	/// Serial device, e.g. /dev/ttyACM0 or COM3
+	pub port: String,
+	pub baud: u32,
+	/// CAN bitrate in bits per second
+	pub bitrate: u32,
+}
+
+impl DataLinkEntry for SlcanDataLinkEntry {
+	fn create(&self) -> TuneResult<Box<DataLink>> {
+		let port = open_serial(&self.port, self.baud)?;
+		Ok(Box::new(SlcanDataLink {
+			can: Rc::new(SlcanCan::open(port, self.bitrate)?),
+		}))
+	}
+
+	fn description(&self) -> String {
+		format!("SLCAN on {} ({} bit/s)", self.port, self.bitrate)
+	}
+
+	fn typename(&self) -> String {
+		"SLCAN".to_owned()
+	}
+}
+
+
+
+pub struct SlcanDataLink {
+	can: Rc<SlcanCan>,
+}
+
+impl DataLink for SlcanDataLink {
+	fn can(&self) -> Option<Rc<CanInterface>> {
+		Some(self.can.clone())
+	}
+}
+
+
+
+struct SlcanState {
+	port: Box<SerialPort>,
+	/// Bytes of a partially received line
+	buffer: Vec<u8>,
+	/// Frames received but not yet returned by `recv`
+	frames: VecDeque<Message>,
+}
+
+/// CAN interface over an SLCAN adapter
+pub struct SlcanCan {
+	state: RefCell<SlcanState>,
+}
+
+impl SlcanCan {
+	/// Configures the bitrate, enables timestamps and opens the channel
+	pub fn open(port: Box<SerialPort>, bitrate: u32) -> TuneResult<SlcanCan> {
+		let setup = bitrate_command(bitrate).ok_or_else(|| slcan_error(&format!("unsupported bitrate {}", bitrate)))?;
+
+		let mut state = SlcanState {
+			port,
+			buffer: Vec::new(),
+			frames: VecDeque::new(),
+		};
+
+		// Close the channel in case it was left open. Errors are expected.
+		state.write_line("C")?;
+		let _ = state.wait_ack();
+
+		state.command(setup)?;
+		// Timestamps on
+		state.command("Z1")?;
+		state.command("O")?;
+
+		Ok(SlcanCan {
+			state: RefCell::new(state),
+		})
+	}
+}
+
+impl Drop for SlcanCan {
+	fn drop(&mut self) {
+		// Close the channel so the adapter stops acknowledging frames
+		let _ = self.state.borrow_mut().write_line("C");
+	}
+}
+
+/// Result of reading a line from the adapter
+enum Line {
+	/// Command acknowledged with CR, or a transmit acknowledgement
+	Ack,
+	/// Command rejected with BEL
+	Nack,
+	Frame(Message),
+}
+
+impl SlcanState {
+	fn write_line(&mut self, line: &str) -> TuneResult<()> {
+		self.port.write_all(line.as_bytes())?;
+		self.port.write_all(b"\r")?;
+		self.port.flush()?;
+		Ok(())
+	}
+
+	/// Sends a command and waits for the acknowledgement
+	fn command(&mut self, line: &str) -> TuneResult<()> {
+		self.write_line(line)?;
+		self.wait_ack().map_err(|_| slcan_error(&format!("adapter rejected {}", line)))
+	}
+
+	/// Waits for an acknowledgement. Frames received meanwhile are queued.
+	fn wait_ack(&mut self) -> TuneResult<()> {
+		let start = Instant::now();
+		while start.elapsed() < COMMAND_TIMEOUT {
+			match self.read_line()? {
+				Some(Line::Ack) => return Ok(()),
+				Some(Line::Nack) => return Err(slcan_error("command rejected")),
+				Some(Line::Frame(frame)) => self.frames.push_back(frame),
+				None => (),
+			}
+		}
+		Err(TuneError::Timeout)
+	}
+
+	/// Reads one line if available
+	fn read_line(&mut self) -> TuneResult<Option<Line>> {
+		let mut byte = [0u8; 1];
+		loop {
+			match self.port.read(&mut byte) {
+				Ok(1) => (),
+				Ok(_) => return Ok(None),
+				Err(ref err) if err.kind() == io::ErrorKind::TimedOut => return Ok(None),
+				Err(err) => return Err(err.into()),
+			}
+
+			match byte[0] {
+				0x07 => {
+					self.buffer.clear();
+					return Ok(Some(Line::Nack));
+				},
+				b'\r' => {
+					let line = String::from_utf8_lossy(&self.buffer).into_owned();
+					self.buffer.clear();
+					match line.as_str() {
+						// Empty line, 'z' or 'Z' acknowledge commands and transmits
+						"" | "z" | "Z" => return Ok(Some(Line::Ack)),
+						_ => if let Some(frame) = parse_frame(&line) {
+							return Ok(Some(Line::Frame(frame)));
+						},
+					}
+					// Remote frames, status replies and noise are skipped
+				},
+				b => self.buffer.push(b),
+			}
+		}
+	}
+}
+
+impl CanInterface for SlcanCan {
+	fn send(&self, id: u32, message: &[u8]) -> TuneResult<()> {
+		if message.len() > 8 {
+			return Err(slcan_error("frames are limited to 8 bytes"));
+		}
+
+		let data: String = message.iter().map(|b| format!("{:02X}", b)).collect();
+		let line = if id > 0x7FF {
+			format!("T{:08X}{}{}", id, message.len(), data)
+		} else {
+			format!("t{:03X}{}{}", id, message.len(), data)
+		};
+		self.state.borrow_mut().command(&line)
+	}
+
+	fn recv(&self, timeout: Duration) -> TuneResult<Message> {
+		let mut state = self.state.borrow_mut();
+		if let Some(frame) = state.frames.pop_front() {
+			return Ok(frame);
+		}
+
+		let start = Instant::now();
+		while start.elapsed() < timeout {
+			if let Some(Line::Frame(frame)) = state.read_line()? {
+				return Ok(frame);
+			}
+		}
+		Err(TuneError::Timeout)
+	}
+}
+
+
+
+/// Parses a received frame such as "t7E88024100BE3FA8130001A2F". The
+/// trailing four digit timestamp is present when timestamps are enabled.
+fn parse_frame(line: &str) -> Option<Message> {
+	let id_len = match line.chars().next()? {
+		't' => 3,
+		'T' => 8,
+		// Remote frames and acknowledgements carry no data
+		_ => return None,
+	};
+	let line = &line[1..];
+	if line.len() < id_len + 1 || !line.is_char_boundary(id_len + 1) {
+		return None;
+	}
+
+	let id = u32::from_str_radix(&line[..id_len], 16).ok()?;
+	let len = line[id_len..=id_len].parse::<usize>().ok()?;
+	if len > 8 {
+		return None;
+	}
+	let data_hex = line.get(id_len + 1..id_len + 1 + len * 2)?;
+	let data: Vec<u8> = data_hex.as_bytes().chunks(2)
+		.filter_map(|pair| u8::from_str_radix(str::from_utf8(pair).ok()?, 16).ok())
+		.collect();
+	if data.len() != len {
+		return None;
+	}
+	Some(message(id, &data))
+}
+
+/// Creates an error for an unexpected adapter response
+fn slcan_error(response: &str) -> TuneError {
+	io::Error::new(io::ErrorKind::Other, format!("SLCAN error: {}", response)).into()
+}
@@ -0,0 +1,391 @@
+//! Detailed trouble code reports: status, occurrence counters and freeze
+//! frames read through OBD-II and UDS ReadDTCInformation (0x19)
+
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use tuneutils::protocols::uds::UdsInterface;
+
+use crate::{
+	error::{Error, Result},
+	obd,
+	uds,
+};
+
+
+
+/// Names of the bits of a UDS DTC status byte, from bit 0
+const STATUS_BITS: [&str; 8] = [
+	"test failed",
+	"failed this cycle",
+	"pending",
+	"confirmed",
+	"not completed since clear",
+	"failed since clear",
+	"not completed this cycle",
+	"MIL requested",
+];
+
+const STATUS_PENDING: u8 = 0x04;
+const STATUS_CONFIRMED: u8 = 0x08;
+
+/// Range of UDS data identifiers that carry OBD-II PIDs (0xF400 + PID)
+const OBD_DID_BASE: u16 = 0xF400;
+
+
+
+/// Parses a trouble code such as "P0301" into its two-byte encoding
+pub fn parse_dtc(code: &str) -> Option<u16> {
+	let mut chars = code.chars();
+	let system = match chars.next()?.to_ascii_uppercase() {
+		'P' => 0,
+		'C' => 1,
+		'B' => 2,
+		'U' => 3,
+		_ => return None,
+	};
+	let rest = chars.as_str();
+	if rest.len() != 4 {
+		return None;
+	}
+	let digits = u16::from_str_radix(rest, 16).ok()?;
+	if digits >> 12 > 3 {
+		return None;
+	}
+	Some((system << 14) | digits)
+}
+
+/// Formats the two-byte encoding of a trouble code, e.g. "P0301"
+pub fn format_dtc(code: u16) -> String {
+	let system = ['P', 'C', 'B', 'U'][(code >> 14) as usize];
+	format!("{}{:04X}", system, code & 0x3FFF)
+}
+
+/// Returns the names of the bits set in a UDS DTC status byte
+pub fn status_names(status: u8) -> Vec<&'static str> {
+	STATUS_BITS.iter().enumerate()
+		.filter(|&(bit, _)| status & (1 << bit) != 0)
+		.map(|(_, name)| *name)
+		.collect()
+}
+
+
+
+/// A value recorded in a freeze frame
+#[derive(Debug, Clone, Serialize)]
+pub struct FrameValue {
+	/// OBD-II PID or UDS data identifier
+	pub id: u16,
+	pub name: Option<String>,
+	pub value: Option<f64>,
+	pub unit: Option<String>,
+	/// Raw bytes in hex
+	pub raw: String,
+}
+
+/// Snapshot of values stored when a trouble code was set
+#[derive(Debug, Clone, Serialize)]
+pub struct FreezeFrame {
+	/// OBD-II frame number or UDS snapshot record number
+	pub record: u8,
+	pub values: Vec<FrameValue>,
+}
+
+/// Everything known about one trouble code
+#[derive(Debug, Clone, Serialize)]
+pub struct DtcReport {
+	/// Code such as "P0301", without the failure type
+	pub code: String,
+	/// UDS failure type byte (DTC low byte), e.g. 0x1A
+	pub failure_type: Option<u8>,
+	pub description: Option<String>,
+	/// UDS status byte
+	pub status: Option<u8>,
+	/// Reported by OBD-II mode 03
+	pub stored: bool,
+	/// Reported by OBD-II mode 07
+	pub pending: bool,
+	/// Reported by OBD-II mode 0A. Cannot be cleared with a scan tool.
+	pub permanent: bool,
+	/// Occurrence counter from the UDS extended data
+	pub occurrences: Option<u32>,
+	pub freeze_frames: Vec<FreezeFrame>,
+}
+
+impl DtcReport {
+	fn new(code: String) -> DtcReport {
+		DtcReport {
+			code,
+			failure_type: None,
+			description: None,
+			status: None,
+			stored: false,
+			pending: false,
+			permanent: false,
+			occurrences: None,
+			freeze_frames: Vec::new(),
+		}
+	}
+
+	/// Returns "confirmed", "pending" and "permanent" as they apply
+	pub fn states(&self) -> Vec<&'static str> {
+		let status = self.status.unwrap_or(0);
+		let mut states = Vec::new();
+		if self.stored || status & STATUS_CONFIRMED != 0 {
+			states.push("confirmed");
+		}
+		if self.pending || status & STATUS_PENDING != 0 {
+			states.push("pending");
+		}
+		if self.permanent {
+			states.push("permanent");
+		}
+		states
+	}
+}
+
+/// A full diagnostic report
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+	/// Seconds since the Unix epoch
+	pub time: u64,
+	pub datalink: String,
+	pub platform: String,
+	pub codes: Vec<DtcReport>,
+}
+
+impl Report {
+	/// Writes the report as readable text
+	pub fn write_text(&self, out: &mut Write) -> io::Result<()> {
+		writeln!(out, "Datalink: {}", self.datalink)?;
+		writeln!(out, "Platform: {}", self.platform)?;
+		if self.codes.is_empty() {
+			return writeln!(out, "No trouble codes");
+		}
+
+		for code in self.codes.iter() {
+			writeln!(out)?;
+			match code.description {
+				Some(ref description) => writeln!(out, "{} – {}", code.code, description)?,
+				None => writeln!(out, "{}", code.code)?,
+			}
+			writeln!(out, "  State: {}", code.states().join(", "))?;
+			if let Some(failure_type) = code.failure_type {
+				writeln!(out, "  Failure type: 0x{:02X}", failure_type)?;
+			}
+			if let Some(status) = code.status {
+				writeln!(out, "  Status: 0x{:02X} ({})", status, status_names(status).join(", "))?;
+			}
+			if let Some(occurrences) = code.occurrences {
+				writeln!(out, "  Occurrences: {}", occurrences)?;
+			}
+			for frame in code.freeze_frames.iter() {
+				writeln!(out, "  Freeze frame {}:", frame.record)?;
+				for value in frame.values.iter() {
+					let name = match value.name {
+						Some(ref name) => name.clone(),
+						None => format!("0x{:04X}", value.id),
+					};
+					match value.value {
+						Some(number) => writeln!(out, "    {}: {:.2} {}", name, number, value.unit.as_ref().map(String::as_str).unwrap_or(""))?,
+						None => writeln!(out, "    {}: {}", name, value.raw)?,
+					}
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+
+
+/// Sends a request. Returns `None` if the ECU does not support it.
+fn optional_request(interface: &UdsInterface, sid: u8, data: &[u8]) -> Result<Option<Vec<u8>>> {
+	match uds::request(interface, sid, data) {
+		Ok(response) => Ok(Some(response)),
+		Err(Error::NegativeResponse { .. }) => Ok(None),
+		Err(err) => Err(err),
+	}
+}
+
+/// Reads codes through OBD-II mode 03, 07 or 0A
+fn obd_codes(interface: &UdsInterface, sid: u8) -> Result<Vec<u16>> {
+	let response = match optional_request(interface, sid, &[])? {
+		Some(response) => response,
+		None => return Ok(Vec::new()),
+	};
+	// Responses over CAN start with the number of codes
+	let codes = if response.len() % 2 == 1 { &response[1..] } else { &response[..] };
+	Ok(codes.chunks(2)
+		.filter(|code| code.len() == 2)
+		.map(|code| (u16::from(code[0]) << 8) | u16::from(code[1]))
+		.filter(|code| *code != 0)
+		.collect())
+}
+
+fn hex(data: &[u8]) -> String {
+	data.iter().map(|b| format!("{:02X}", b)).collect::<Vec<String>>().join(" ")
+}
+
+/// Decodes a standard PID value
+fn obd_value(pid: u8, data: &[u8]) -> FrameValue {
+	let standard = obd::pid(pid);
+	FrameValue {
+		id: u16::from(pid),
+		name: standard.map(|standard| standard.name.to_owned()),
+		value: standard.and_then(|standard| standard.decode(data)),
+		unit: standard.map(|standard| standard.unit.to_owned()),
+		raw: hex(data),
+	}
+}
+
+/// Reads OBD-II freeze frame 0. Returns the code that stored it and the frame.
+fn obd_freeze_frame(interface: &UdsInterface) -> Result<Option<(u16, FreezeFrame)>> {
+	// PID 02 holds the code that caused the freeze frame
+	let response = match optional_request(interface, 0x02, &[0x02, 0x00])? {
+		Some(response) => response,
+		None => return Ok(None),
+	};
+	if response.len() < 4 || (response[2] == 0 && response[3] == 0) {
+		return Ok(None);
+	}
+	let code = (u16::from(response[2]) << 8) | u16::from(response[3]);
+
+	let mut values = Vec::new();
+	for pid in obd::supported_pids(interface, 0x02)? {
+		if pid == 0x02 {
+			continue;
+		}
+		if let Some(response) = optional_request(interface, 0x02, &[pid, 0x00])? {
+			if response.len() > 2 {
+				values.push(obd_value(pid, &response[2..]));
+			}
+		}
+	}
+	Ok(Some((code, FreezeFrame { record: 0, values })))
+}
+
+/// Parses the records of a UDS snapshot response. Values of identifiers of
+/// unknown size take the rest of the record.
+fn parse_snapshots<F>(mut data: &[u8], pid_name: &F) -> Vec<FreezeFrame>
+where F: Fn(u16) -> Option<String> {
+	let mut frames = Vec::new();
+	while data.len() >= 2 {
+		let record = data[0];
+		let count = data[1] as usize;
+		data = &data[2..];
+
+		let mut values = Vec::new();
+		for _ in 0..count {
+			if data.len() < 2 {
+				break;
+			}
+			let id = (u16::from(data[0]) << 8) | u16::from(data[1]);
+			data = &data[2..];
+
+			let standard = if id & 0xFF00 == OBD_DID_BASE { obd::pid(id as u8) } else { None };
+			match standard {
+				Some(standard) => {
+					let len = standard.bytes.min(data.len());
+					let mut value = obd_value(id as u8, &data[..len]);
+					value.id = id;
+					values.push(value);
+					data = &data[len..];
+				},
+				None => {
+					values.push(FrameValue {
+						id,
+						name: pid_name(id),
+						value: None,
+						unit: None,
+						raw: hex(data),
+					});
+					data = &[];
+				},
+			}
+		}
+		frames.push(FreezeFrame { record, values });
+	}
+	frames
+}
+
+/// Reads UDS codes with their status, occurrence counters and snapshots
+fn uds_codes<F>(interface: &UdsInterface, pid_name: &F) -> Result<Vec<DtcReport>>
+where F: Fn(u16) -> Option<String> {
+	// reportDTCByStatusMask with all status bits
+	let response = match optional_request(interface, 0x19, &[0x02, 0xFF])? {
+		Some(response) => response,
+		None => return Ok(Vec::new()),
+	};
+
+	let mut reports = Vec::new();
+	// Skip the sub-function and status availability mask
+	for record in response.get(2..).unwrap_or(&[]).chunks(4).filter(|record| record.len() == 4) {
+		let number = &record[..3];
+		let mut report = DtcReport::new(format_dtc((u16::from(number[0]) << 8) | u16::from(number[1])));
+		if number[2] != 0 {
+			report.failure_type = Some(number[2]);
+		}
+		report.status = Some(record[3]);
+
+		// reportDTCExtDataRecordByDTCNumber. Record 01 is the occurrence
+		// counter on most ECUs.
+		let mut request = vec![0x06];
+		request.extend_from_slice(number);
+		request.push(0xFF);
+		if let Some(data) = optional_request(interface, 0x19, &request)? {
+			if data.len() >= 7 && data[5] == 0x01 {
+				report.occurrences = Some(u32::from(data[6]));
+			}
+		}
+
+		// reportDTCSnapshotRecordByDTCNumber
+		request[0] = 0x04;
+		if let Some(data) = optional_request(interface, 0x19, &request)? {
+			if data.len() > 5 {
+				report.freeze_frames = parse_snapshots(&data[5..], pid_name);
+			}
+		}
+		reports.push(report);
+	}
+	Ok(reports)
+}
+
+/// Reads all trouble codes with as much detail as the ECU provides.
+/// `pid_name` names manufacturer data identifiers found in snapshots.
+pub fn read_codes<F>(interface: &UdsInterface, pid_name: F) -> Result<Vec<DtcReport>>
+where F: Fn(u16) -> Option<String> {
+	let mut reports = uds_codes(interface, &pid_name)?;
+
+	// Merge the OBD-II view of the same codes. OBD-II has no failure type,
+	// so a code marks every UDS code with the same base code.
+	let modes: [(u8, fn(&mut DtcReport)); 3] = [
+		(0x03, |report| report.stored = true),
+		(0x07, |report| report.pending = true),
+		(0x0A, |report| report.permanent = true),
+	];
+	for &(sid, mark) in modes.iter() {
+		for code in obd_codes(interface, sid)? {
+			let code = format_dtc(code);
+			let mut found = false;
+			for report in reports.iter_mut().filter(|report| report.code == code) {
+				mark(report);
+				found = true;
+			}
+			if !found {
+				let mut report = DtcReport::new(code);
+				mark(&mut report);
+				reports.push(report);
+			}
+		}
+	}
+
+	if let Some((code, frame)) = obd_freeze_frame(interface)? {
+		let code = format_dtc(code);
+		if let Some(report) = reports.iter_mut().find(|report| report.code == code && report.freeze_frames.is_empty()) {
+			report.freeze_frames.push(frame);
+		}
+	}
+	Ok(reports)
+}
@@ -0,0 +1,228 @@
+//! Trouble code descriptions. SAE J2012 generic descriptions are built in.
+//! Platforms can add or override descriptions with a `dtc.toml` file in
+//! their definition directory:
+//!
+//! ```toml
+//! P1234 = "Fuel pump module circuit"
+//! ```
+
+use std::{
+	collections::HashMap,
+	fs,
+	path::{Component, Path},
+};
+
+use tuneutils::definition::Definitions;
+
+use crate::error::Result;
+
+
+
+/// SAE J2012 generic powertrain and network codes
+const GENERIC: &[(&str, &str)] = &[
+	("P0010", "Intake camshaft position actuator circuit bank 1"),
+	("P0011", "Intake camshaft position timing over-advanced or system performance bank 1"),
+	("P0012", "Intake camshaft position timing over-retarded bank 1"),
+	("P0013", "Exhaust camshaft position actuator circuit bank 1"),
+	("P0014", "Exhaust camshaft position timing over-advanced or system performance bank 1"),
+	("P0016", "Crankshaft position - camshaft position correlation bank 1 sensor A"),
+	("P0017", "Crankshaft position - camshaft position correlation bank 1 sensor B"),
+	("P0030", "HO2S heater control circuit bank 1 sensor 1"),
+	("P0036", "HO2S heater control circuit bank 1 sensor 2"),
+	("P0068", "MAP/MAF - throttle position correlation"),
+	("P0087", "Fuel rail/system pressure too low"),
+	("P0088", "Fuel rail/system pressure too high"),
+	("P0100", "Mass or volume air flow circuit"),
+	("P0101", "Mass or volume air flow circuit range/performance"),
+	("P0102", "Mass or volume air flow circuit low input"),
+	("P0103", "Mass or volume air flow circuit high input"),
+	("P0105", "Manifold absolute pressure/barometric pressure circuit"),
+	("P0106", "Manifold absolute pressure/barometric pressure circuit range/performance"),
+	("P0107", "Manifold absolute pressure/barometric pressure circuit low input"),
+	("P0108", "Manifold absolute pressure/barometric pressure circuit high input"),
+	("P0110", "Intake air temperature circuit"),
+	("P0111", "Intake air temperature circuit range/performance"),
+	("P0112", "Intake air temperature circuit low input"),
+	("P0113", "Intake air temperature circuit high input"),
+	("P0115", "Engine coolant temperature circuit"),
+	("P0116", "Engine coolant temperature circuit range/performance"),
+	("P0117", "Engine coolant temperature circuit low input"),
+	("P0118", "Engine coolant temperature circuit high input"),
+	("P0120", "Throttle/pedal position sensor/switch A circuit"),
+	("P0121", "Throttle/pedal position sensor/switch A circuit range/performance"),
+	("P0122", "Throttle/pedal position sensor/switch A circuit low input"),
+	("P0123", "Throttle/pedal position sensor/switch A circuit high input"),
+	("P0125", "Insufficient coolant temperature for closed loop fuel control"),
+	("P0128", "Coolant thermostat (coolant temperature below thermostat regulating temperature)"),
+	("P0130", "O2 sensor circuit bank 1 sensor 1"),
+	("P0131", "O2 sensor circuit low voltage bank 1 sensor 1"),
+	("P0132", "O2 sensor circuit high voltage bank 1 sensor 1"),
+	("P0133", "O2 sensor circuit slow response bank 1 sensor 1"),
+	("P0134", "O2 sensor circuit no activity detected bank 1 sensor 1"),
+	("P0135", "O2 sensor heater circuit bank 1 sensor 1"),
+	("P0136", "O2 sensor circuit bank 1 sensor 2"),
+	("P0137", "O2 sensor circuit low voltage bank 1 sensor 2"),
+	("P0138", "O2 sensor circuit high voltage bank 1 sensor 2"),
+	("P0140", "O2 sensor circuit no activity detected bank 1 sensor 2"),
+	("P0141", "O2 sensor heater circuit bank 1 sensor 2"),
+	("P0150", "O2 sensor circuit bank 2 sensor 1"),
+	("P0151", "O2 sensor circuit low voltage bank 2 sensor 1"),
+	("P0152", "O2 sensor circuit high voltage bank 2 sensor 1"),
+	("P0155", "O2 sensor heater circuit bank 2 sensor 1"),
+	("P0171", "System too lean bank 1"),
+	("P0172", "System too rich bank 1"),
+	("P0174", "System too lean bank 2"),
+	("P0175", "System too rich bank 2"),
+	("P0191", "Fuel rail pressure sensor A circuit range/performance"),
+	("P0192", "Fuel rail pressure sensor A circuit low"),
+	("P0193", "Fuel rail pressure sensor A circuit high"),
+	("P0201", "Injector circuit/open cylinder 1"),
+	("P0202", "Injector circuit/open cylinder 2"),
+	("P0203", "Injector circuit/open cylinder 3"),
+	("P0204", "Injector circuit/open cylinder 4"),
+	("P0205", "Injector circuit/open cylinder 5"),
+	("P0206", "Injector circuit/open cylinder 6"),
+	("P0220", "Throttle/pedal position sensor/switch B circuit"),
+	("P0222", "Throttle/pedal position sensor/switch B circuit low"),
+	("P0223", "Throttle/pedal position sensor/switch B circuit high"),
+	("P0234", "Turbocharger/supercharger A overboost condition"),
+	("P0235", "Turbocharger/supercharger boost sensor A circuit"),
+	("P0299", "Turbocharger/supercharger A underboost condition"),
+	("P0300", "Random/multiple cylinder misfire detected"),
+	("P0301", "Cylinder 1 misfire detected"),
+	("P0302", "Cylinder 2 misfire detected"),
+	("P0303", "Cylinder 3 misfire detected"),
+	("P0304", "Cylinder 4 misfire detected"),
+	("P0305", "Cylinder 5 misfire detected"),
+	("P0306", "Cylinder 6 misfire detected"),
+	("P0307", "Cylinder 7 misfire detected"),
+	("P0308", "Cylinder 8 misfire detected"),
+	("P0325", "Knock sensor 1 circuit bank 1 or single sensor"),
+	("P0327", "Knock sensor 1 circuit low bank 1 or single sensor"),
+	("P0328", "Knock sensor 1 circuit high bank 1 or single sensor"),
+	("P0335", "Crankshaft position sensor A circuit"),
+	("P0336", "Crankshaft position sensor A circuit range/performance"),
+	("P0340", "Camshaft position sensor A circuit bank 1 or single sensor"),
+	("P0341", "Camshaft position sensor A circuit range/performance bank 1 or single sensor"),
+	("P0351", "Ignition coil A primary/secondary circuit"),
+	("P0352", "Ignition coil B primary/secondary circuit"),
+	("P0353", "Ignition coil C primary/secondary circuit"),
+	("P0354", "Ignition coil D primary/secondary circuit"),
+	("P0400", "Exhaust gas recirculation flow"),
+	("P0401", "Exhaust gas recirculation flow insufficient detected"),
+	("P0402", "Exhaust gas recirculation flow excessive detected"),
+	("P0403", "Exhaust gas recirculation control circuit"),
+	("P0410", "Secondary air injection system"),
+	("P0420", "Catalyst system efficiency below threshold bank 1"),
+	("P0421", "Warm up catalyst efficiency below threshold bank 1"),
+	("P0430", "Catalyst system efficiency below threshold bank 2"),
+	("P0440", "Evaporative emission system"),
+	("P0441", "Evaporative emission system incorrect purge flow"),
+	("P0442", "Evaporative emission system leak detected (small leak)"),
+	("P0443", "Evaporative emission system purge control valve circuit"),
+	("P0446", "Evaporative emission system vent control circuit"),
+	("P0449", "Evaporative emission system vent valve/solenoid circuit"),
+	("P0455", "Evaporative emission system leak detected (large leak)"),
+	("P0456", "Evaporative emission system leak detected (very small leak)"),
+	("P0461", "Fuel level sensor A circuit range/performance"),
+	("P0480", "Fan 1 control circuit"),
+	("P0500", "Vehicle speed sensor A"),
+	("P0505", "Idle air control system"),
+	("P0506", "Idle air control system RPM lower than expected"),
+	("P0507", "Idle air control system RPM higher than expected"),
+	("P0562", "System voltage low"),
+	("P0563", "System voltage high"),
+	("P0571", "Brake switch A circuit"),
+	("P0600", "Serial communication link"),
+	("P0601", "Internal control module memory checksum error"),
+	("P0602", "Control module programming error"),
+	("P0603", "Internal control module keep alive memory (KAM) error"),
+	("P0604", "Internal control module random access memory (RAM) error"),
+	("P0605", "Internal control module read only memory (ROM) error"),
+	("P0606", "Control module processor"),
+	("P0607", "Control module performance"),
+	("P0620", "Generator control circuit"),
+	("P0625", "Generator field terminal circuit low"),
+	("P0641", "Sensor reference voltage A circuit/open"),
+	("P0700", "Transmission control system (MIL request)"),
+	("P0705", "Transmission range sensor A circuit (PRNDL input)"),
+	("P0715", "Input/turbine speed sensor A circuit"),
+	("P0720", "Output speed sensor circuit"),
+	("P0730", "Incorrect gear ratio"),
+	("P0740", "Torque converter clutch solenoid circuit/open"),
+	("P0750", "Shift solenoid A"),
+	("P0755", "Shift solenoid B"),
+	("P2096", "Post catalyst fuel trim system too lean bank 1"),
+	("P2097", "Post catalyst fuel trim system too rich bank 1"),
+	("P2101", "Throttle actuator control motor circuit range/performance"),
+	("P2135", "Throttle/pedal position sensor/switch A / B voltage correlation"),
+	("P2138", "Throttle/pedal position sensor/switch D / E voltage correlation"),
+	("P2187", "System too lean at idle bank 1"),
+	("P2188", "System too rich at idle bank 1"),
+	("P2195", "O2 sensor signal biased/stuck lean bank 1 sensor 1"),
+	("P2196", "O2 sensor signal biased/stuck rich bank 1 sensor 1"),
+	("P2270", "O2 sensor signal biased/stuck lean bank 1 sensor 2"),
+	("P2271", "O2 sensor signal biased/stuck rich bank 1 sensor 2"),
+	("U0001", "High speed CAN communication bus"),
+	("U0073", "Control module communication bus A off"),
+	("U0100", "Lost communication with ECM/PCM A"),
+	("U0101", "Lost communication with TCM"),
+	("U0121", "Lost communication with anti-lock brake system (ABS) control module"),
+	("U0140", "Lost communication with body control module"),
+	("U0155", "Lost communication with instrument panel cluster (IPC) control module"),
+];
+
+
+
+/// Trouble code descriptions for a platform
+#[derive(Debug, Default)]
+pub struct Descriptions {
+	overrides: HashMap<String, String>,
+}
+
+impl Descriptions {
+	/// Loads the overrides of a platform from
+	/// `definitions/<platform>/dtc.toml`. Only the generic descriptions are
+	/// used if the platform is not installed or the file does not exist.
+	pub fn load(definitions_dir: &Path, definitions: &Definitions, platform: &str) -> Result<Descriptions> {
+		// The directory is named by the installed definition, never by the
+		// argument, and must be a single directory name
+		let id = match definitions.find(platform) {
+			Some(definition) => definition.id.as_str(),
+			None => return Ok(Descriptions::default()),
+		};
+		let mut components = Path::new(id).components();
+		match (components.next(), components.next()) {
+			(Some(Component::Normal(_)), None) => (),
+			_ => return Ok(Descriptions::default()),
+		}
+
+		let path = definitions_dir.join(id).join("dtc.toml");
+		if !path.exists() {
+			return Ok(Descriptions::default());
+		}
+		let contents = fs::read_to_string(&path)?;
+		let overrides: HashMap<String, String> = toml::from_str(&contents)?;
+		Ok(Descriptions {
+			overrides: overrides.into_iter().map(|(code, description)| (code.to_uppercase(), description)).collect(),
+		})
+	}
+
+	/// Returns the description of a code such as "P0301". A failure type
+	/// suffix, as in "P0301-1A", is ignored.
+	pub fn describe(&self, code: &str) -> Option<&str> {
+		let code = code.split('-').next().unwrap_or(code).to_uppercase();
+		match self.overrides.get(&code) {
+			Some(description) => Some(description.as_str()),
+			None => GENERIC.iter().find(|&&(generic, _)| generic == code).map(|&(_, description)| description),
+		}
+	}
+
+	/// Formats a code with its description, e.g. "P0301 – Cylinder 1 misfire detected"
+	pub fn label(&self, code: &str) -> String {
+		match self.describe(code) {
+			Some(description) => format!("{} – {}", code, description),
+			None => code.to_owned(),
+		}
+	}
+}
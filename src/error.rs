@@ -1,27 +1,81 @@
 use tuneutils;
 use std::{result, io, fmt};
 
+use crate::uds;
+
 #[derive(Debug)]
 pub enum Error {
 	TuneUtils(tuneutils::error::Error),
+	/// The ECU answered a UDS request with a negative response code. The
+	/// service ID is unknown if the request was made inside tuneutils.
+	NegativeResponse { sid: Option<u8>, nrc: u8 },
 	Io(io::Error),
+	Toml(toml::de::Error),
+	TomlSerialize(toml::ser::Error),
 	NoHome,
 	#[cfg(feature = "cli")]
 	InvalidCommand,
 	#[cfg(feature = "cli")]
 	Clap(clap::Error),
+	#[cfg(feature = "cli")]
+	InvalidSyntax,
+	#[cfg(feature = "cli")]
+	RecursiveScript(std::path::PathBuf),
+	#[cfg(feature = "cli")]
+	Script { path: std::path::PathBuf, line: usize, err: Box<Error> },
+	#[cfg(feature = "cli")]
+	ScriptFailed(usize),
+	#[cfg(feature = "cli")]
+	InvalidFormat(String),
+	#[cfg(feature = "cli")]
+	NoDefault(&'static str),
 	InvalidPlatform,
 	UnknownModel,
 	InvalidDatalink,
 	DownloadUnsupported,
 	InvalidRom,
+	UnsupportedLink(&'static str),
+	InvalidLinkName(String),
+	DuplicateLink(String),
+	DiscoveredLink(String),
+	InvalidBitrate(u32),
+	InvalidTrace(usize),
+	CanUnsupported,
+	CodesNotCleared(usize),
+	InvalidResponse,
+	InvalidExpression(String, String),
+	InvalidPid(String),
+	/// A math channel uses a math channel defined after it, or itself
+	MathOrder { channel: String, name: String },
+	LogUnsupported,
+	#[cfg(feature = "cli")]
+	InvalidFilter(String),
+	#[cfg(feature = "cli")]
+	InvalidHex(String),
+	#[cfg(feature = "cli")]
+	InvalidLog(usize),
+	#[cfg(feature = "cli")]
+	InvalidMdf(&'static str),
+	#[cfg(feature = "cli")]
+	NoLog,
+	#[cfg(feature = "cli")]
+	InvalidLogName(String),
+	#[cfg(feature = "cli")]
+	LogExists(String),
+	#[cfg(feature = "cli")]
+	InvalidChannel(String),
+	#[cfg(feature = "cli")]
+	UnsupportedImage(String),
 }
 
 pub type Result<T> = result::Result<T, Error>;
 
 impl From<tuneutils::error::Error> for Error {
 	fn from(err: tuneutils::error::Error) -> Error {
-		Error::TuneUtils(err)
+		match err {
+			tuneutils::error::Error::NegativeResponse(nrc) => Error::NegativeResponse { sid: None, nrc },
+			err => Error::TuneUtils(err),
+		}
 	}
 }
 
@@ -31,6 +85,18 @@ impl From<io::Error> for Error {
 	}
 }
 
+impl From<toml::de::Error> for Error {
+	fn from(err: toml::de::Error) -> Error {
+		Error::Toml(err)
+	}
+}
+
+impl From<toml::ser::Error> for Error {
+	fn from(err: toml::ser::Error) -> Error {
+		Error::TomlSerialize(err)
+	}
+}
+
 #[cfg(feature = "cli")]
 impl From<clap::Error> for Error {
 	fn from(err: clap::Error) -> Error {
@@ -42,17 +108,64 @@ impl fmt::Display for Error {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match *self {
 			Error::TuneUtils(ref err) => write!(f, "TuneUtils error: {}", err),
+			Error::NegativeResponse { sid: Some(sid), nrc } => write!(f, "Negative response to {} (0x{:02X}): {} (0x{:02X})", uds::service_name(sid), sid, uds::nrc_name(nrc), nrc),
+			Error::NegativeResponse { sid: None, nrc } => write!(f, "Negative response: {} (0x{:02X})", uds::nrc_name(nrc), nrc),
 			Error::NoHome => write!(f, "No valid home directory path could be retrieved from the operating system"),
 			Error::Io(ref err) => write!(f, "IO error: {}", err),
+			Error::Toml(ref err) => write!(f, "Configuration error: {}", err),
+			Error::TomlSerialize(ref err) => write!(f, "Failed to serialize configuration: {}", err),
 			#[cfg(feature = "cli")]
 			Error::InvalidCommand => write!(f, "Invalid command"),
 			#[cfg(feature = "cli")]
 			Error::Clap(ref err) => write!(f, "{}", err),
+			#[cfg(feature = "cli")]
+			Error::InvalidSyntax => write!(f, "Invalid syntax"),
+			#[cfg(feature = "cli")]
+			Error::RecursiveScript(ref path) => write!(f, "Script \"{}\" is already running and cannot be sourced again", path.display()),
+			#[cfg(feature = "cli")]
+			Error::Script { ref path, line, ref err } => write!(f, "{}:{}: {}", path.display(), line, err),
+			#[cfg(feature = "cli")]
+			Error::ScriptFailed(count) => write!(f, "{} command(s) in the script failed", count),
+			#[cfg(feature = "cli")]
+			Error::NoDefault(name) => write!(f, "No {0} given and no default {0} is configured", name),
+			#[cfg(feature = "cli")]
+			Error::InvalidFormat(ref format) => write!(f, "Invalid output format \"{}\". Expected text, json or csv", format),
 			Error::InvalidPlatform => write!(f, "Invalid platform"),
 			Error::InvalidDatalink => write!(f, "Invalid datalink"),
 			Error::DownloadUnsupported => write!(f, "Downloading unsupported for a datalink or platform"),
 			Error::UnknownModel => write!(f, "Unknown model"),
 			Error::InvalidRom => write!(f, "Invalid ROM"),
+			Error::UnsupportedLink(typename) => write!(f, "Datalink type \"{}\" is not supported by this build", typename),
+			Error::InvalidLinkName(ref name) => write!(f, "Invalid datalink name \"{}\". Names may contain letters, digits, '_', '-', '.' and ':'", name),
+			Error::DuplicateLink(ref name) => write!(f, "A datalink named \"{}\" already exists", name),
+			Error::DiscoveredLink(ref name) => write!(f, "Datalink \"{}\" was discovered automatically and cannot be removed", name),
+			Error::InvalidBitrate(bitrate) => write!(f, "Unsupported CAN bitrate {}", bitrate),
+			Error::InvalidTrace(line) => write!(f, "Invalid CAN trace at line {}", line),
+			Error::CanUnsupported => write!(f, "The datalink does not provide raw CAN access"),
+			Error::CodesNotCleared(count) => write!(f, "{} trouble code(s) did not clear", count),
+			Error::InvalidResponse => write!(f, "The ECU sent a malformed response"),
+			Error::InvalidExpression(ref expression, ref reason) => write!(f, "Invalid expression \"{}\": {}", expression, reason),
+			Error::InvalidPid(ref pid) => write!(f, "Unknown PID \"{}\". See 'pids' for a list", pid),
+			Error::MathOrder { ref channel, ref name } => write!(f, "Math channel \"{}\" uses \"{}\". A math channel must be defined before use", channel, name),
+			Error::LogUnsupported => write!(f, "Datalogging is unsupported on this platform or datalink"),
+			#[cfg(feature = "cli")]
+			Error::InvalidFilter(ref filter) => write!(f, "Invalid filter \"{}\". Expected id, id:mask or id~mask in hex", filter),
+			#[cfg(feature = "cli")]
+			Error::InvalidHex(ref hex) => write!(f, "Invalid hex \"{}\"", hex),
+			#[cfg(feature = "cli")]
+			Error::InvalidLog(line) => write!(f, "Invalid datalog at line {}", line),
+			#[cfg(feature = "cli")]
+			Error::InvalidMdf(reason) => write!(f, "Invalid MDF file: {}", reason),
+			#[cfg(feature = "cli")]
+			Error::NoLog => write!(f, "No log is open. Open one with 'log_open'"),
+			#[cfg(feature = "cli")]
+			Error::InvalidLogName(ref name) => write!(f, "Invalid log name \"{}\". Names cannot be paths", name),
+			#[cfg(feature = "cli")]
+			Error::LogExists(ref name) => write!(f, "A log named \"{}\" already exists", name),
+			#[cfg(feature = "cli")]
+			Error::InvalidChannel(ref name) => write!(f, "The log has no PID named \"{}\"", name),
+			#[cfg(feature = "cli")]
+			Error::UnsupportedImage(ref path) => write!(f, "Cannot plot to \"{}\". Images must end in .svg or .png", path),
 		}
 	}
 }
\ No newline at end of file
@@ -0,0 +1,359 @@
+//! Arithmetic expressions over named values, e.g. "(a * 256 + b) / 4" or
+//! "[Engine speed] > 3000 && throttle > 80". Names containing spaces are
+//! written in brackets. Comparisons and logical operators give 1 or 0.
+
+use std::{
+	iter::Peekable,
+	str::Chars,
+};
+
+use crate::error::{Error, Result};
+
+
+
+/// Functions that can be called from expressions, with their argument counts
+const FUNCTIONS: &[(&str, usize)] = &[
+	("abs", 1),
+	("sqrt", 1),
+	("round", 1),
+	("floor", 1),
+	("ceil", 1),
+	("min", 2),
+	("max", 2),
+	("if", 3),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+	Neg,
+	Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+	Add,
+	Sub,
+	Mul,
+	Div,
+	Rem,
+	Pow,
+	Lt,
+	Le,
+	Gt,
+	Ge,
+	Eq,
+	Ne,
+	And,
+	Or,
+}
+
+/// A parsed expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+	Number(f64),
+	Variable(String),
+	Unary(UnaryOp, Box<Expr>),
+	Binary(BinaryOp, Box<Expr>, Box<Expr>),
+	Call(String, Vec<Expr>),
+}
+
+impl Expr {
+	pub fn parse(source: &str) -> Result<Expr> {
+		let tokens = tokenize(source).map_err(|reason| invalid(source, reason))?;
+		let mut parser = Parser { tokens: &tokens, position: 0 };
+		let expr = parser.or().map_err(|reason| invalid(source, reason))?;
+		if parser.position != tokens.len() {
+			return Err(invalid(source, "unexpected input after the expression".to_owned()));
+		}
+		Ok(expr)
+	}
+
+	/// Evaluates the expression. Returns `None` if `lookup` does not know a
+	/// variable.
+	pub fn eval<F>(&self, lookup: &F) -> Option<f64>
+	where F: Fn(&str) -> Option<f64> {
+		Some(match *self {
+			Expr::Number(value) => value,
+			Expr::Variable(ref name) => lookup(name)?,
+			Expr::Unary(op, ref operand) => {
+				let value = operand.eval(lookup)?;
+				match op {
+					UnaryOp::Neg => -value,
+					UnaryOp::Not => truth(value == 0.0),
+				}
+			},
+			Expr::Binary(op, ref left, ref right) => {
+				let left = left.eval(lookup)?;
+				let right = right.eval(lookup)?;
+				match op {
+					BinaryOp::Add => left + right,
+					BinaryOp::Sub => left - right,
+					BinaryOp::Mul => left * right,
+					BinaryOp::Div => left / right,
+					BinaryOp::Rem => left % right,
+					BinaryOp::Pow => left.powf(right),
+					BinaryOp::Lt => truth(left < right),
+					BinaryOp::Le => truth(left <= right),
+					BinaryOp::Gt => truth(left > right),
+					BinaryOp::Ge => truth(left >= right),
+					BinaryOp::Eq => truth(left == right),
+					BinaryOp::Ne => truth(left != right),
+					BinaryOp::And => truth(left != 0.0 && right != 0.0),
+					BinaryOp::Or => truth(left != 0.0 || right != 0.0),
+				}
+			},
+			Expr::Call(ref name, ref args) => {
+				let args = args.iter().map(|arg| arg.eval(lookup)).collect::<Option<Vec<f64>>>()?;
+				match name.as_str() {
+					"abs" => args[0].abs(),
+					"sqrt" => args[0].sqrt(),
+					"round" => args[0].round(),
+					"floor" => args[0].floor(),
+					"ceil" => args[0].ceil(),
+					"min" => args[0].min(args[1]),
+					"max" => args[0].max(args[1]),
+					"if" => if args[0] != 0.0 { args[1] } else { args[2] },
+					// Unknown functions are rejected by the parser
+					_ => return None,
+				}
+			},
+		})
+	}
+
+	/// Returns the names of the variables used by the expression
+	pub fn variables(&self) -> Vec<&str> {
+		let mut variables = Vec::new();
+		self.collect_variables(&mut variables);
+		variables
+	}
+
+	fn collect_variables<'a>(&'a self, variables: &mut Vec<&'a str>) {
+		match *self {
+			Expr::Number(_) => {},
+			Expr::Variable(ref name) => {
+				if !variables.contains(&name.as_str()) {
+					variables.push(name);
+				}
+			},
+			Expr::Unary(_, ref operand) => operand.collect_variables(variables),
+			Expr::Binary(_, ref left, ref right) => {
+				left.collect_variables(variables);
+				right.collect_variables(variables);
+			},
+			Expr::Call(_, ref args) => {
+				for arg in args.iter() {
+					arg.collect_variables(variables);
+				}
+			},
+		}
+	}
+}
+
+fn truth(value: bool) -> f64 {
+	if value { 1.0 } else { 0.0 }
+}
+
+fn invalid(source: &str, reason: String) -> Error {
+	Error::InvalidExpression(source.to_owned(), reason)
+}
+
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+	Number(f64),
+	Name(String),
+	/// Operator or punctuation
+	Symbol(&'static str),
+}
+
+const SYMBOLS: &[&str] = &["<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "^", "<", ">", "!", "(", ")", ","];
+
+fn tokenize(source: &str) -> ::std::result::Result<Vec<Token>, String> {
+	let mut tokens = Vec::new();
+	let mut chars: Peekable<Chars> = source.chars().peekable();
+
+	while let Some(&c) = chars.peek() {
+		if c.is_whitespace() {
+			chars.next();
+		} else if c.is_ascii_digit() || c == '.' {
+			let mut number = String::new();
+			while let Some(&c) = chars.peek() {
+				if c.is_ascii_alphanumeric() || c == '.' {
+					number.push(c);
+					chars.next();
+				} else {
+					break;
+				}
+			}
+			let value = if number.starts_with("0x") || number.starts_with("0X") {
+				u64::from_str_radix(&number[2..], 16).ok().map(|value| value as f64)
+			} else {
+				number.parse::<f64>().ok()
+			};
+			tokens.push(Token::Number(value.ok_or_else(|| format!("invalid number \"{}\"", number))?));
+		} else if c.is_alphabetic() || c == '_' {
+			let mut name = String::new();
+			while let Some(&c) = chars.peek() {
+				if c.is_alphanumeric() || c == '_' || c == '.' {
+					name.push(c);
+					chars.next();
+				} else {
+					break;
+				}
+			}
+			tokens.push(Token::Name(name));
+		} else if c == '[' {
+			chars.next();
+			let mut name = String::new();
+			loop {
+				match chars.next() {
+					Some(']') => break,
+					Some(c) => name.push(c),
+					None => return Err("unterminated '['".to_owned()),
+				}
+			}
+			if name.trim().is_empty() {
+				return Err("empty name in brackets".to_owned());
+			}
+			tokens.push(Token::Name(name.trim().to_owned()));
+		} else {
+			let rest: String = chars.clone().take(2).collect();
+			let symbol = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol))
+				.ok_or_else(|| format!("unexpected character '{}'", c))?;
+			for _ in 0..symbol.len() {
+				chars.next();
+			}
+			tokens.push(Token::Symbol(*symbol));
+		}
+	}
+	Ok(tokens)
+}
+
+
+
+/// Recursive descent parser. From lowest to highest precedence:
+/// ||, &&, comparisons, + -, * / %, unary - !, ^ (right associative)
+struct Parser<'a> {
+	tokens: &'a [Token],
+	position: usize,
+}
+
+type ParseResult = ::std::result::Result<Expr, String>;
+
+impl<'a> Parser<'a> {
+	fn peek_symbol(&self) -> Option<&'static str> {
+		match self.tokens.get(self.position) {
+			Some(&Token::Symbol(symbol)) => Some(symbol),
+			_ => None,
+		}
+	}
+
+	/// Consumes one of `symbols` if it is next
+	fn accept(&mut self, symbols: &[&'static str]) -> Option<&'static str> {
+		let symbol = self.peek_symbol().filter(|symbol| symbols.contains(symbol))?;
+		self.position += 1;
+		Some(symbol)
+	}
+
+	fn expect(&mut self, symbol: &'static str) -> ::std::result::Result<(), String> {
+		self.accept(&[symbol]).map(|_| ()).ok_or_else(|| format!("expected '{}'", symbol))
+	}
+
+	fn binary<F>(&mut self, symbols: &[&'static str], next: F) -> ParseResult
+	where F: Fn(&mut Parser<'a>) -> ParseResult {
+		let mut left = next(self)?;
+		while let Some(symbol) = self.accept(symbols) {
+			let right = next(self)?;
+			let op = match symbol {
+				"||" => BinaryOp::Or,
+				"&&" => BinaryOp::And,
+				"<" => BinaryOp::Lt,
+				"<=" => BinaryOp::Le,
+				">" => BinaryOp::Gt,
+				">=" => BinaryOp::Ge,
+				"==" => BinaryOp::Eq,
+				"!=" => BinaryOp::Ne,
+				"+" => BinaryOp::Add,
+				"-" => BinaryOp::Sub,
+				"*" => BinaryOp::Mul,
+				"/" => BinaryOp::Div,
+				_ => BinaryOp::Rem,
+			};
+			left = Expr::Binary(op, Box::new(left), Box::new(right));
+		}
+		Ok(left)
+	}
+
+	fn or(&mut self) -> ParseResult {
+		self.binary(&["||"], Parser::and)
+	}
+
+	fn and(&mut self) -> ParseResult {
+		self.binary(&["&&"], Parser::comparison)
+	}
+
+	fn comparison(&mut self) -> ParseResult {
+		self.binary(&["<", "<=", ">", ">=", "==", "!="], Parser::sum)
+	}
+
+	fn sum(&mut self) -> ParseResult {
+		self.binary(&["+", "-"], Parser::product)
+	}
+
+	fn product(&mut self) -> ParseResult {
+		self.binary(&["*", "/", "%"], Parser::unary)
+	}
+
+	fn unary(&mut self) -> ParseResult {
+		match self.accept(&["-", "!"]) {
+			Some("-") => Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.unary()?))),
+			Some(_) => Ok(Expr::Unary(UnaryOp::Not, Box::new(self.unary()?))),
+			None => self.power(),
+		}
+	}
+
+	fn power(&mut self) -> ParseResult {
+		let base = self.primary()?;
+		if self.accept(&["^"]).is_some() {
+			// Right associative, and allows a signed exponent as in 2^-1
+			let exponent = self.unary()?;
+			return Ok(Expr::Binary(BinaryOp::Pow, Box::new(base), Box::new(exponent)));
+		}
+		Ok(base)
+	}
+
+	fn primary(&mut self) -> ParseResult {
+		let token = self.tokens.get(self.position).cloned().ok_or_else(|| "unexpected end of expression".to_owned())?;
+		self.position += 1;
+		match token {
+			Token::Number(value) => Ok(Expr::Number(value)),
+			Token::Name(name) => {
+				if self.accept(&["("]).is_none() {
+					return Ok(Expr::Variable(name));
+				}
+				let mut args = Vec::new();
+				if self.accept(&[")"]).is_none() {
+					loop {
+						args.push(self.or()?);
+						if self.accept(&[","]).is_none() {
+							break;
+						}
+					}
+					self.expect(")")?;
+				}
+				match FUNCTIONS.iter().find(|&&(function, _)| function == name) {
+					Some(&(_, count)) if count == args.len() => Ok(Expr::Call(name, args)),
+					Some(&(_, count)) => Err(format!("{}() takes {} argument(s)", name, count)),
+					None => Err(format!("unknown function \"{}\"", name)),
+				}
+			},
+			Token::Symbol("(") => {
+				let expr = self.or()?;
+				self.expect(")")?;
+				Ok(expr)
+			},
+			Token::Symbol(symbol) => Err(format!("unexpected '{}'", symbol)),
+		}
+	}
+}
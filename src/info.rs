@@ -0,0 +1,150 @@
+//! Vehicle and ECU identification read through OBD-II mode 09 and UDS
+//! ReadDataByIdentifier (0x22)
+
+use std::{
+	fs,
+	path::Path,
+};
+
+use serde::{Serialize, Deserialize};
+
+use tuneutils::{
+	error::Error as TuneError,
+	protocols::uds::UdsInterface,
+};
+
+use crate::{
+	error::{Error, Result},
+	uds,
+};
+
+
+
+/// Identification data of a vehicle and its ECU. Fields the ECU does not
+/// report are left empty.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EcuInfo {
+	pub vin: Option<String>,
+	/// Calibration IDs (mode 09 PID 04)
+	pub calibration_ids: Vec<String>,
+	/// Calibration verification numbers in hex (mode 09 PID 06)
+	pub cvns: Vec<String>,
+	/// ECU name (mode 09 PID 0A)
+	pub ecu_name: Option<String>,
+	/// ECU serial number (DID F18C)
+	pub serial: Option<String>,
+	/// Manufacturer spare part number (DID F187)
+	pub part_number: Option<String>,
+	/// Manufacturer ECU software number (DID F188)
+	pub software_number: Option<String>,
+	/// Manufacturer ECU hardware number (DID F191)
+	pub hardware_number: Option<String>,
+	/// Supplier ECU software number (DID F194)
+	pub supplier_software_number: Option<String>,
+	/// Supplier ECU software version (DID F195)
+	pub supplier_software_version: Option<String>,
+}
+
+impl EcuInfo {
+	/// Reads everything the ECU reports. Fields the ECU rejects or does not
+	/// answer in time are left empty.
+	pub fn read(interface: &UdsInterface) -> Result<EcuInfo> {
+		let mut info = EcuInfo::default();
+
+		info.vin = vehicle_info(interface, 0x02)?.map(|data| text(&data));
+		if info.vin.is_none() {
+			info.vin = data_identifier(interface, 0xF190)?.map(|data| text(&data));
+		}
+		if let Some(data) = vehicle_info(interface, 0x04)? {
+			info.calibration_ids = data.chunks(16).map(text).filter(|id| !id.is_empty()).collect();
+		}
+		if let Some(data) = vehicle_info(interface, 0x06)? {
+			info.cvns = data.chunks(4).filter(|cvn| cvn.len() == 4)
+				.map(|cvn| cvn.iter().map(|b| format!("{:02X}", b)).collect())
+				.collect();
+		}
+		info.ecu_name = vehicle_info(interface, 0x0A)?.map(|data| text(&data));
+
+		info.serial = data_identifier(interface, 0xF18C)?.map(|data| text(&data));
+		info.part_number = data_identifier(interface, 0xF187)?.map(|data| text(&data));
+		info.software_number = data_identifier(interface, 0xF188)?.map(|data| text(&data));
+		info.hardware_number = data_identifier(interface, 0xF191)?.map(|data| text(&data));
+		info.supplier_software_number = data_identifier(interface, 0xF194)?.map(|data| text(&data));
+		info.supplier_software_version = data_identifier(interface, 0xF195)?.map(|data| text(&data));
+		Ok(info)
+	}
+
+	/// Returns (name, value) pairs of the fields that were read
+	pub fn fields(&self) -> Vec<(&'static str, String)> {
+		let mut fields = Vec::new();
+		let single = [
+			("VIN", &self.vin),
+			("ECU name", &self.ecu_name),
+			("Serial number", &self.serial),
+			("Part number", &self.part_number),
+			("Software number", &self.software_number),
+			("Hardware number", &self.hardware_number),
+			("Supplier software number", &self.supplier_software_number),
+			("Supplier software version", &self.supplier_software_version),
+		];
+		for &(name, value) in single.iter() {
+			if let Some(ref value) = *value {
+				fields.push((name, value.clone()));
+			}
+		}
+		for id in self.calibration_ids.iter() {
+			fields.push(("Calibration ID", id.clone()));
+		}
+		for cvn in self.cvns.iter() {
+			fields.push(("CVN", cvn.clone()));
+		}
+		fields
+	}
+
+	/// Loads identification saved with `save`
+	pub fn load(path: &Path) -> Result<EcuInfo> {
+		let contents = fs::read_to_string(path)?;
+		Ok(toml::from_str(&contents)?)
+	}
+
+	/// Saves identification as TOML
+	pub fn save(&self, path: &Path) -> Result<()> {
+		fs::write(path, toml::to_string(self)?)?;
+		Ok(())
+	}
+}
+
+
+
+/// Reads an OBD-II mode 09 PID. Returns the data after the item count, or
+/// `None` if the ECU does not support the PID or does not answer.
+fn vehicle_info(interface: &UdsInterface, pid: u8) -> Result<Option<Vec<u8>>> {
+	match uds::request(interface, 0x09, &[pid]) {
+		// The response repeats the PID, followed by the number of items
+		Ok(response) => Ok(response.get(2..).filter(|data| !data.is_empty()).map(|data| data.to_vec())),
+		Err(Error::NegativeResponse { .. }) | Err(Error::TuneUtils(TuneError::Timeout)) => Ok(None),
+		Err(err) => Err(err),
+	}
+}
+
+/// Reads a UDS data identifier. Returns `None` if the ECU does not support
+/// it or does not answer.
+fn data_identifier(interface: &UdsInterface, did: u16) -> Result<Option<Vec<u8>>> {
+	match uds::request(interface, 0x22, &[(did >> 8) as u8, did as u8]) {
+		// The response repeats the identifier
+		Ok(response) => Ok(response.get(2..).filter(|data| !data.is_empty()).map(|data| data.to_vec())),
+		Err(Error::NegativeResponse { .. }) | Err(Error::TuneUtils(TuneError::Timeout)) => Ok(None),
+		Err(err) => Err(err),
+	}
+}
+
+/// Converts padded ASCII to a string
+fn text(data: &[u8]) -> String {
+	data.iter()
+		.filter(|b| b.is_ascii_graphic() || **b == b' ')
+		.map(|b| *b as char)
+		.collect::<String>()
+		.trim()
+		.to_owned()
+}
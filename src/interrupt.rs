@@ -0,0 +1,48 @@
+#![cfg(feature = "cli")]
+
+use std::{
+	process,
+	sync::{Once, atomic::{AtomicBool, Ordering}},
+};
+
+static INIT: Once = Once::new();
+/// True while a command is waiting for Ctrl-C
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Lets a long-running command stop cleanly on Ctrl-C. Ctrl-C exits the
+/// process as usual when no guard is alive.
+pub struct Guard {
+	_private: (),
+}
+
+impl Guard {
+	pub fn new() -> Guard {
+		INIT.call_once(|| {
+			// If the handler cannot be installed Ctrl-C keeps its default behavior
+			let _ = ctrlc::set_handler(|| {
+				if ACTIVE.load(Ordering::SeqCst) {
+					INTERRUPTED.store(true, Ordering::SeqCst);
+				} else {
+					process::exit(130);
+				}
+			});
+		});
+		INTERRUPTED.store(false, Ordering::SeqCst);
+		ACTIVE.store(true, Ordering::SeqCst);
+		Guard {
+			_private: (),
+		}
+	}
+
+	/// Returns true once Ctrl-C has been pressed
+	pub fn interrupted(&self) -> bool {
+		INTERRUPTED.load(Ordering::SeqCst)
+	}
+}
+
+impl Drop for Guard {
+	fn drop(&mut self) {
+		ACTIVE.store(false, Ordering::SeqCst);
+	}
+}
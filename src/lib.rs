@@ -1,5 +1,22 @@
 pub mod app;
 pub mod error;
+pub mod datalink;
+pub mod uds;
 pub mod cli;
+pub mod output;
+pub mod completion;
+pub mod interrupt;
+pub mod capture;
+pub mod obd;
+pub mod diag;
+pub mod dtc;
+pub mod info;
+pub mod expr;
+pub mod logger;
+pub mod math;
+pub mod logfile;
+pub mod dashboard;
+pub mod analysis;
+pub mod plot;
 
 pub use tuneutils;
\ No newline at end of file
@@ -0,0 +1,710 @@
+#![cfg(feature = "cli")]
+
+//! Datalog files. Logs are recorded as CSV and as ASAM MDF 4.1 files, which
+//! can be opened by most measurement data tools, and are read back from
+//! either.
+
+use std::{
+	fs::{self, File},
+	io::{self, BufWriter, Seek, SeekFrom, Write},
+	path::{Component, Path, PathBuf},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+	error::{Error, Result},
+	expr::Expr,
+	logger::{Channel, Sample},
+	math::{self, MathChannel},
+	output::{csv_records, parse_csv_row, write_csv_row},
+};
+
+
+
+/// Extension of CSV logs
+pub const CSV_EXTENSION: &str = "csv";
+/// Extension of MDF logs
+pub const MDF_EXTENSION: &str = "mf4";
+
+
+
+/// Writes samples as CSV. The header holds channel names with units in
+/// brackets, e.g. "Engine speed [rpm]".
+pub struct CsvWriter {
+	out: BufWriter<File>,
+}
+
+impl CsvWriter {
+	pub fn create(path: &Path, channels: &[Channel]) -> io::Result<CsvWriter> {
+		let mut out = BufWriter::new(File::create(path)?);
+		let mut header = vec!["Time [s]".to_owned()];
+		header.extend(channels.iter().map(|channel| format!("{} [{}]", channel.name, channel.unit)));
+		write_csv_row(&mut out, &header)?;
+		Ok(CsvWriter { out })
+	}
+
+	pub fn write(&mut self, sample: &Sample) -> io::Result<()> {
+		let mut cells = vec![format!("{:.3}", sample.time)];
+		// Missing values are left empty
+		cells.extend(sample.values.iter().map(|value| value.map(|value| value.to_string()).unwrap_or_default()));
+		write_csv_row(&mut self.out, &cells)
+	}
+
+	pub fn finish(mut self) -> io::Result<()> {
+		self.out.flush()
+	}
+}
+
+
+
+/// Size of a block header: id, reserved, length and link count
+const HEADER_SIZE: u64 = 24;
+/// File identifier of finished and unfinished files
+const ID_FINISHED: &[u8; 8] = b"MDF     ";
+const ID_UNFINISHED: &[u8; 8] = b"UnFinMF ";
+/// Offset of the unfinalized flags in the identification block
+const ID_FLAGS_OFFSET: u64 = 60;
+/// Cycle counters and the length of the last data block must be updated
+const FLAGS_UNFINISHED: u16 = 0x01 | 0x04;
+
+/// Data type of little endian IEEE 754 floats
+const DATA_TYPE_FLOAT: u8 = 4;
+
+/// Builds a block, padded to a multiple of 8 bytes
+fn block(id: &[u8; 2], links: &[u64], data: &[u8]) -> Vec<u8> {
+	let length = HEADER_SIZE + links.len() as u64 * 8 + data.len() as u64;
+	let mut out = Vec::with_capacity(length as usize + 7);
+	out.extend_from_slice(b"##");
+	out.extend_from_slice(id);
+	out.extend_from_slice(&[0; 4]);
+	out.extend_from_slice(&length.to_le_bytes());
+	out.extend_from_slice(&(links.len() as u64).to_le_bytes());
+	for link in links {
+		out.extend_from_slice(&link.to_le_bytes());
+	}
+	out.extend_from_slice(data);
+	while out.len() % 8 != 0 {
+		out.push(0);
+	}
+	out
+}
+
+/// Builds a text block holding a zero terminated string
+fn text_block(id: &[u8; 2], text: &str) -> Vec<u8> {
+	let mut data = text.as_bytes().to_vec();
+	data.push(0);
+	block(id, &[], &data)
+}
+
+/// Builds a channel block. Value channels store an invalidation bit for
+/// missing samples.
+fn channel_block(links: &[u64], master: bool, byte_offset: u32, invalidation_bit: Option<u32>) -> Vec<u8> {
+	let mut data = Vec::with_capacity(72);
+	// Type (master or fixed length), sync type (time or none), data type and bit offset
+	data.extend_from_slice(&[if master { 2 } else { 0 }, if master { 1 } else { 0 }, DATA_TYPE_FLOAT, 0]);
+	data.extend_from_slice(&byte_offset.to_le_bytes());
+	data.extend_from_slice(&64u32.to_le_bytes());
+	// Flags: invalidation bit valid
+	data.extend_from_slice(&(if invalidation_bit.is_some() { 0x02u32 } else { 0 }).to_le_bytes());
+	data.extend_from_slice(&invalidation_bit.unwrap_or(0).to_le_bytes());
+	// Precision, reserved, attachment count, then ranges and limits
+	data.extend_from_slice(&[0xFF, 0, 0, 0]);
+	data.extend_from_slice(&[0; 48]);
+	block(b"CN", links, &data)
+}
+
+/// Writes samples to an MDF 4.1 file with one data group. The file is
+/// marked unfinished until `finish` updates the sample count.
+pub struct Mdf4Writer {
+	out: BufWriter<File>,
+	/// Address of the channel group, whose cycle count is updated at the end
+	channel_group: u64,
+	/// Address of the data block, whose length is updated at the end
+	data_block: u64,
+	records: u64,
+	/// Size of the invalidation bytes after the values of a record
+	invalidation_bytes: usize,
+}
+
+impl Mdf4Writer {
+	pub fn create(path: &Path, channels: &[Channel], start: SystemTime) -> io::Result<Mdf4Writer> {
+		let mut out = BufWriter::new(File::create(path)?);
+		let start_ns = start.duration_since(UNIX_EPOCH).map(|time| time.as_secs() * 1_000_000_000 + u64::from(time.subsec_nanos())).unwrap_or(0);
+		let invalidation_bytes = (channels.len() + 7) / 8;
+
+		// Identification block
+		let mut id = Vec::with_capacity(64);
+		id.extend_from_slice(ID_UNFINISHED);
+		id.extend_from_slice(b"4.10    ");
+		id.extend_from_slice(b"LibreTun");
+		id.extend_from_slice(&[0; 4]);
+		id.extend_from_slice(&410u16.to_le_bytes());
+		id.extend_from_slice(&[0; 30]);
+		id.extend_from_slice(&FLAGS_UNFINISHED.to_le_bytes());
+		id.extend_from_slice(&[0; 2]);
+
+		// Blocks are laid out in this order after the identification block:
+		// HD, FH, MD (history comment), DG, CG, then CN, TX name and TX unit
+		// for the time channel and each value channel, then DT
+		let comment = text_block(b"MD", &format!(
+			"<FHcomment xmlns=\"http://www.asam.net/mdf/v4\"><TX>Datalog</TX><tool_id>LibreTuner</tool_id><tool_vendor>LibreTuner</tool_vendor><tool_version>{}</tool_version></FHcomment>",
+			env!("CARGO_PKG_VERSION")));
+		let mut names: Vec<(Vec<u8>, Vec<u8>)> = vec![(text_block(b"TX", "time"), text_block(b"TX", "s"))];
+		for channel in channels {
+			names.push((text_block(b"TX", &channel.name), text_block(b"TX", &channel.unit)));
+		}
+
+		const HD_SIZE: u64 = 104;
+		const FH_SIZE: u64 = 56;
+		const DG_SIZE: u64 = 64;
+		const CG_SIZE: u64 = 104;
+		const CN_SIZE: u64 = 160;
+		let hd = 64;
+		let fh = hd + HD_SIZE;
+		let md = fh + FH_SIZE;
+		let dg = md + comment.len() as u64;
+		let cg = dg + DG_SIZE;
+		let mut cn = Vec::new();
+		let mut address = cg + CG_SIZE;
+		for &(ref name, ref unit) in names.iter() {
+			cn.push(address);
+			address += CN_SIZE + name.len() as u64 + unit.len() as u64;
+		}
+		let dt = address;
+
+		let mut blocks = Vec::new();
+		// Header: first data group and file history, start time in UTC
+		let mut data = Vec::new();
+		data.extend_from_slice(&start_ns.to_le_bytes());
+		data.extend_from_slice(&[0; 24]);
+		blocks.push(block(b"HD", &[dg, fh, 0, 0, 0, 0], &data));
+		// File history
+		let mut data = Vec::new();
+		data.extend_from_slice(&start_ns.to_le_bytes());
+		data.extend_from_slice(&[0; 8]);
+		blocks.push(block(b"FH", &[0, md], &data));
+		blocks.push(comment);
+		// Data group without record ids
+		blocks.push(block(b"DG", &[0, cg, dt, 0], &[0; 8]));
+		// Channel group. The cycle count is written by `finish`.
+		let mut data = Vec::new();
+		data.extend_from_slice(&0u64.to_le_bytes());
+		data.extend_from_slice(&0u64.to_le_bytes());
+		data.extend_from_slice(&[0; 8]);
+		data.extend_from_slice(&((names.len() * 8) as u32).to_le_bytes());
+		data.extend_from_slice(&(invalidation_bytes as u32).to_le_bytes());
+		blocks.push(block(b"CG", &[0, cn[0], 0, 0, 0, 0], &data));
+		// Channels
+		for (i, (name, unit)) in names.into_iter().enumerate() {
+			let next = cn.get(i + 1).cloned().unwrap_or(0);
+			let name_address = cn[i] + CN_SIZE;
+			let unit_address = name_address + name.len() as u64;
+			let invalidation_bit = if i == 0 { None } else { Some(i as u32 - 1) };
+			blocks.push(channel_block(&[next, 0, name_address, 0, 0, 0, unit_address, 0], i == 0, i as u32 * 8, invalidation_bit));
+			blocks.push(name);
+			blocks.push(unit);
+		}
+
+		out.write_all(&id)?;
+		for block in blocks {
+			out.write_all(&block)?;
+		}
+		// Data block header. The length is written by `finish`.
+		out.write_all(b"##DT")?;
+		out.write_all(&[0; 4])?;
+		out.write_all(&HEADER_SIZE.to_le_bytes())?;
+		out.write_all(&0u64.to_le_bytes())?;
+
+		Ok(Mdf4Writer {
+			out,
+			channel_group: cg,
+			data_block: dt,
+			records: 0,
+			invalidation_bytes,
+		})
+	}
+
+	pub fn write(&mut self, sample: &Sample) -> io::Result<()> {
+		self.out.write_all(&sample.time.to_le_bytes())?;
+		let mut invalid = vec![0u8; self.invalidation_bytes];
+		for (i, value) in sample.values.iter().enumerate() {
+			match *value {
+				Some(value) => self.out.write_all(&value.to_le_bytes())?,
+				None => {
+					self.out.write_all(&0f64.to_le_bytes())?;
+					invalid[i / 8] |= 1 << (i % 8);
+				},
+			}
+		}
+		self.out.write_all(&invalid)?;
+		self.records += 1;
+		Ok(())
+	}
+
+	/// Updates the sample count and data length, and marks the file finished
+	pub fn finish(mut self) -> io::Result<()> {
+		let end = self.out.seek(SeekFrom::End(0))?;
+		self.out.seek(SeekFrom::Start(self.data_block + 8))?;
+		self.out.write_all(&(end - self.data_block).to_le_bytes())?;
+		// Cycle count follows the header, six links and the record id
+		self.out.seek(SeekFrom::Start(self.channel_group + HEADER_SIZE + 6 * 8 + 8))?;
+		self.out.write_all(&self.records.to_le_bytes())?;
+		self.out.seek(SeekFrom::Start(0))?;
+		self.out.write_all(ID_FINISHED)?;
+		self.out.seek(SeekFrom::Start(ID_FLAGS_OFFSET))?;
+		self.out.write_all(&0u16.to_le_bytes())?;
+		self.out.flush()
+	}
+}
+
+
+
+/// Records samples to a CSV and an MDF file with the same name
+pub struct Recorder {
+	csv: CsvWriter,
+	mdf: Mdf4Writer,
+}
+
+impl Recorder {
+	/// Creates `<name>.csv` and `<name>.mf4` in `dir`. Existing logs are
+	/// never overwritten.
+	pub fn create(dir: &Path, name: &str, channels: &[Channel]) -> Result<Recorder> {
+		let mut components = Path::new(name).components();
+		match (components.next(), components.next()) {
+			(Some(Component::Normal(_)), None) => (),
+			_ => return Err(Error::InvalidLogName(name.to_owned())),
+		}
+		let csv = log_path(dir, name, CSV_EXTENSION);
+		let mdf = log_path(dir, name, MDF_EXTENSION);
+		if csv.exists() || mdf.exists() {
+			return Err(Error::LogExists(name.to_owned()));
+		}
+
+		fs::create_dir_all(dir)?;
+		Ok(Recorder {
+			csv: CsvWriter::create(&csv, channels)?,
+			mdf: Mdf4Writer::create(&mdf, channels, SystemTime::now())?,
+		})
+	}
+
+	pub fn write(&mut self, sample: &Sample) -> io::Result<()> {
+		self.csv.write(sample)?;
+		self.mdf.write(sample)
+	}
+
+	/// Finishes both files. The MDF file is finished even if the CSV file
+	/// fails, and the first error is returned.
+	pub fn finish(self) -> io::Result<()> {
+		let csv = self.csv.finish();
+		let mdf = self.mdf.finish();
+		csv.and(mdf)
+	}
+}
+
+
+
+/// A recorded log
+pub struct LogEntry {
+	pub name: String,
+	/// Extensions of the files recorded under this name
+	pub formats: Vec<String>,
+	/// Total size of the files in bytes
+	pub size: u64,
+	pub modified: Option<SystemTime>,
+}
+
+/// Lists the logs in `dir`, sorted by name
+pub fn list(dir: &Path) -> io::Result<Vec<LogEntry>> {
+	let mut logs: Vec<LogEntry> = Vec::new();
+	if !dir.exists() {
+		return Ok(logs);
+	}
+
+	for entry in fs::read_dir(dir)? {
+		let path = entry?.path();
+		// The name is everything before the last extension, as in `log_path`
+		let (name, extension) = match (path.file_stem().and_then(|s| s.to_str()), path.extension().and_then(|s| s.to_str())) {
+			(Some(name), Some(extension)) if extension == CSV_EXTENSION || extension == MDF_EXTENSION => (name.to_owned(), extension.to_owned()),
+			_ => continue,
+		};
+		let metadata = fs::metadata(&path)?;
+
+		let index = match logs.iter().position(|log| log.name == name) {
+			Some(index) => index,
+			None => {
+				logs.push(LogEntry { name, formats: Vec::new(), size: 0, modified: None });
+				logs.len() - 1
+			},
+		};
+		let log = &mut logs[index];
+		log.formats.push(extension);
+		log.formats.sort();
+		log.size += metadata.len();
+		log.modified = log.modified.max(metadata.modified().ok());
+	}
+
+	logs.sort_by(|a, b| a.name.cmp(&b.name));
+	Ok(logs)
+}
+
+/// Returns the path of the file a log is read from. The CSV file is used if
+/// it exists, otherwise the MDF file. `name` may also be a path to a CSV or
+/// MDF file.
+pub fn log_file(dir: &Path, name: &str) -> PathBuf {
+	let path = Path::new(name);
+	match path.extension().and_then(|s| s.to_str()) {
+		Some(CSV_EXTENSION) | Some(MDF_EXTENSION) => return path.to_path_buf(),
+		_ => (),
+	}
+	let csv = log_path(dir, name, CSV_EXTENSION);
+	let mdf = log_path(dir, name, MDF_EXTENSION);
+	if !csv.exists() && mdf.exists() {
+		mdf
+	} else {
+		csv
+	}
+}
+
+/// Returns the path of a log file. The extension is appended so names may
+/// contain dots, e.g. "run.1".
+fn log_path(dir: &Path, name: &str, extension: &str) -> PathBuf {
+	dir.join(format!("{}.{}", name, extension))
+}
+
+
+
+/// Name and unit of a recorded channel
+#[derive(Debug, Clone)]
+pub struct LogChannel {
+	pub name: String,
+	pub unit: String,
+	/// True for math channels computed after the log was opened
+	pub computed: bool,
+}
+
+/// A log read back from its CSV or MDF file
+#[derive(Debug, Clone)]
+pub struct Datalog {
+	pub name: String,
+	pub channels: Vec<LogChannel>,
+	pub samples: Vec<Sample>,
+}
+
+impl Datalog {
+	/// Loads a log. Files ending in .mf4 are read as MDF, others as CSV.
+	pub fn load(path: &Path) -> Result<Datalog> {
+		let (channels, samples) = if path.extension().and_then(|s| s.to_str()) == Some(MDF_EXTENSION) {
+			read_mdf(&fs::read(path)?)?
+		} else {
+			read_csv(&fs::read_to_string(path)?)?
+		};
+		Ok(Datalog {
+			name: path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default(),
+			channels,
+			samples,
+		})
+	}
+
+	/// Returns the time of the last sample in seconds
+	pub fn duration(&self) -> f64 {
+		self.samples.last().map(|sample| sample.time).unwrap_or(0.0)
+	}
+
+	/// Finds a channel by name, ignoring case
+	pub fn channel(&self, name: &str) -> Result<usize> {
+		self.channels.iter().position(|channel| channel.name.eq_ignore_ascii_case(name))
+			.ok_or_else(|| Error::InvalidChannel(name.to_owned()))
+	}
+
+	/// Returns the value of a channel in `sample`, or the sample time for
+	/// `time`. Used to evaluate expressions over a log.
+	pub fn lookup(&self, sample: &Sample, name: &str) -> Option<f64> {
+		if math::is_time(name) {
+			return Some(sample.time);
+		}
+		*sample.values.get(self.channel(name).ok()?)?
+	}
+
+	/// Computes the math channels that are missing from the log and whose
+	/// inputs are in it. Returns the number of channels added.
+	pub fn add_math(&mut self, channels: &[MathChannel]) -> Result<usize> {
+		let mut added = 0;
+		for (index, channel) in channels.iter().enumerate() {
+			if self.channel(&channel.name).is_ok() {
+				continue;
+			}
+			let expression = channel.parse()?;
+			let missing: Vec<&str> = expression.variables().into_iter()
+				.filter(|name| !math::is_time(name) && self.channel(name).is_err())
+				.collect();
+			math::check_order(channels, index, &missing)?;
+			if !missing.is_empty() {
+				continue;
+			}
+
+			let values: Vec<Option<f64>> = self.samples.iter()
+				.map(|sample| expression.eval(&|name: &str| self.lookup(sample, name)))
+				.collect();
+			for (sample, value) in self.samples.iter_mut().zip(values) {
+				sample.values.push(value);
+			}
+			self.channels.push(LogChannel {
+				name: channel.name.clone(),
+				unit: channel.unit.clone(),
+				computed: true,
+			});
+			added += 1;
+		}
+		Ok(added)
+	}
+
+	/// Returns the samples between `from` and `to` seconds for which
+	/// `condition` is true. Samples missing a value used by the condition
+	/// are left out.
+	pub fn select(&self, from: Option<f64>, to: Option<f64>, condition: Option<&Expr>) -> Result<Vec<&Sample>> {
+		if let Some(condition) = condition {
+			for name in condition.variables() {
+				if !math::is_time(name) {
+					self.channel(name)?;
+				}
+			}
+		}
+
+		Ok(self.samples.iter()
+			.filter(|sample| from.map_or(true, |from| sample.time >= from) && to.map_or(true, |to| sample.time <= to))
+			.filter(|sample| condition.map_or(true, |condition| {
+				condition.eval(&|name: &str| self.lookup(sample, name)).map_or(false, |value| value != 0.0)
+			}))
+			.collect())
+	}
+}
+
+/// Reads the channels and samples of a CSV log
+fn read_csv(contents: &str) -> Result<(Vec<LogChannel>, Vec<Sample>)> {
+	// Quoted channel names may contain line breaks
+	let mut records = csv_records(contents).into_iter();
+	let header = parse_csv_row(records.next().ok_or(Error::InvalidLog(1))?.1);
+	// The first column is the time
+	let channels = header.iter().skip(1).map(|column| parse_column(column)).collect();
+
+	let mut samples = Vec::new();
+	for (number, record) in records {
+		if record.trim().is_empty() {
+			continue;
+		}
+		let invalid = || Error::InvalidLog(number);
+		let cells = parse_csv_row(record);
+		if cells.len() != header.len() {
+			return Err(invalid());
+		}
+		let time = cells[0].parse::<f64>().map_err(|_| invalid())?;
+		// Missing values are empty
+		let values = cells[1..].iter()
+			.map(|cell| if cell.is_empty() { Ok(None) } else { cell.parse::<f64>().map(Some) })
+			.collect::<::std::result::Result<Vec<Option<f64>>, _>>()
+			.map_err(|_| invalid())?;
+		samples.push(Sample { time, values });
+	}
+
+	Ok((channels, samples))
+}
+
+/// Splits a header column such as "Engine speed [rpm]" into name and unit
+fn parse_column(column: &str) -> LogChannel {
+	if column.ends_with(']') {
+		if let Some(start) = column.rfind(" [") {
+			return LogChannel {
+				name: column[..start].to_owned(),
+				unit: column[start + 2..column.len() - 1].to_owned(),
+				computed: false,
+			};
+		}
+	}
+	LogChannel {
+		name: column.to_owned(),
+		unit: String::new(),
+		computed: false,
+	}
+}
+
+
+
+/// A fixed length value in an MDF record
+struct MdfField {
+	byte_offset: usize,
+	bytes: usize,
+	data_type: u8,
+	/// Invalidation bit in the bytes after the values
+	invalidation_bit: Option<usize>,
+}
+
+impl MdfField {
+	fn decode(&self, record: &[u8], data_bytes: usize) -> Option<f64> {
+		if let Some(bit) = self.invalidation_bit {
+			if record[data_bytes + bit / 8] & (1 << (bit % 8)) != 0 {
+				return None;
+			}
+		}
+		let raw = le(&record[self.byte_offset..self.byte_offset + self.bytes]);
+		let unused = 64 - 8 * self.bytes as u32;
+		Some(match self.data_type {
+			DATA_TYPE_UNSIGNED => raw as f64,
+			// Sign extend
+			DATA_TYPE_SIGNED => ((raw << unused) as i64 >> unused) as f64,
+			_ if self.bytes == 4 => f64::from(f32::from_bits(raw as u32)),
+			_ => f64::from_bits(raw),
+		})
+	}
+}
+
+/// Data type of little endian unsigned and signed integers
+const DATA_TYPE_UNSIGNED: u8 = 0;
+const DATA_TYPE_SIGNED: u8 = 2;
+
+/// Reads a little endian integer
+fn le(bytes: &[u8]) -> u64 {
+	bytes.iter().rev().fold(0, |value, &byte| value << 8 | u64::from(byte))
+}
+
+/// Reads a little endian integer of `size` bytes at `offset`
+fn read_le(data: &[u8], offset: u64, size: usize) -> Result<u64> {
+	let start = offset as usize;
+	data.get(start..start + size).map(le).ok_or(Error::InvalidMdf("unexpected end of file"))
+}
+
+/// Checks that the block at `address` has the id `id`
+fn mdf_block(data: &[u8], address: u64, id: &[u8; 2]) -> Result<u64> {
+	let start = address as usize;
+	match data.get(start..start + 4) {
+		Some(header) if header[..2] == *b"##" && header[2..] == id[..] => Ok(address),
+		_ => Err(Error::InvalidMdf("broken block link")),
+	}
+}
+
+/// Returns link `index` of the block at `address`, or 0 if it has no such link
+fn mdf_link(data: &[u8], address: u64, index: u64) -> Result<u64> {
+	if index >= read_le(data, address + 16, 8)? {
+		return Ok(0);
+	}
+	read_le(data, address + HEADER_SIZE + index * 8, 8)
+}
+
+/// Returns the text of a TX block
+fn mdf_text(data: &[u8], address: u64) -> Result<String> {
+	mdf_block(data, address, b"TX")?;
+	let length = read_le(data, address + 8, 8)?;
+	let text = data.get((address + HEADER_SIZE) as usize..(address + length) as usize)
+		.ok_or(Error::InvalidMdf("unexpected end of file"))?;
+	let end = text.iter().position(|&byte| byte == 0).unwrap_or(text.len());
+	Ok(String::from_utf8_lossy(&text[..end]).into_owned())
+}
+
+/// Reads the channels and samples of an MDF 4 log with one channel group
+/// of fixed length integer or float channels, as written by `Mdf4Writer`.
+/// Unfinished files are read up to the last complete record.
+fn read_mdf(data: &[u8]) -> Result<(Vec<LogChannel>, Vec<Sample>)> {
+	let finished = match data.get(..8) {
+		Some(id) if id == ID_FINISHED => true,
+		Some(id) if id == ID_UNFINISHED => false,
+		_ => return Err(Error::InvalidMdf("not an MDF file")),
+	};
+	if read_le(data, 28, 2)? < 400 {
+		return Err(Error::InvalidMdf("only MDF 4 files are supported"));
+	}
+
+	let hd = mdf_block(data, 64, b"HD")?;
+	let dg = mdf_block(data, mdf_link(data, hd, 0)?, b"DG")?;
+	// Record ids are only needed for several channel groups in a data group
+	if read_le(data, dg + HEADER_SIZE + 4 * 8, 1)? != 0 {
+		return Err(Error::InvalidMdf("record ids are not supported"));
+	}
+	let cg = mdf_block(data, mdf_link(data, dg, 1)?, b"CG")?;
+	let cycle_count = read_le(data, cg + HEADER_SIZE + 6 * 8 + 8, 8)?;
+	let data_bytes = read_le(data, cg + HEADER_SIZE + 6 * 8 + 24, 4)? as usize;
+	let invalidation_bytes = read_le(data, cg + HEADER_SIZE + 6 * 8 + 28, 4)? as usize;
+
+	let mut time = None;
+	let mut channels = Vec::new();
+	let mut fields = Vec::new();
+	let mut cn = mdf_link(data, cg, 1)?;
+	while cn != 0 {
+		mdf_block(data, cn, b"CN")?;
+		let info = cn + HEADER_SIZE + 8 * 8;
+		let channel_type = read_le(data, info, 1)?;
+		let data_type = read_le(data, info + 2, 1)? as u8;
+		let bit_offset = read_le(data, info + 3, 1)?;
+		let byte_offset = read_le(data, info + 4, 4)? as usize;
+		let bits = read_le(data, info + 8, 4)? as usize;
+		let flags = read_le(data, info + 12, 4)?;
+		let invalidation_bit = read_le(data, info + 16, 4)? as usize;
+
+		// Fixed length and master channels
+		if channel_type != 0 && channel_type != 2 {
+			return Err(Error::InvalidMdf("only fixed length channels are supported"));
+		}
+		if mdf_link(data, cn, 4)? != 0 {
+			return Err(Error::InvalidMdf("channel conversions are not supported"));
+		}
+		let supported = match data_type {
+			DATA_TYPE_UNSIGNED | DATA_TYPE_SIGNED => bits == 8 || bits == 16 || bits == 32 || bits == 64,
+			DATA_TYPE_FLOAT => bits == 32 || bits == 64,
+			_ => false,
+		};
+		if !supported || bit_offset != 0 || byte_offset + bits / 8 > data_bytes {
+			return Err(Error::InvalidMdf("only little endian integer and float channels are supported"));
+		}
+		// Flag 0x02: invalidation bit valid
+		if flags & 0x02 != 0 && invalidation_bit / 8 >= invalidation_bytes {
+			return Err(Error::InvalidMdf("invalidation bit out of range"));
+		}
+		let field = MdfField {
+			byte_offset,
+			bytes: bits / 8,
+			data_type,
+			invalidation_bit: if flags & 0x02 != 0 { Some(invalidation_bit) } else { None },
+		};
+
+		if channel_type == 2 {
+			time = Some(field);
+		} else {
+			// The unit is a TX block, or an MD block that is left out
+			let unit = match mdf_link(data, cn, 6)? {
+				0 => String::new(),
+				unit => mdf_text(data, unit).unwrap_or_default(),
+			};
+			channels.push(LogChannel {
+				name: mdf_text(data, mdf_link(data, cn, 2)?)?,
+				unit,
+				computed: false,
+			});
+			fields.push(field);
+		}
+		cn = mdf_link(data, cn, 0)?;
+	}
+	let time = time.ok_or(Error::InvalidMdf("no time channel"))?;
+
+	// The records of a single data block. Unfinished files may not have the
+	// length of the block or the record count yet.
+	let dt = mdf_link(data, dg, 2)?;
+	let records: &[u8] = if dt == 0 {
+		&[]
+	} else {
+		mdf_block(data, dt, b"DT").map_err(|_| Error::InvalidMdf("only single data blocks are supported"))?;
+		let length = read_le(data, dt + 8, 8)?;
+		let end = if finished || length > HEADER_SIZE { (dt + length) as usize } else { data.len() };
+		data.get((dt + HEADER_SIZE) as usize..end).ok_or(Error::InvalidMdf("unexpected end of file"))?
+	};
+	let record_size = data_bytes + invalidation_bytes;
+	let mut count = records.len().checked_div(record_size).unwrap_or(0);
+	if finished {
+		count = count.min(cycle_count as usize);
+	}
+
+	let samples = records.chunks(record_size.max(1)).take(count)
+		.map(|record| Sample {
+			time: time.decode(record, data_bytes).unwrap_or(::std::f64::NAN),
+			values: fields.iter().map(|field| field.decode(record, data_bytes)).collect(),
+		})
+		.collect();
+	Ok((channels, samples))
+}
@@ -0,0 +1,239 @@
+//! Live data channels. Platform PIDs are read by the platform's datalogger
+//! into a `datalog::Log`. The generic OBD-II platform has no definition to
+//! log with, so its PIDs are polled through mode 01.
+
+use std::{
+	collections::HashMap,
+	rc::Rc,
+	sync::{Arc, Mutex},
+	thread,
+	time::{Duration, Instant},
+};
+
+use tuneutils::{
+	datalog,
+	definition::Definitions,
+	error::Error as TuneError,
+	link::PlatformLink,
+	protocols::uds::UdsInterface,
+};
+
+use crate::{
+	error::{Error, Result},
+	expr::Expr,
+	math::{self, MathChannel},
+	obd::{self, ObdPid},
+	uds,
+};
+
+
+
+/// Where the value of a channel comes from
+enum Source {
+	/// Standard PID of the generic platform, read through OBD-II mode 01
+	Obd(&'static ObdPid),
+	/// Platform PID read by the datalogger, by id
+	Pid(u32),
+	/// Math channel computed from the channels before it and the sample time
+	Math(Expr),
+}
+
+/// A value that can be logged
+pub struct Channel {
+	pub name: String,
+	pub unit: String,
+	source: Source,
+}
+
+/// Finds the channels named by `selectors`. A selector is a PID id or a
+/// name, matched case-insensitively. Standard PIDs of the generic platform
+/// are given in hex, e.g. "0x0C". Selectors may also name math channels, which
+/// are placed after the PIDs along with the PIDs they use.
+pub fn select(definitions: &Definitions, platform: &str, selectors: &[&str], math: &[MathChannel]) -> Result<Vec<Channel>> {
+	let (selected_math, selectors): (Vec<&str>, Vec<&str>) = selectors.iter().cloned()
+		.partition(|selector| math::find(math, selector).is_some());
+	let (math, inputs) = math::resolve(math, &selected_math)?;
+
+	let mut channels = Vec::new();
+	for selector in selectors {
+		channels.push(pid_channel(definitions, platform, selector)?);
+	}
+	for input in inputs.iter() {
+		let channel = pid_channel(definitions, platform, input)?;
+		// Inputs of math channels may already be selected
+		if !channels.iter().any(|selected: &Channel| selected.name == channel.name) {
+			channels.push(channel);
+		}
+	}
+	for channel in math {
+		channels.push(Channel {
+			name: channel.name.clone(),
+			unit: channel.unit.clone(),
+			source: Source::Math(channel.parse()?),
+		});
+	}
+	Ok(channels)
+}
+
+/// Finds the PID named by `selector`
+fn pid_channel(definitions: &Definitions, platform: &str, selector: &str) -> Result<Channel> {
+	if platform == obd::PLATFORM_ID {
+		let id = u8::from_str_radix(selector.trim_start_matches("0x").trim_start_matches("0X"), 16).ok();
+		let pid = obd::pids().iter()
+			.find(|pid| Some(pid.pid) == id || pid.name.eq_ignore_ascii_case(selector))
+			.ok_or_else(|| Error::InvalidPid(selector.to_string()))?;
+		return Ok(Channel {
+			name: pid.name.to_owned(),
+			unit: pid.unit.to_owned(),
+			source: Source::Obd(pid),
+		});
+	}
+
+	let platform = definitions.find(platform).ok_or(Error::InvalidPlatform)?;
+	let pid = platform.pids.iter()
+		.find(|pid| pid.id.to_string() == selector || pid.name.eq_ignore_ascii_case(selector))
+		.ok_or_else(|| Error::InvalidPid(selector.to_string()))?;
+	Ok(Channel {
+		name: pid.name.clone(),
+		unit: pid.unit.clone(),
+		source: Source::Pid(pid.id),
+	})
+}
+
+
+
+/// Values of all channels at one point in time
+#[derive(Debug, Clone)]
+pub struct Sample {
+	/// Seconds since logging started
+	pub time: f64,
+	pub values: Vec<Option<f64>>,
+}
+
+/// Connection PID values are read through
+pub enum Link {
+	/// UDS interface of the generic OBD-II platform
+	Obd(Rc<UdsInterface>),
+	/// Link to a platform with a definition
+	Platform(PlatformLink),
+}
+
+/// Reads samples of a set of channels
+pub struct Sampler {
+	link: Link,
+	pub channels: Vec<Channel>,
+	start: Instant,
+}
+
+impl Sampler {
+	pub fn new(link: Link, channels: Vec<Channel>) -> Sampler {
+		Sampler {
+			link,
+			channels,
+			start: Instant::now(),
+		}
+	}
+
+	/// Reads samples, at most one every `interval`, until `each` returns
+	/// false or an error
+	pub fn run<F>(&self, interval: Duration, mut each: F) -> Result<()>
+	where F: FnMut(Sample) -> Result<bool> {
+		match self.link {
+			Link::Obd(ref interface) => loop {
+				let started = Instant::now();
+				let mut values = Vec::with_capacity(self.channels.len());
+				for channel in self.channels.iter() {
+					values.push(match channel.source {
+						Source::Obd(pid) => read_obd(&**interface, pid)?,
+						_ => None,
+					});
+				}
+				if !each(self.sample(values))? {
+					return Ok(());
+				}
+				pace(started, interval);
+			},
+			Link::Platform(ref link) => {
+				let mut logger = link.datalogger().ok_or(Error::LogUnsupported)?;
+				let mut log = datalog::Log::new(link.platform.clone());
+				for channel in self.channels.iter() {
+					if let Source::Pid(id) = channel.source {
+						let pid = link.platform.pids.iter().find(|pid| pid.id == id).ok_or_else(|| Error::InvalidPid(channel.name.clone()))?;
+						logger.add_entry(pid);
+						log.add_entry(pid);
+					}
+				}
+
+				// The log hands every value to its listeners as it is read
+				let latest: Arc<Mutex<HashMap<u32, f64>>> = Arc::new(Mutex::new(HashMap::new()));
+				{
+					let latest = latest.clone();
+					log.register(move |entry, value| {
+						latest.lock().unwrap().insert(entry.pid_id, f64::from(value));
+					});
+				}
+
+				loop {
+					let started = Instant::now();
+					// Each run is one pass over the entries. A missed value
+					// should not end the log.
+					match logger.run(&mut log) {
+						Ok(()) | Err(TuneError::Timeout) => (),
+						Err(err) => return Err(err.into()),
+					}
+					let values = {
+						let mut latest = latest.lock().unwrap();
+						self.channels.iter().map(|channel| match channel.source {
+							Source::Pid(id) => latest.remove(&id),
+							_ => None,
+						}).collect()
+					};
+					if !each(self.sample(values))? {
+						return Ok(());
+					}
+					pace(started, interval);
+				}
+			},
+		}
+	}
+
+	/// Timestamps values read from the ECU and computes the math channels
+	fn sample(&self, mut values: Vec<Option<f64>>) -> Sample {
+		let elapsed = self.start.elapsed();
+		let time = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+		for (index, channel) in self.channels.iter().enumerate() {
+			if let Source::Math(ref expression) = channel.source {
+				let value = expression.eval(&|name: &str| {
+					if math::is_time(name) {
+						return Some(time);
+					}
+					self.channels[..index].iter().zip(values.iter())
+						.find(|&(channel, _)| channel.name.eq_ignore_ascii_case(name))
+						.and_then(|(_, value)| *value)
+				});
+				values[index] = value;
+			}
+		}
+		Sample { time, values }
+	}
+}
+
+/// Reads a standard PID through mode 01. Returns `None` if the ECU does not
+/// answer.
+fn read_obd(interface: &UdsInterface, pid: &ObdPid) -> Result<Option<f64>> {
+	match uds::request(interface, 0x01, &[pid.pid]) {
+		// The response repeats the PID
+		Ok(response) => Ok(response.get(1..).and_then(|data| pid.decode(data))),
+		// A missed sample should not end the log
+		Err(Error::NegativeResponse { .. }) | Err(Error::TuneUtils(TuneError::Timeout)) => Ok(None),
+		Err(err) => Err(err),
+	}
+}
+
+/// Sleeps for the rest of `interval` after `started`
+fn pace(started: Instant, interval: Duration) {
+	let elapsed = started.elapsed();
+	if elapsed < interval {
+		thread::sleep(interval - elapsed);
+	}
+}
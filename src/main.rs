@@ -1,33 +1,135 @@
 pub mod app;
 pub mod error;
+pub mod datalink;
+pub mod uds;
 pub mod cli;
-
-use shlex::Shlex;
+pub mod output;
+pub mod completion;
+pub mod interrupt;
+pub mod capture;
+pub mod obd;
+pub mod diag;
+pub mod dtc;
+pub mod info;
+pub mod expr;
+pub mod logger;
+pub mod math;
+pub mod logfile;
+pub mod dashboard;
+pub mod analysis;
+pub mod plot;
+
+use std::{process, path::Path};
 
 use rustyline::{Editor, error::ReadlineError};
 
 #[cfg(feature = "cli")]
 fn main() {
-	let mut app = app::App::new().unwrap();
+	let mut app = match app::App::new() {
+		Ok(app) => app,
+		Err(err) => {
+			eprintln!("Failed to load LibreTuner: {}", err);
+			process::exit(1);
+		},
+	};
+	let config = match cli::Config::load(&app.config_dir.join("cli.toml")) {
+		Ok(config) => config,
+		Err(err) => {
+			eprintln!("Failed to load CLI configuration: {}", err);
+			process::exit(1);
+		},
+	};
+	let history_path = app.data_dir.join("history.txt");
+
 	let mut cli = cli::Cli::new(&mut app);
+	cli.settings = match cli::Settings::from_config(&config) {
+		Ok(settings) => settings,
+		Err(err) => {
+			eprintln!("Failed to load CLI configuration: {}", err);
+			process::exit(1);
+		},
+	};
 
 	// Register default commands
 	cli.register_all();
 
+	let matches = clap::App::new("libretuner")
+		.version(env!("CARGO_PKG_VERSION"))
+		.about("Runs a LibreTuner command, a script, or an interactive shell")
+		.setting(clap::AppSettings::TrailingVarArg)
+		.arg(clap::Arg::with_name("script")
+			.help("Runs each line of a file as a command and exits")
+			.short("s")
+			.long("script")
+			.takes_value(true)
+			.conflicts_with("command"))
+		.arg(clap::Arg::with_name("keep-going")
+			.help("Continue running the script after a command fails")
+			.short("k")
+			.long("keep-going")
+			.requires("script"))
+		.arg(clap::Arg::with_name("format")
+			.help("Output format of listing commands")
+			.short("f")
+			.long("format")
+			.takes_value(true)
+			.possible_values(&["text", "json", "csv"]))
+		.arg(clap::Arg::with_name("command")
+			.help("Command to run and exit. Starts the interactive shell if omitted")
+			.index(1)
+			.multiple(true))
+		.get_matches();
+
+	if let Some(format) = matches.value_of("format") {
+		cli.settings.format = format.parse().unwrap();
+	}
+
+	// Run a script and exit
+	if let Some(path) = matches.value_of("script") {
+		if let Err(err) = cli.run_script(Path::new(path), matches.is_present("keep-going")) {
+			cli::print_error(&err);
+			process::exit(1);
+		}
+		return;
+	}
+
+	// If a command was given on the command line, run it and exit
+	if let Some(args) = matches.values_of("command") {
+		let args: Vec<String> = args.map(|arg| arg.to_owned()).collect();
+		if let Err(err) = cli.process(args.into_iter()) {
+			cli::print_error(&err);
+			process::exit(1);
+		}
+		return;
+	}
+
 	println!("LibreTuner  Copyright (C) 2018  LibreTuner Team
 This program comes with ABSOLUTELY NO WARRANTY; for details type `show w'.
 This is free software, and you are welcome to redistribute it
 under certain conditions; type `show c' for details.");
-    let mut rl = Editor::<()>::new();
+    // Run startup commands from the configuration
+    for line in config.startup.iter() {
+        if let Err(err) = cli.process_line(line) {
+            cli::print_error(&err);
+        }
+    }
+
+    let prompt = config.prompt.as_ref().map(String::as_str).unwrap_or(">> ");
+    let mut rl = Editor::<completion::CliHelper>::new();
+    // History does not exist on the first run
+    let _ = rl.load_history(&history_path);
     loop {
+    	// Refresh completions as commands may have added links, ROMs or tunes
+    	rl.set_helper(Some(completion::CliHelper::new(cli.completions())));
     	println!();
-        let readline = rl.readline(">> ");
+        let readline = rl.readline(prompt);
         match readline {
             Ok(line) => {
                 rl.add_history_entry(line.as_ref());
-                
-                let parts = Shlex::new(&line);
-                cli.process(parts).unwrap();
+
+                if let Err(err) = cli.process_line(&line) {
+                    cli::print_error(&err);
+                }
             },
             Err(ReadlineError::Interrupted) => {
                 println!("Terminated");
@@ -43,6 +145,10 @@ under certain conditions; type `show c' for details.");
             }
         }
     }
+
+    if let Err(err) = rl.save_history(&history_path) {
+        eprintln!("Failed to save history: {}", err);
+    }
 }
 
 /*#[macro_use]
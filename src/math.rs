@@ -0,0 +1,82 @@
+//! Math channels: values computed from other channels by an expression,
+//! such as boost in psi from manifold and barometric pressure
+
+use serde::Deserialize;
+
+use crate::{
+	error::{Error, Result},
+	expr::Expr,
+};
+
+
+
+/// A math channel as written in the configuration
+#[derive(Debug, Clone, Deserialize)]
+pub struct MathChannel {
+	pub name: String,
+	#[serde(default)]
+	pub unit: String,
+	/// Expression over other channels by name, e.g.
+	/// "([Intake manifold pressure] - [Barometric pressure]) * 0.145".
+	/// Math channels may use math channels defined before them and `time`,
+	/// the time of the sample in seconds.
+	pub expression: String,
+}
+
+impl MathChannel {
+	pub fn parse(&self) -> Result<Expr> {
+		Expr::parse(&self.expression)
+	}
+}
+
+/// Returns true if `name` refers to the sample time rather than a channel
+pub fn is_time(name: &str) -> bool {
+	name.eq_ignore_ascii_case("time")
+}
+
+/// Checks that none of `variables`, used by the math channel at `index`,
+/// names a math channel defined at or after it
+pub fn check_order(channels: &[MathChannel], index: usize, variables: &[&str]) -> Result<()> {
+	match variables.iter().find(|name| find(&channels[index..], name).is_some()) {
+		Some(name) => Err(Error::MathOrder { channel: channels[index].name.clone(), name: (*name).to_owned() }),
+		None => Ok(()),
+	}
+}
+
+/// Returns the math channel named `name`, ignoring case
+pub fn find<'a>(channels: &'a [MathChannel], name: &str) -> Option<&'a MathChannel> {
+	channels.iter().find(|channel| channel.name.eq_ignore_ascii_case(name))
+}
+
+/// Returns the math channels needed to compute the math channels named
+/// `selected`, in the order they were defined, and the names of the other
+/// channels they use.
+pub fn resolve<'a>(channels: &'a [MathChannel], selected: &[&str]) -> Result<(Vec<&'a MathChannel>, Vec<String>)> {
+	let mut needed = vec![false; channels.len()];
+	let mut inputs: Vec<String> = Vec::new();
+	let mut pending: Vec<usize> = selected.iter()
+		.filter_map(|name| channels.iter().position(|channel| channel.name.eq_ignore_ascii_case(name)))
+		.collect();
+
+	while let Some(index) = pending.pop() {
+		if needed[index] {
+			continue;
+		}
+		needed[index] = true;
+		let expression = channels[index].parse()?;
+		let variables = expression.variables();
+		// Only earlier definitions can be used, which rules out cycles
+		check_order(channels, index, &variables)?;
+		for name in variables.into_iter().filter(|name| !is_time(name)) {
+			match channels[..index].iter().position(|channel| channel.name.eq_ignore_ascii_case(name)) {
+				Some(dependency) => pending.push(dependency),
+				None => if !inputs.iter().any(|input| input.eq_ignore_ascii_case(name)) {
+					inputs.push(name.to_owned());
+				},
+			}
+		}
+	}
+
+	let needed = channels.iter().zip(needed).filter(|&(_, needed)| needed).map(|(channel, _)| channel).collect();
+	Ok((needed, inputs))
+}
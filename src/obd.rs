@@ -0,0 +1,282 @@
+//! Standard OBD-II (SAE J1979) parameter IDs and their formulas
+
+use tuneutils::protocols::uds::UdsInterface;
+
+use crate::{
+	error::{Error, Result},
+	uds,
+};
+
+
+
+/// ID of the built-in generic OBD-II platform. It reads the standard PIDs
+/// of any OBD-II vehicle without a platform definition.
+pub const PLATFORM_ID: &str = "obd2";
+pub const PLATFORM_NAME: &str = "Generic OBD-II (SAE J1979)";
+
+
+
+/// A standard mode 01 / mode 02 PID
+pub struct ObdPid {
+	pub pid: u8,
+	pub name: &'static str,
+	pub unit: &'static str,
+	/// Number of data bytes in the response
+	pub bytes: usize,
+	formula: fn(&[u8]) -> f64,
+}
+
+impl ObdPid {
+	/// Decodes the data bytes of a response. Returns `None` if there are
+	/// too few bytes.
+	pub fn decode(&self, data: &[u8]) -> Option<f64> {
+		if data.len() < self.bytes {
+			return None;
+		}
+		Some((self.formula)(&data[..self.bytes]))
+	}
+}
+
+fn a(d: &[u8]) -> f64 {
+	f64::from(d[0])
+}
+
+fn ab(d: &[u8]) -> f64 {
+	f64::from(d[0]) * 256.0 + f64::from(d[1])
+}
+
+fn percent(d: &[u8]) -> f64 {
+	a(d) * 100.0 / 255.0
+}
+
+fn temperature(d: &[u8]) -> f64 {
+	a(d) - 40.0
+}
+
+fn fuel_trim(d: &[u8]) -> f64 {
+	(a(d) - 128.0) * 100.0 / 128.0
+}
+
+fn lambda(d: &[u8]) -> f64 {
+	ab(d) * 2.0 / 65536.0
+}
+
+fn fuel_pressure(d: &[u8]) -> f64 {
+	a(d) * 3.0
+}
+
+fn engine_speed(d: &[u8]) -> f64 {
+	ab(d) / 4.0
+}
+
+fn timing_advance(d: &[u8]) -> f64 {
+	a(d) / 2.0 - 64.0
+}
+
+fn air_flow(d: &[u8]) -> f64 {
+	ab(d) / 100.0
+}
+
+fn o2_voltage(d: &[u8]) -> f64 {
+	a(d) / 200.0
+}
+
+fn rail_pressure(d: &[u8]) -> f64 {
+	ab(d) * 0.079
+}
+
+fn rail_gauge_pressure(d: &[u8]) -> f64 {
+	ab(d) * 10.0
+}
+
+fn catalyst_temperature(d: &[u8]) -> f64 {
+	ab(d) / 10.0 - 40.0
+}
+
+fn voltage(d: &[u8]) -> f64 {
+	ab(d) / 1000.0
+}
+
+fn absolute_load(d: &[u8]) -> f64 {
+	ab(d) * 100.0 / 255.0
+}
+
+fn fuel_rate(d: &[u8]) -> f64 {
+	ab(d) / 20.0
+}
+
+const PIDS: &[ObdPid] = &[
+	ObdPid { pid: 0x04, name: "Calculated engine load", unit: "%", bytes: 1, formula: percent },
+	ObdPid { pid: 0x05, name: "Engine coolant temperature", unit: "°C", bytes: 1, formula: temperature },
+	ObdPid { pid: 0x06, name: "Short term fuel trim bank 1", unit: "%", bytes: 1, formula: fuel_trim },
+	ObdPid { pid: 0x07, name: "Long term fuel trim bank 1", unit: "%", bytes: 1, formula: fuel_trim },
+	ObdPid { pid: 0x08, name: "Short term fuel trim bank 2", unit: "%", bytes: 1, formula: fuel_trim },
+	ObdPid { pid: 0x09, name: "Long term fuel trim bank 2", unit: "%", bytes: 1, formula: fuel_trim },
+	ObdPid { pid: 0x0A, name: "Fuel pressure", unit: "kPa", bytes: 1, formula: fuel_pressure },
+	ObdPid { pid: 0x0B, name: "Intake manifold pressure", unit: "kPa", bytes: 1, formula: a },
+	ObdPid { pid: 0x0C, name: "Engine speed", unit: "rpm", bytes: 2, formula: engine_speed },
+	ObdPid { pid: 0x0D, name: "Vehicle speed", unit: "km/h", bytes: 1, formula: a },
+	ObdPid { pid: 0x0E, name: "Timing advance", unit: "°", bytes: 1, formula: timing_advance },
+	ObdPid { pid: 0x0F, name: "Intake air temperature", unit: "°C", bytes: 1, formula: temperature },
+	ObdPid { pid: 0x10, name: "Mass air flow", unit: "g/s", bytes: 2, formula: air_flow },
+	ObdPid { pid: 0x11, name: "Throttle position", unit: "%", bytes: 1, formula: percent },
+	ObdPid { pid: 0x14, name: "O2 sensor bank 1 sensor 1 voltage", unit: "V", bytes: 2, formula: o2_voltage },
+	ObdPid { pid: 0x15, name: "O2 sensor bank 1 sensor 2 voltage", unit: "V", bytes: 2, formula: o2_voltage },
+	ObdPid { pid: 0x1F, name: "Run time since engine start", unit: "s", bytes: 2, formula: ab },
+	ObdPid { pid: 0x21, name: "Distance traveled with MIL on", unit: "km", bytes: 2, formula: ab },
+	ObdPid { pid: 0x22, name: "Fuel rail pressure (relative to manifold)", unit: "kPa", bytes: 2, formula: rail_pressure },
+	ObdPid { pid: 0x23, name: "Fuel rail gauge pressure", unit: "kPa", bytes: 2, formula: rail_gauge_pressure },
+	ObdPid { pid: 0x24, name: "O2 sensor bank 1 sensor 1 lambda", unit: "λ", bytes: 4, formula: lambda },
+	ObdPid { pid: 0x2C, name: "Commanded EGR", unit: "%", bytes: 1, formula: percent },
+	ObdPid { pid: 0x2F, name: "Fuel tank level", unit: "%", bytes: 1, formula: percent },
+	ObdPid { pid: 0x31, name: "Distance traveled since codes cleared", unit: "km", bytes: 2, formula: ab },
+	ObdPid { pid: 0x33, name: "Barometric pressure", unit: "kPa", bytes: 1, formula: a },
+	ObdPid { pid: 0x3C, name: "Catalyst temperature bank 1 sensor 1", unit: "°C", bytes: 2, formula: catalyst_temperature },
+	ObdPid { pid: 0x42, name: "Control module voltage", unit: "V", bytes: 2, formula: voltage },
+	ObdPid { pid: 0x43, name: "Absolute load", unit: "%", bytes: 2, formula: absolute_load },
+	ObdPid { pid: 0x44, name: "Commanded air-fuel equivalence ratio", unit: "λ", bytes: 2, formula: lambda },
+	ObdPid { pid: 0x45, name: "Relative throttle position", unit: "%", bytes: 1, formula: percent },
+	ObdPid { pid: 0x46, name: "Ambient air temperature", unit: "°C", bytes: 1, formula: temperature },
+	ObdPid { pid: 0x47, name: "Absolute throttle position B", unit: "%", bytes: 1, formula: percent },
+	ObdPid { pid: 0x49, name: "Accelerator pedal position D", unit: "%", bytes: 1, formula: percent },
+	ObdPid { pid: 0x4A, name: "Accelerator pedal position E", unit: "%", bytes: 1, formula: percent },
+	ObdPid { pid: 0x4C, name: "Commanded throttle actuator", unit: "%", bytes: 1, formula: percent },
+	ObdPid { pid: 0x5C, name: "Engine oil temperature", unit: "°C", bytes: 1, formula: temperature },
+	ObdPid { pid: 0x5E, name: "Engine fuel rate", unit: "L/h", bytes: 2, formula: fuel_rate },
+];
+
+/// Looks up a standard PID
+pub fn pid(pid: u8) -> Option<&'static ObdPid> {
+	PIDS.iter().find(|obd| obd.pid == pid)
+}
+
+/// Returns all standard PIDs with known formulas
+pub fn pids() -> &'static [ObdPid] {
+	PIDS
+}
+
+/// Monitors in byte C and D of PID 01 on spark ignition engines, from bit 0
+const SPARK_MONITORS: [&str; 8] = [
+	"Catalyst",
+	"Heated catalyst",
+	"Evaporative system",
+	"Secondary air system",
+	"A/C refrigerant",
+	"Oxygen sensor",
+	"Oxygen sensor heater",
+	"EGR system",
+];
+
+/// Monitors in byte C and D of PID 01 on compression ignition engines
+const COMPRESSION_MONITORS: [&str; 8] = [
+	"NMHC catalyst",
+	"NOx/SCR monitor",
+	"",
+	"Boost pressure",
+	"",
+	"Exhaust gas sensor",
+	"PM filter",
+	"EGR/VVT system",
+];
+
+/// Readiness of an emissions monitor
+pub struct Monitor {
+	pub name: &'static str,
+	pub complete: bool,
+}
+
+/// Monitor status from PID 01 (since codes were cleared) or PID 41 (this
+/// drive cycle)
+pub struct MonitorStatus {
+	/// Malfunction indicator lamp. Only reported by PID 01.
+	pub mil: bool,
+	/// Number of confirmed codes. Only reported by PID 01.
+	pub dtc_count: u8,
+	/// True for compression ignition (diesel) engines. Only reported by
+	/// PID 01.
+	pub compression_ignition: bool,
+	/// Supported monitors
+	pub monitors: Vec<Monitor>,
+}
+
+impl MonitorStatus {
+	/// Decodes the four data bytes of PID 01
+	pub fn decode(data: &[u8]) -> Option<MonitorStatus> {
+		let compression_ignition = data.get(1)? & 0x08 != 0;
+		MonitorStatus::decode_monitors(data, compression_ignition)
+	}
+
+	/// Decodes the four data bytes of PID 41. The engine type bit is
+	/// reserved in PID 41, so it is taken from the PID 01 status.
+	pub fn decode_cycle(data: &[u8], since_clear: &MonitorStatus) -> Option<MonitorStatus> {
+		MonitorStatus::decode_monitors(data, since_clear.compression_ignition)
+	}
+
+	fn decode_monitors(data: &[u8], compression_ignition: bool) -> Option<MonitorStatus> {
+		if data.len() < 4 {
+			return None;
+		}
+		let (a, b, c, d) = (data[0], data[1], data[2], data[3]);
+
+		// Byte B: continuous monitors, supported in bits 0-2 and incomplete in bits 4-6
+		let mut monitors = Vec::new();
+		for (bit, name) in ["Misfire", "Fuel system", "Comprehensive components"].iter().enumerate() {
+			if b & (1 << bit) != 0 {
+				monitors.push(Monitor { name: *name, complete: b & (0x10 << bit) == 0 });
+			}
+		}
+
+		// Bytes C and D: supported and incomplete non-continuous monitors
+		let names = if compression_ignition { &COMPRESSION_MONITORS } else { &SPARK_MONITORS };
+		for (bit, name) in names.iter().enumerate() {
+			if c & (1 << bit) != 0 && !name.is_empty() {
+				monitors.push(Monitor { name: *name, complete: d & (1 << bit) == 0 });
+			}
+		}
+
+		Some(MonitorStatus {
+			mil: a & 0x80 != 0,
+			dtc_count: a & 0x7F,
+			compression_ignition,
+			monitors,
+		})
+	}
+}
+
+/// Reads the PIDs an ECU supports for `sid` (0x01 for current data or 0x02
+/// for freeze frame 0) from the bitmask PIDs 00, 20, 40 and so on
+pub fn supported_pids(interface: &UdsInterface, sid: u8) -> Result<Vec<u8>> {
+	let mut supported = Vec::new();
+	let mut base = 0u8;
+	loop {
+		let mut request = vec![base];
+		if sid == 0x02 {
+			request.push(0x00);
+		}
+		let response = match uds::request(interface, sid, &request) {
+			Ok(response) => response,
+			// The ECU supports no PIDs in this range
+			Err(Error::NegativeResponse { .. }) => break,
+			Err(err) => return Err(err),
+		};
+		// The response repeats the PID (and frame for mode 02) before the mask
+		let mask = &response[request.len().min(response.len())..];
+		if mask.len() < 4 {
+			break;
+		}
+		let mask = mask[..4].iter().fold(0u32, |acc, b| (acc << 8) | u32::from(*b));
+		for bit in 1..=0x20u32 {
+			let pid = u32::from(base) + bit;
+			// Bitmask PIDs are not values
+			if mask & (1 << (0x20 - bit)) != 0 && pid % 0x20 != 0 {
+				supported.push(pid as u8);
+			}
+		}
+		// The last bit of each mask flags the next range
+		if mask & 1 == 0 || base == 0xE0 {
+			break;
+		}
+		base += 0x20;
+	}
+	Ok(supported)
+}
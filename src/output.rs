@@ -0,0 +1,246 @@
+#![cfg(feature = "cli")]
+
+use std::{
+	cmp::max,
+	fmt,
+	io::{self, Write},
+	str::FromStr,
+};
+
+use serde_json::{Map, Value};
+
+use crate::error::{Error, Result};
+
+
+
+/// Format used when printing listings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+	/// Aligned columns for humans
+	Text,
+	/// An array of objects, one per row
+	Json,
+	/// Comma-separated values with a header row
+	Csv,
+}
+
+impl Default for Format {
+	fn default() -> Format {
+		Format::Text
+	}
+}
+
+impl FromStr for Format {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Format> {
+		match s.to_lowercase().as_str() {
+			"text" => Ok(Format::Text),
+			"json" => Ok(Format::Json),
+			"csv" => Ok(Format::Csv),
+			_ => Err(Error::InvalidFormat(s.to_owned())),
+		}
+	}
+}
+
+impl fmt::Display for Format {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Format::Text => write!(f, "text"),
+			Format::Json => write!(f, "json"),
+			Format::Csv => write!(f, "csv"),
+		}
+	}
+}
+
+
+
+/// A column of a `Table`
+struct Column {
+	/// Key used for JSON objects and the CSV header
+	key: &'static str,
+	/// Heading used for text output
+	title: &'static str,
+}
+
+/// Tabular data that can be printed in any `Format`
+pub struct Table {
+	columns: Vec<Column>,
+	rows: Vec<Vec<Value>>,
+}
+
+impl Table {
+	/// Creates an empty table.
+	///
+	/// # Arguments
+	/// `columns` - List of (key, title) pairs. The key is used in JSON and
+	/// CSV output and the title is used in text output.
+	pub fn new(columns: &[(&'static str, &'static str)]) -> Table {
+		Table {
+			columns: columns.iter().map(|&(key, title)| Column { key, title }).collect(),
+			rows: Vec::new(),
+		}
+	}
+
+	/// Appends a row. Each value must correspond to a column.
+	pub fn add_row(&mut self, row: Vec<Value>) {
+		debug_assert_eq!(row.len(), self.columns.len());
+		self.rows.push(row);
+	}
+
+	/// Prints the table to stdout
+	pub fn print(&self, format: Format) -> Result<()> {
+		let stdout = io::stdout();
+		let mut out = stdout.lock();
+		self.write(&mut out, format)?;
+		Ok(())
+	}
+
+	/// Writes the table in the given format
+	pub fn write<W: Write>(&self, out: &mut W, format: Format) -> io::Result<()> {
+		match format {
+			Format::Text => self.write_text(out),
+			Format::Json => self.write_json(out),
+			Format::Csv => self.write_csv(out),
+		}
+	}
+
+	fn write_text<W: Write>(&self, out: &mut W) -> io::Result<()> {
+		let cells: Vec<Vec<String>> = self.rows.iter().map(|row| row.iter().map(cell_string).collect()).collect();
+
+		// Find the widest value of each column
+		let mut widths: Vec<usize> = self.columns.iter().map(|column| column.title.len()).collect();
+		for row in cells.iter() {
+			for (width, cell) in widths.iter_mut().zip(row.iter()) {
+				*width = max(*width, cell.chars().count());
+			}
+		}
+
+		let titles: Vec<String> = self.columns.iter().map(|column| column.title.to_owned()).collect();
+		write_text_row(out, &titles, &widths)?;
+		for row in cells.iter() {
+			write_text_row(out, row, &widths)?;
+		}
+		Ok(())
+	}
+
+	fn write_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+		let rows: Vec<Value> = self.rows.iter().map(|row| {
+			let mut object = Map::new();
+			for (column, value) in self.columns.iter().zip(row.iter()) {
+				object.insert(column.key.to_owned(), value.clone());
+			}
+			Value::Object(object)
+		}).collect();
+
+		serde_json::to_writer_pretty(&mut *out, &rows)?;
+		writeln!(out)
+	}
+
+	fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+		let keys: Vec<String> = self.columns.iter().map(|column| column.key.to_owned()).collect();
+		write_csv_row(out, &keys)?;
+		for row in self.rows.iter() {
+			let cells: Vec<String> = row.iter().map(cell_string).collect();
+			write_csv_row(out, &cells)?;
+		}
+		Ok(())
+	}
+}
+
+
+
+/// Converts a value to the string shown in text and CSV output
+fn cell_string(value: &Value) -> String {
+	match *value {
+		Value::String(ref s) => s.clone(),
+		Value::Null => String::new(),
+		ref other => other.to_string(),
+	}
+}
+
+fn write_text_row<W: Write>(out: &mut W, cells: &[String], widths: &[usize]) -> io::Result<()> {
+	let last = cells.len().saturating_sub(1);
+	for (i, (cell, width)) in cells.iter().zip(widths.iter()).enumerate() {
+		if i == last {
+			// Don't pad the last column
+			write!(out, "{}", cell)?;
+		} else {
+			write!(out, "{1:<0$}   ", width, cell)?;
+		}
+	}
+	writeln!(out)
+}
+
+/// Writes a CSV row, quoting fields as described in RFC 4180
+pub fn write_csv_row<W: Write, S: AsRef<str>>(out: &mut W, cells: &[S]) -> io::Result<()> {
+	for (i, cell) in cells.iter().enumerate() {
+		if i != 0 {
+			write!(out, ",")?;
+		}
+		let cell = cell.as_ref();
+		if cell.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+			write!(out, "\"{}\"", cell.replace('"', "\"\""))?;
+		} else {
+			write!(out, "{}", cell)?;
+		}
+	}
+	writeln!(out)
+}
+
+/// Splits CSV text into records at line breaks outside quoted cells.
+/// Returns each record with the number of the line it starts on. Line
+/// breaks in quoted cells are kept and the record terminators are removed.
+pub fn csv_records(text: &str) -> Vec<(usize, &str)> {
+	let mut records = Vec::new();
+	let mut start = 0;
+	let mut number = 1;
+	let mut line = 1;
+	let mut quoted = false;
+
+	for (index, c) in text.char_indices() {
+		match c {
+			'"' => quoted = !quoted,
+			'\n' => {
+				line += 1;
+				if !quoted {
+					records.push((number, text[start..index].trim_end_matches('\r')));
+					start = index + 1;
+					number = line;
+				}
+			},
+			_ => (),
+		}
+	}
+	if start < text.len() {
+		records.push((number, text[start..].trim_end_matches('\r')));
+	}
+	records
+}
+
+/// Splits a CSV row written by `write_csv_row` into cells
+pub fn parse_csv_row(line: &str) -> Vec<String> {
+	let mut cells = Vec::new();
+	let mut cell = String::new();
+	let mut quoted = false;
+	let mut chars = line.chars().peekable();
+
+	while let Some(c) = chars.next() {
+		match c {
+			'"' if quoted => {
+				// A doubled quote is a literal quote
+				if chars.peek() == Some(&'"') {
+					cell.push('"');
+					chars.next();
+				} else {
+					quoted = false;
+				}
+			},
+			'"' if cell.is_empty() => quoted = true,
+			',' if !quoted => cells.push(std::mem::replace(&mut cell, String::new())),
+			c => cell.push(c),
+		}
+	}
+	cells.push(cell);
+	cells
+}
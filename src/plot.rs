@@ -0,0 +1,354 @@
+//! Charts of recorded values, drawn as text, SVG or PNG. Each series gets its
+//! own chart and all charts share the time axis.
+
+use std::io::{self, Write};
+
+/// Width of the value labels left of text charts
+const LABEL_WIDTH: usize = 10;
+
+/// Margins of SVG charts in pixels
+const SVG_LEFT: f64 = 70.0;
+const SVG_RIGHT: f64 = 20.0;
+const SVG_TOP: f64 = 24.0;
+const SVG_BOTTOM: f64 = 30.0;
+/// Line colors of SVG and PNG series
+const SVG_COLORS: &[&str] = &["#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b"];
+
+/// Values of a channel over time
+#[derive(Debug, Clone)]
+pub struct Series {
+	pub name: String,
+	pub unit: String,
+	/// (time in seconds, value) pairs in time order
+	pub points: Vec<(f64, f64)>,
+}
+
+impl Series {
+	fn title(&self) -> String {
+		if self.unit.is_empty() {
+			self.name.clone()
+		} else {
+			format!("{} [{}]", self.name, self.unit)
+		}
+	}
+
+	/// Returns the lowest and highest value, widened if they are equal
+	fn value_range(&self) -> (f64, f64) {
+		let low = self.points.iter().map(|&(_, value)| value).fold(::std::f64::INFINITY, f64::min);
+		let high = self.points.iter().map(|&(_, value)| value).fold(::std::f64::NEG_INFINITY, f64::max);
+		if !low.is_finite() || !high.is_finite() {
+			(0.0, 1.0)
+		} else if high > low {
+			(low, high)
+		} else {
+			(low - 0.5, high + 0.5)
+		}
+	}
+}
+
+/// Returns the first and last time of all series, widened if they are equal
+fn time_range(series: &[Series]) -> (f64, f64) {
+	let times = series.iter().flat_map(|series| series.points.iter().map(|&(time, _)| time));
+	let (start, end) = times.fold((::std::f64::INFINITY, ::std::f64::NEG_INFINITY), |(start, end), time| (start.min(time), end.max(time)));
+	if !start.is_finite() || !end.is_finite() {
+		(0.0, 1.0)
+	} else if end > start {
+		(start, end)
+	} else {
+		(start, start + 1.0)
+	}
+}
+
+
+
+/// Draws the series as text charts of `width` by `height` characters
+pub fn text(out: &mut Write, series: &[Series], width: usize, height: usize) -> io::Result<()> {
+	let (start, end) = time_range(series);
+	let width = width.max(2);
+	let height = height.max(2);
+
+	for series in series.iter() {
+		writeln!(out, "{}", series.title())?;
+		let (low, high) = series.value_range();
+
+		// Each column holds the lowest and highest value in its time span
+		let mut columns: Vec<Option<(f64, f64)>> = vec![None; width];
+		for &(time, value) in series.points.iter() {
+			let column = ((time - start) / (end - start) * (width - 1) as f64).round() as usize;
+			columns[column] = Some(match columns[column] {
+				Some((lowest, highest)) => (lowest.min(value), highest.max(value)),
+				None => (value, value),
+			});
+		}
+
+		// Row 0 is the bottom of the chart
+		let row = |value: f64| ((value - low) / (high - low) * (height - 1) as f64).round() as usize;
+		for line in (0..height).rev() {
+			let label = if line == height - 1 {
+				format!("{:>1$.2}", high, LABEL_WIDTH)
+			} else if line == 0 {
+				format!("{:>1$.2}", low, LABEL_WIDTH)
+			} else {
+				" ".repeat(LABEL_WIDTH)
+			};
+			let cells: String = columns.iter().map(|column| match *column {
+				Some((lowest, highest)) if row(lowest) <= line && line <= row(highest) => '•',
+				_ => ' ',
+			}).collect();
+			writeln!(out, "{} │{}", label, cells.trim_end())?;
+		}
+
+		writeln!(out, "{} └{}", " ".repeat(LABEL_WIDTH), "─".repeat(width))?;
+		let start_label = format!("{:.1}s", start);
+		writeln!(out, "{0}  {1}{3:>2$}", " ".repeat(LABEL_WIDTH), start_label, width - start_label.len().min(width), format!("{:.1}s", end))?;
+		writeln!(out)?;
+	}
+	Ok(())
+}
+
+
+
+/// Draws the series as an SVG image of `width` by `height` pixels
+pub fn svg(out: &mut Write, series: &[Series], width: u32, height: u32) -> io::Result<()> {
+	let (start, end) = time_range(series);
+	let (width, height) = (f64::from(width), f64::from(height));
+	let panel_height = (height - SVG_BOTTOM) / series.len().max(1) as f64;
+	let x = |time: f64| SVG_LEFT + (time - start) / (end - start) * (width - SVG_LEFT - SVG_RIGHT);
+
+	writeln!(out, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\" font-family=\"sans-serif\" font-size=\"12\">", width, height)?;
+	writeln!(out, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>")?;
+
+	for (index, series) in series.iter().enumerate() {
+		let top = index as f64 * panel_height + SVG_TOP;
+		let bottom = (index + 1) as f64 * panel_height;
+		let (low, high) = series.value_range();
+		let y = |value: f64| bottom - (value - low) / (high - low) * (bottom - top);
+		let color = SVG_COLORS[index % SVG_COLORS.len()];
+
+		writeln!(out, "<text x=\"{}\" y=\"{}\" font-weight=\"bold\">{}</text>", SVG_LEFT, top - 8.0, escape(&series.title()))?;
+		writeln!(out, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"#999\"/>", SVG_LEFT, top, width - SVG_LEFT - SVG_RIGHT, bottom - top)?;
+		writeln!(out, "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{:.2}</text>", SVG_LEFT - 6.0, top + 12.0, high)?;
+		writeln!(out, "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{:.2}</text>", SVG_LEFT - 6.0, bottom, low)?;
+
+		let points: Vec<String> = series.points.iter()
+			.map(|&(time, value)| format!("{:.1},{:.1}", x(time), y(value)))
+			.collect();
+		writeln!(out, "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{}\"/>", color, points.join(" "))?;
+	}
+
+	let axis = height - SVG_BOTTOM + 16.0;
+	writeln!(out, "<text x=\"{}\" y=\"{}\">{:.1}s</text>", SVG_LEFT, axis, start)?;
+	writeln!(out, "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{:.1}s</text>", width - SVG_RIGHT, axis, end)?;
+	writeln!(out, "</svg>")
+}
+
+/// Digits of PNG labels, 3 pixels wide and 5 high. Each row is 3 bits with
+/// the leftmost pixel in the highest bit.
+const PNG_GLYPHS: &[(char, [u8; 5])] = &[
+	('0', [7, 5, 5, 5, 7]), ('1', [2, 6, 2, 2, 7]), ('2', [7, 1, 7, 4, 7]), ('3', [7, 1, 7, 1, 7]),
+	('4', [5, 5, 7, 1, 1]), ('5', [7, 4, 7, 1, 7]), ('6', [7, 4, 7, 5, 7]), ('7', [7, 1, 1, 1, 1]),
+	('8', [7, 5, 7, 5, 7]), ('9', [7, 5, 7, 1, 7]), ('.', [0, 0, 0, 0, 2]), ('-', [0, 0, 7, 0, 0]),
+	('s', [0, 3, 6, 3, 6]),
+];
+/// Scale of PNG labels
+const PNG_GLYPH_SCALE: i64 = 2;
+
+/// RGB image drawn in software
+struct Canvas {
+	width: i64,
+	height: i64,
+	pixels: Vec<u8>,
+}
+
+impl Canvas {
+	fn new(width: u32, height: u32) -> Canvas {
+		Canvas {
+			width: i64::from(width),
+			height: i64::from(height),
+			pixels: vec![0xFF; width as usize * height as usize * 3],
+		}
+	}
+
+	fn set(&mut self, x: i64, y: i64, color: [u8; 3]) {
+		if x >= 0 && y >= 0 && x < self.width && y < self.height {
+			let offset = ((y * self.width + x) * 3) as usize;
+			self.pixels[offset..offset + 3].copy_from_slice(&color);
+		}
+	}
+
+	/// Draws a line with Bresenham's algorithm
+	fn line(&mut self, (mut x0, mut y0): (i64, i64), (x1, y1): (i64, i64), color: [u8; 3]) {
+		let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+		let (sx, sy) = (if x0 < x1 { 1 } else { -1 }, if y0 < y1 { 1 } else { -1 });
+		let mut error = dx + dy;
+		loop {
+			self.set(x0, y0, color);
+			if x0 == x1 && y0 == y1 {
+				break;
+			}
+			let doubled = 2 * error;
+			if doubled >= dy {
+				error += dy;
+				x0 += sx;
+			}
+			if doubled <= dx {
+				error += dx;
+				y0 += sy;
+			}
+		}
+	}
+
+	fn rect(&mut self, left: i64, top: i64, right: i64, bottom: i64, color: [u8; 3]) {
+		self.line((left, top), (right, top), color);
+		self.line((right, top), (right, bottom), color);
+		self.line((right, bottom), (left, bottom), color);
+		self.line((left, bottom), (left, top), color);
+	}
+
+	/// Draws a label of digits with its top left corner at `x`, `y`.
+	/// Characters without a glyph are left blank.
+	fn label(&mut self, x: i64, y: i64, text: &str, color: [u8; 3]) {
+		for (index, c) in text.chars().enumerate() {
+			let rows = match PNG_GLYPHS.iter().find(|&&(glyph, _)| glyph == c) {
+				Some(&(_, rows)) => rows,
+				None => continue,
+			};
+			let left = x + index as i64 * 4 * PNG_GLYPH_SCALE;
+			for (row, bits) in rows.iter().enumerate() {
+				for column in 0..3 {
+					if bits & (4 >> column) == 0 {
+						continue;
+					}
+					for dy in 0..PNG_GLYPH_SCALE {
+						for dx in 0..PNG_GLYPH_SCALE {
+							self.set(left + column * PNG_GLYPH_SCALE + dx, y + row as i64 * PNG_GLYPH_SCALE + dy, color);
+						}
+					}
+				}
+			}
+		}
+	}
+
+	/// Returns the width of a label in pixels
+	fn label_width(text: &str) -> i64 {
+		(text.chars().count() as i64 * 4 - 1).max(0) * PNG_GLYPH_SCALE
+	}
+
+	/// Encodes the image as a PNG with uncompressed deflate blocks
+	fn encode(&self, out: &mut Write) -> io::Result<()> {
+		// Each scanline starts with filter type 0
+		let row = self.width as usize * 3;
+		let mut raw = Vec::with_capacity((row + 1) * self.height as usize);
+		for line in self.pixels.chunks(row) {
+			raw.push(0);
+			raw.extend_from_slice(line);
+		}
+
+		let mut zlib = vec![0x78, 0x01];
+		let blocks = raw.chunks(0xFFFF).count();
+		for (index, block) in raw.chunks(0xFFFF).enumerate() {
+			let len = block.len() as u16;
+			zlib.push(if index + 1 == blocks { 1 } else { 0 });
+			zlib.extend_from_slice(&len.to_le_bytes());
+			zlib.extend_from_slice(&(!len).to_le_bytes());
+			zlib.extend_from_slice(block);
+		}
+		zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+		let mut header = Vec::with_capacity(13);
+		header.extend_from_slice(&(self.width as u32).to_be_bytes());
+		header.extend_from_slice(&(self.height as u32).to_be_bytes());
+		// 8 bit RGB, no interlacing
+		header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+		out.write_all(b"\x89PNG\r\n\x1a\n")?;
+		png_chunk(out, b"IHDR", &header)?;
+		png_chunk(out, b"IDAT", &zlib)?;
+		png_chunk(out, b"IEND", &[])
+	}
+}
+
+fn png_chunk(out: &mut Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+	out.write_all(&(data.len() as u32).to_be_bytes())?;
+	out.write_all(kind)?;
+	out.write_all(data)?;
+	let mut crc = crc32(!0, kind);
+	crc = crc32(crc, data);
+	out.write_all(&(!crc).to_be_bytes())
+}
+
+/// Updates a CRC-32 as used by PNG chunks
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+	for &byte in data {
+		crc ^= u32::from(byte);
+		for _ in 0..8 {
+			crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+		}
+	}
+	crc
+}
+
+/// Returns the Adler-32 checksum that ends zlib streams
+fn adler32(data: &[u8]) -> u32 {
+	let (mut a, mut b) = (1u32, 0u32);
+	for &byte in data {
+		a = (a + u32::from(byte)) % 65521;
+		b = (b + a) % 65521;
+	}
+	(b << 16) | a
+}
+
+/// Parses a `#rrggbb` color
+fn rgb(color: &str) -> [u8; 3] {
+	let channel = |index: usize| u8::from_str_radix(&color[1 + index * 2..3 + index * 2], 16).unwrap_or(0);
+	[channel(0), channel(1), channel(2)]
+}
+
+
+
+/// Draws the series as a PNG image of `width` by `height` pixels with the
+/// layout of `svg`. Titles are not drawn; value and time labels are.
+pub fn png(out: &mut Write, series: &[Series], width: u32, height: u32) -> io::Result<()> {
+	const FRAME: [u8; 3] = [0x99, 0x99, 0x99];
+	const TEXT: [u8; 3] = [0x00, 0x00, 0x00];
+
+	let (start, end) = time_range(series);
+	let (width, height) = (width.max(1), height.max(1));
+	let mut canvas = Canvas::new(width, height);
+	let (width, height) = (f64::from(width), f64::from(height));
+	let panel_height = (height - SVG_BOTTOM) / series.len().max(1) as f64;
+	let x = |time: f64| (SVG_LEFT + (time - start) / (end - start) * (width - SVG_LEFT - SVG_RIGHT)).round() as i64;
+	let right = (width - SVG_RIGHT).round() as i64;
+
+	for (index, series) in series.iter().enumerate() {
+		let top = index as f64 * panel_height + SVG_TOP;
+		let bottom = (index + 1) as f64 * panel_height;
+		let (low, high) = series.value_range();
+		let y = |value: f64| (bottom - (value - low) / (high - low) * (bottom - top)).round() as i64;
+		let color = rgb(SVG_COLORS[index % SVG_COLORS.len()]);
+
+		canvas.rect(SVG_LEFT as i64, top.round() as i64, right, bottom.round() as i64, FRAME);
+		let (high, low) = (format!("{:.2}", high), format!("{:.2}", low));
+		canvas.label(SVG_LEFT as i64 - 6 - Canvas::label_width(&high), top.round() as i64, &high, TEXT);
+		canvas.label(SVG_LEFT as i64 - 6 - Canvas::label_width(&low), bottom.round() as i64 - 5 * PNG_GLYPH_SCALE, &low, TEXT);
+
+		let points: Vec<(i64, i64)> = series.points.iter().map(|&(time, value)| (x(time), y(value))).collect();
+		for pair in points.windows(2) {
+			canvas.line(pair[0], pair[1], color);
+		}
+		if points.len() == 1 {
+			canvas.set(points[0].0, points[0].1, color);
+		}
+	}
+
+	let axis = (height - SVG_BOTTOM).round() as i64 + 6;
+	let (start, end) = (format!("{:.1}s", start), format!("{:.1}s", end));
+	canvas.label(SVG_LEFT as i64, axis, &start, TEXT);
+	canvas.label(right - Canvas::label_width(&end), axis, &end, TEXT);
+	canvas.encode(out)
+}
+
+/// Escapes text for use in XML
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,115 @@
+//! UDS (ISO 14229) helpers: negative response codes and service names
+
+use tuneutils::protocols::uds::UdsInterface;
+
+use crate::error::{Error, Result};
+
+
+
+/// A negative response code
+pub struct Nrc {
+	pub code: u8,
+	/// Name used by ISO 14229
+	pub name: &'static str,
+	pub description: &'static str,
+	/// What the user can do about it
+	pub hint: Option<&'static str>,
+}
+
+const NRCS: &[Nrc] = &[
+	Nrc { code: 0x10, name: "generalReject", description: "The ECU rejected the request without a specific reason", hint: None },
+	Nrc { code: 0x11, name: "serviceNotSupported", description: "The ECU does not support this service", hint: Some("Check that the platform definition matches the vehicle") },
+	Nrc { code: 0x12, name: "subFunctionNotSupported", description: "The ECU does not support this sub-function", hint: Some("Check that the platform definition matches the vehicle") },
+	Nrc { code: 0x13, name: "incorrectMessageLengthOrInvalidFormat", description: "The request has the wrong length or format", hint: Some("Check the request data") },
+	Nrc { code: 0x14, name: "responseTooLong", description: "The response would exceed the transport's maximum length", hint: Some("Request less data at once") },
+	Nrc { code: 0x21, name: "busyRepeatRequest", description: "The ECU is busy", hint: Some("Wait a moment and try again") },
+	Nrc { code: 0x22, name: "conditionsNotCorrect", description: "The ECU is not in a state that allows this request", hint: Some("Turn the ignition on with the engine off, or enter the required diagnostic session") },
+	Nrc { code: 0x24, name: "requestSequenceError", description: "The request was sent out of order", hint: Some("Start the procedure from the beginning") },
+	Nrc { code: 0x25, name: "noResponseFromSubnetComponent", description: "A component behind the ECU did not respond", hint: None },
+	Nrc { code: 0x26, name: "failurePreventsExecutionOfRequestedAction", description: "A fault in the ECU prevents the request", hint: Some("Scan and fix trouble codes first") },
+	Nrc { code: 0x31, name: "requestOutOfRange", description: "A parameter, address or identifier is not supported", hint: Some("Check the identifier or address. The ECU may not support it in this session") },
+	Nrc { code: 0x33, name: "securityAccessDenied", description: "The ECU is locked", hint: Some("Unlock the ECU with SecurityAccess (0x27) in the correct session first") },
+	Nrc { code: 0x35, name: "invalidKey", description: "The security key is wrong", hint: Some("Check the platform's security key algorithm") },
+	Nrc { code: 0x36, name: "exceedNumberOfAttempts", description: "Too many failed security attempts", hint: Some("Cycle the ignition and wait before trying again") },
+	Nrc { code: 0x37, name: "requiredTimeDelayNotExpired", description: "The ECU requires a delay before another security attempt", hint: Some("Wait about 10 seconds, or cycle the ignition, and try again") },
+	Nrc { code: 0x70, name: "uploadDownloadNotAccepted", description: "The ECU refused the transfer", hint: Some("Check the address and size, and that the ECU is unlocked") },
+	Nrc { code: 0x71, name: "transferDataSuspended", description: "The transfer was stopped", hint: Some("Restart the transfer") },
+	Nrc { code: 0x72, name: "generalProgrammingFailure", description: "The ECU failed to erase or program memory", hint: None },
+	Nrc { code: 0x73, name: "wrongBlockSequenceCounter", description: "A transfer block was sent out of order", hint: Some("Restart the transfer") },
+	Nrc { code: 0x78, name: "requestCorrectlyReceivedResponsePending", description: "The ECU is still processing the request", hint: None },
+	Nrc { code: 0x7E, name: "subFunctionNotSupportedInActiveSession", description: "The sub-function is not available in the current diagnostic session", hint: Some("Enter the extended or programming session with DiagnosticSessionControl (0x10)") },
+	Nrc { code: 0x7F, name: "serviceNotSupportedInActiveSession", description: "The service is not available in the current diagnostic session", hint: Some("Enter the extended or programming session with DiagnosticSessionControl (0x10)") },
+	Nrc { code: 0x81, name: "rpmTooHigh", description: "Engine speed is too high", hint: Some("Let the engine idle or turn it off") },
+	Nrc { code: 0x82, name: "rpmTooLow", description: "Engine speed is too low", hint: Some("Start the engine") },
+	Nrc { code: 0x83, name: "engineIsRunning", description: "The engine must be off", hint: Some("Turn the engine off with the ignition on") },
+	Nrc { code: 0x84, name: "engineIsNotRunning", description: "The engine must be running", hint: Some("Start the engine") },
+	Nrc { code: 0x85, name: "engineRunTimeTooLow", description: "The engine has not run long enough", hint: Some("Let the engine run and try again") },
+	Nrc { code: 0x86, name: "temperatureTooHigh", description: "Temperature is too high", hint: None },
+	Nrc { code: 0x87, name: "temperatureTooLow", description: "Temperature is too low", hint: Some("Let the engine warm up") },
+	Nrc { code: 0x88, name: "vehicleSpeedTooHigh", description: "The vehicle must be stopped", hint: Some("Stop the vehicle") },
+	Nrc { code: 0x89, name: "vehicleSpeedTooLow", description: "Vehicle speed is too low", hint: None },
+	Nrc { code: 0x8A, name: "throttlePedalTooHigh", description: "The throttle pedal is pressed", hint: Some("Release the throttle pedal") },
+	Nrc { code: 0x8B, name: "throttlePedalTooLow", description: "The throttle pedal must be pressed", hint: None },
+	Nrc { code: 0x8C, name: "transmissionRangeNotInNeutral", description: "The transmission must be in neutral", hint: Some("Shift to neutral") },
+	Nrc { code: 0x8D, name: "transmissionRangeNotInGear", description: "The transmission must be in gear", hint: None },
+	Nrc { code: 0x8F, name: "brakeSwitchNotClosed", description: "The brake pedal must be pressed", hint: Some("Press the brake pedal") },
+	Nrc { code: 0x90, name: "shifterLeverNotInPark", description: "The shifter must be in park", hint: Some("Shift to park") },
+	Nrc { code: 0x91, name: "torqueConverterClutchLocked", description: "The torque converter clutch is locked", hint: None },
+	Nrc { code: 0x92, name: "voltageTooHigh", description: "Supply voltage is too high", hint: Some("Check the battery charger or alternator") },
+	Nrc { code: 0x93, name: "voltageTooLow", description: "Supply voltage is too low", hint: Some("Connect a battery charger") },
+];
+
+/// Looks up a negative response code
+pub fn nrc(code: u8) -> Option<&'static Nrc> {
+	NRCS.iter().find(|nrc| nrc.code == code)
+}
+
+/// Returns the name of a negative response code or "unknown"
+pub fn nrc_name(code: u8) -> &'static str {
+	match nrc(code) {
+		Some(nrc) => nrc.name,
+		None if code >= 0x38 && code <= 0x4F => "reservedByExtendedDataLinkSecurity",
+		None if code >= 0xF0 && code <= 0xFE => "vehicleManufacturerSpecific",
+		None => "unknown",
+	}
+}
+
+/// Returns the name of a UDS or OBD-II service
+pub fn service_name(sid: u8) -> &'static str {
+	match sid {
+		0x01 => "OBD current data",
+		0x02 => "OBD freeze frame data",
+		0x03 => "OBD stored trouble codes",
+		0x04 => "OBD clear trouble codes",
+		0x07 => "OBD pending trouble codes",
+		0x09 => "OBD vehicle information",
+		0x0A => "OBD permanent trouble codes",
+		0x10 => "DiagnosticSessionControl",
+		0x11 => "ECUReset",
+		0x14 => "ClearDiagnosticInformation",
+		0x19 => "ReadDTCInformation",
+		0x22 => "ReadDataByIdentifier",
+		0x23 => "ReadMemoryByAddress",
+		0x27 => "SecurityAccess",
+		0x28 => "CommunicationControl",
+		0x2E => "WriteDataByIdentifier",
+		0x31 => "RoutineControl",
+		0x34 => "RequestDownload",
+		0x35 => "RequestUpload",
+		0x36 => "TransferData",
+		0x37 => "RequestTransferExit",
+		0x3D => "WriteMemoryByAddress",
+		0x3E => "TesterPresent",
+		0x85 => "ControlDTCSetting",
+		_ => "unknown service",
+	}
+}
+
+/// Sends a request. Negative responses are returned as
+/// `Error::NegativeResponse` carrying the service ID.
+pub fn request(interface: &UdsInterface, sid: u8, data: &[u8]) -> Result<Vec<u8>> {
+	interface.request(sid, data).map_err(|err| match err {
+		tuneutils::error::Error::NegativeResponse(nrc) => Error::NegativeResponse { sid: Some(sid), nrc },
+		err => err.into(),
+	})
+}
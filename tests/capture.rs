@@ -0,0 +1,104 @@
+//! Byte-level tests of the capture writers and id filters
+
+use std::{
+	env, fs,
+	path::PathBuf,
+	process,
+	time::{Duration, UNIX_EPOCH},
+};
+
+use libretuner::{
+	capture::{CaptureFormat, CaptureWriter, IdFilter},
+	datalink::message,
+};
+
+/// Writes frames to a capture file and returns its contents
+fn capture(name: &str, format: CaptureFormat, frames: &[(u32, &[u8])]) -> Vec<u8> {
+	let path: PathBuf = env::temp_dir().join(format!("libretuner-{}-{}", name, process::id()));
+	let mut writer = CaptureWriter::create(&path, format, "can 0").unwrap();
+	let time = UNIX_EPOCH + Duration::from_micros(1_436_509_052_249_713);
+	for &(id, data) in frames {
+		writer.write(time, &message(id, data)).unwrap();
+	}
+	writer.flush().unwrap();
+	fs::read(&path).unwrap()
+}
+
+#[test]
+fn candump_lines() {
+	let contents = capture("capture.log", CaptureFormat::Candump, &[
+		(0x7E8, &[0x06, 0x41, 0x00, 0xBE]),
+		(0x18DAF110, &[]),
+	]);
+	assert_eq!(String::from_utf8(contents).unwrap(), "\
+(1436509052.249713) can_0 7E8#064100BE
+(1436509052.249713) can_0 18DAF110#
+");
+}
+
+#[test]
+fn pcap_global_header() {
+	let contents = capture("capture-empty.pcap", CaptureFormat::Pcap, &[]);
+	assert_eq!(contents, vec![
+		0xD4, 0xC3, 0xB2, 0xA1, // magic
+		0x02, 0x00, 0x04, 0x00, // version 2.4
+		0x00, 0x00, 0x00, 0x00, // UTC offset
+		0x00, 0x00, 0x00, 0x00, // accuracy
+		0xFF, 0xFF, 0x00, 0x00, // snaplen
+		0xE3, 0x00, 0x00, 0x00, // LINKTYPE_CAN_SOCKETCAN
+	]);
+}
+
+#[test]
+fn pcap_records() {
+	let contents = capture("capture.pcap", CaptureFormat::Pcap, &[
+		(0x7E8, &[0x06, 0x41, 0x00]),
+		(0x18DAF110, &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]),
+	]);
+	assert_eq!(contents.len(), 24 + 2 * (16 + 16));
+
+	let standard = &contents[24..56];
+	assert_eq!(&standard[..16], &[
+		0x7C, 0x63, 0x9F, 0x55, // seconds
+		0x71, 0xCF, 0x03, 0x00, // microseconds
+		0x10, 0x00, 0x00, 0x00, // captured length
+		0x10, 0x00, 0x00, 0x00, // original length
+	]);
+	assert_eq!(&standard[16..], &[
+		0x00, 0x00, 0x07, 0xE8, // big endian id
+		0x03, 0x00, 0x00, 0x00, // length and padding
+		0x06, 0x41, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+	]);
+
+	// Extended ids have the EFF flag set
+	let extended = &contents[56..];
+	assert_eq!(&extended[16..24], &[0x98, 0xDA, 0xF1, 0x10, 0x08, 0x00, 0x00, 0x00]);
+	assert_eq!(&extended[24..], &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]);
+}
+
+#[test]
+fn filter_parsing() {
+	assert_eq!(IdFilter::parse("7E8"), Some(IdFilter { id: 0x7E8, mask: 0x1FFF_FFFF, inverted: false }));
+	assert_eq!(IdFilter::parse("7E0:7F0"), Some(IdFilter { id: 0x7E0, mask: 0x7F0, inverted: false }));
+	assert_eq!(IdFilter::parse("7DF~7FF"), Some(IdFilter { id: 0x7DF, mask: 0x7FF, inverted: true }));
+	assert_eq!(IdFilter::parse("7E0:"), None);
+	assert_eq!(IdFilter::parse("7E0~"), None);
+	assert_eq!(IdFilter::parse("XYZ"), None);
+	assert_eq!(IdFilter::parse(""), None);
+}
+
+#[test]
+fn filter_matching() {
+	let range = IdFilter::parse("7E0:7F0").unwrap();
+	assert!(range.matches(0x7E0));
+	assert!(range.matches(0x7EF));
+	assert!(!range.matches(0x7DF));
+
+	let single = IdFilter::parse("7E8").unwrap();
+	assert!(single.matches(0x7E8));
+	assert!(!single.matches(0x7E9));
+
+	let inverted = IdFilter::parse("7DF~7FF").unwrap();
+	assert!(!inverted.matches(0x7DF));
+	assert!(inverted.matches(0x7E8));
+}
@@ -0,0 +1,84 @@
+//! Tab completion of command arguments
+
+#![cfg(feature = "cli")]
+
+use libretuner::{
+	cli::ArgKind,
+	completion::{CommandArgs, Completions},
+};
+
+fn completions() -> Completions {
+	let mut completions = Completions::default();
+	completions.commands = vec![
+		CommandArgs {
+			name: "log".to_owned(),
+			args: vec![ArgKind::Datalink, ArgKind::Platform, ArgKind::Pid],
+			options: vec![("-i", ArgKind::Other), ("--interval", ArgKind::Other), ("-r", ArgKind::Other)],
+		},
+		CommandArgs {
+			name: "pids".to_owned(),
+			args: vec![ArgKind::Platform],
+			options: vec![("-l", ArgKind::Datalink), ("--datalink", ArgKind::Datalink)],
+		},
+		CommandArgs {
+			name: "log_open".to_owned(),
+			args: vec![ArgKind::Log],
+			options: vec![("-p", ArgKind::Platform), ("--platform", ArgKind::Platform)],
+		},
+	];
+	completions.datalinks = vec!["sim".to_owned(), "can0".to_owned()];
+	completions.platforms = vec!["obd2".to_owned(), "mazdaspeed6".to_owned()];
+	completions.logs = vec!["run1".to_owned()];
+	completions.pids.insert("obd2".to_owned(), vec!["0x0C".to_owned(), "0x0D".to_owned()]);
+	completions.pids.insert("mazdaspeed6".to_owned(), vec!["1".to_owned(), "2".to_owned()]);
+	completions
+}
+
+#[test]
+fn option_values_are_not_positionals() {
+	let completions = completions();
+	assert_eq!(completions.candidates("log -i 100 "), vec!["can0", "sim"]);
+	assert_eq!(completions.candidates("log -i 100 sim "), vec!["mazdaspeed6", "obd2"]);
+	assert_eq!(completions.candidates("log sim --interval 100 obd2 0x"), vec!["0x0C", "0x0D"]);
+	// Flags do not take the next word
+	assert_eq!(completions.candidates("log --dashboard s"), vec!["sim"]);
+}
+
+#[test]
+fn option_values_complete_by_kind() {
+	let completions = completions();
+	assert_eq!(completions.candidates("pids -l "), vec!["can0", "sim"]);
+	assert_eq!(completions.candidates("log_open --platform m"), vec!["mazdaspeed6"]);
+	assert_eq!(completions.candidates("log_open -p obd2 r"), vec!["run1"]);
+	assert!(completions.candidates("log -i ").is_empty());
+}
+
+#[test]
+fn pids_use_the_configured_platform() {
+	let mut completions = completions();
+	assert_eq!(completions.candidates("log sim mazdaspeed6 "), vec!["1", "2"]);
+	assert!(completions.candidates("log sim nonexistent ").is_empty());
+
+	completions.commands[0].args = vec![ArgKind::Pid];
+	assert!(completions.candidates("log ").is_empty());
+	completions.platform = Some("obd2".to_owned());
+	assert_eq!(completions.candidates("log 0x0C "), vec!["0x0C", "0x0D"]);
+}
+
+#[test]
+fn link_options_replace_positionals() {
+	let mut completions = completions();
+	completions.commands.push(CommandArgs {
+		name: "download".to_owned(),
+		args: vec![ArgKind::Datalink, ArgKind::Platform, ArgKind::Other],
+		options: vec![("-l", ArgKind::Datalink), ("--link", ArgKind::Datalink), ("-p", ArgKind::Platform), ("--platform", ArgKind::Platform)],
+	});
+	completions.commands[0].options.push(("--platform", ArgKind::Platform));
+
+	assert_eq!(completions.candidates("download "), vec!["can0", "sim"]);
+	assert_eq!(completions.candidates("download --link "), vec!["can0", "sim"]);
+	assert!(completions.candidates("download --link sim ").is_empty());
+	assert_eq!(completions.candidates("log --platform mazdaspeed6 "), vec!["1", "2"]);
+	// Platform positionals of commands without a datalink are kept
+	assert_eq!(completions.candidates("pids -l sim o"), vec!["obd2"]);
+}
@@ -0,0 +1,84 @@
+//! ELM327 datalink against an adapter emulated on a pseudo terminal
+
+#![cfg(all(unix, feature = "serial"))]
+
+mod pty;
+
+use std::{
+	io::Write,
+	sync::{Arc, Mutex},
+	thread,
+	time::Duration,
+};
+
+use libretuner::datalink::elm327::{Elm327DataLinkEntry, DEFAULT_BAUD};
+use tuneutils::link::DataLinkEntry;
+
+/// Starts an emulated adapter. Answers AT commands with OK and data with a
+/// frame from the ECU addressed by the last ATSH. Returns the lines received.
+fn emulate() -> (String, Arc<Mutex<Vec<String>>>) {
+	let (mut master, path) = pty::open().unwrap();
+	let lines = Arc::new(Mutex::new(Vec::new()));
+	let received = lines.clone();
+
+	thread::spawn(move || {
+		let mut header = String::new();
+		while let Some(line) = pty::read_line(&mut master) {
+			received.lock().unwrap().push(line.clone());
+			let response = if line == "ATZ" {
+				"\r\rELM327 v1.5\r\r>".to_owned()
+			} else if line.starts_with("ATSH") {
+				header = line[4..].to_owned();
+				"OK\r\r>".to_owned()
+			} else if line.starts_with("AT") {
+				"OK\r\r>".to_owned()
+			} else if header.len() == 3 {
+				// Engine speed from the engine ECU
+				"7E804410C1AF8\r\r>".to_owned()
+			} else {
+				"18DAF11003410C00\r\r>".to_owned()
+			};
+			if master.write_all(response.as_bytes()).is_err() {
+				break;
+			}
+		}
+	});
+	(path, lines)
+}
+
+#[test]
+fn sends_and_receives_frames() {
+	let (port, lines) = emulate();
+	let datalink = Elm327DataLinkEntry { port, baud: DEFAULT_BAUD }.create().unwrap();
+	let can = datalink.can().unwrap();
+
+	can.send(0x7E0, &[0x02, 0x01, 0x0C]).unwrap();
+	let frame = can.recv(Duration::from_secs(1)).unwrap();
+	assert_eq!(frame.id, 0x7E8);
+	assert_eq!(&frame.data[..frame.len as usize], &[0x04, 0x41, 0x0C, 0x1A, 0xF8]);
+
+	let lines = lines.lock().unwrap();
+	assert!(lines.contains(&"ATSP6".to_owned()));
+	assert!(lines.contains(&"ATSH7E0".to_owned()));
+	assert_eq!(lines.last().map(|line| line.as_str()), Some("02010C"));
+}
+
+#[test]
+fn extended_ids_switch_protocol() {
+	let (port, lines) = emulate();
+	let datalink = Elm327DataLinkEntry { port, baud: DEFAULT_BAUD }.create().unwrap();
+	let can = datalink.can().unwrap();
+
+	can.send(0x18DA_10F1, &[0x02, 0x01, 0x0C]).unwrap();
+	let frame = can.recv(Duration::from_secs(1)).unwrap();
+	assert_eq!(frame.id, 0x18DA_F110);
+	assert_eq!(&frame.data[..frame.len as usize], &[0x03, 0x41, 0x0C, 0x00]);
+
+	can.send(0x7E0, &[0x02, 0x01, 0x0C]).unwrap();
+	let frame = can.recv(Duration::from_secs(1)).unwrap();
+	assert_eq!(frame.id, 0x7E8);
+
+	let lines = lines.lock().unwrap();
+	let sent: Vec<&str> = lines.iter().skip_while(|line| line.as_str() != "ATST32").skip(1).map(|line| line.as_str()).collect();
+	assert_eq!(sent, vec!["ATSP7", "ATCP18", "ATSHDA10F1", "02010C", "ATSP6", "ATSH7E0", "02010C"]);
+}
@@ -0,0 +1,86 @@
+//! Parsing and evaluation of expressions
+
+use libretuner::{
+	error::Error,
+	expr::{BinaryOp, Expr},
+};
+
+/// Evaluates an expression with `a` = 2 and `[Engine speed]` = 4500
+fn eval(source: &str) -> Option<f64> {
+	Expr::parse(source).unwrap().eval(&|name: &str| match name {
+		"a" => Some(2.0),
+		"Engine speed" => Some(4500.0),
+		_ => None,
+	})
+}
+
+/// Returns the reason an expression is rejected
+fn error(source: &str) -> String {
+	match Expr::parse(source) {
+		Err(Error::InvalidExpression(ref expression, ref reason)) if expression == source => reason.clone(),
+		Err(err) => panic!("unexpected error {}", err),
+		Ok(expr) => panic!("\"{}\" was accepted as {:?}", source, expr),
+	}
+}
+
+#[test]
+fn precedence() {
+	assert_eq!(eval("1 + 2 * 3"), Some(7.0));
+	assert_eq!(eval("(1 + 2) * 3"), Some(9.0));
+	assert_eq!(eval("10 - 4 - 3"), Some(3.0));
+	assert_eq!(eval("7 % 4 * 2"), Some(6.0));
+	assert_eq!(eval("1 + 1 > 1 && 0 || 1"), Some(1.0));
+	assert_eq!(eval("1 < 2 == 1"), Some(1.0));
+	assert_eq!(eval("!0 + 1"), Some(2.0));
+	// Negation applies after the power
+	assert_eq!(eval("-a ^ 2"), Some(-4.0));
+}
+
+#[test]
+fn power_is_right_associative() {
+	assert_eq!(eval("2 ^ 3 ^ 2"), Some(512.0));
+	assert_eq!(eval("2 ^ -1"), Some(0.5));
+	match Expr::parse("a ^ 2 ^ 3").unwrap() {
+		Expr::Binary(BinaryOp::Pow, ref base, ref exponent) => {
+			assert_eq!(**base, Expr::Variable("a".to_owned()));
+			assert_eq!(**exponent, Expr::parse("2 ^ 3").unwrap());
+		},
+		expr => panic!("unexpected {:?}", expr),
+	}
+}
+
+#[test]
+fn function_arity() {
+	assert_eq!(eval("min(a, 3)"), Some(2.0));
+	assert_eq!(eval("if(a > 1, 10, 20)"), Some(10.0));
+	assert_eq!(eval("abs(-a)"), Some(2.0));
+	assert_eq!(error("min(1)"), "min() takes 2 argument(s)");
+	assert_eq!(error("abs()"), "abs() takes 1 argument(s)");
+	assert_eq!(error("if(1, 2, 3, 4)"), "if() takes 3 argument(s)");
+	assert_eq!(error("log(1)"), "unknown function \"log\"");
+}
+
+#[test]
+fn brackets() {
+	assert_eq!(eval("[Engine speed] > 4000"), Some(1.0));
+	assert_eq!(eval("[ Engine speed ] / a"), Some(2250.0));
+	assert_eq!(Expr::parse("[Engine speed] * [Boost, psi]").unwrap().variables(), vec!["Engine speed", "Boost, psi"]);
+	assert_eq!(error("[Engine speed > 4000"), "unterminated '['");
+	assert_eq!(error("[] + 1"), "empty name in brackets");
+	assert_eq!(error("(1 + 2"), "expected ')'");
+	assert_eq!(error("1 + 2)"), "unexpected input after the expression");
+}
+
+#[test]
+fn hex_literals() {
+	assert_eq!(eval("0x1F"), Some(31.0));
+	assert_eq!(eval("0XFF + 1"), Some(256.0));
+	assert_eq!(eval("1.5e0"), Some(1.5));
+	assert_eq!(error("0xZZ"), "invalid number \"0xZZ\"");
+}
+
+#[test]
+fn unknown_variables() {
+	assert_eq!(eval("a + b"), None);
+	assert_eq!(eval("a * 3"), Some(6.0));
+}
@@ -0,0 +1,212 @@
+//! Recorded log files read back from disk
+
+#![cfg(feature = "cli")]
+
+use std::{
+	env, fs,
+	path::PathBuf,
+	process,
+	time::{Duration, UNIX_EPOCH},
+};
+
+use libretuner::{
+	logfile::{self, CsvWriter, Datalog, Mdf4Writer},
+	logger::{self, Channel, Sample},
+	math::MathChannel,
+	obd,
+};
+use tuneutils::definition::Definitions;
+
+/// Engine speed and two math channels whose names need quoting in CSV
+fn channels() -> Vec<Channel> {
+	let math = vec![
+		MathChannel { name: "Boost, \"gauge\"".to_owned(), unit: "psi".to_owned(), expression: "[Engine speed] / 100".to_owned() },
+		MathChannel { name: "Split\nname".to_owned(), unit: String::new(), expression: "time".to_owned() },
+	];
+	logger::select(&Definitions::default(), obd::PLATFORM_ID, &["0x0C", "Boost, \"gauge\"", "Split\nname"], &math).unwrap()
+}
+
+fn samples() -> Vec<Sample> {
+	vec![
+		Sample { time: 0.0, values: vec![Some(850.0), Some(8.5), Some(0.0)] },
+		Sample { time: 0.1, values: vec![None, Some(-1.25), None] },
+		Sample { time: 0.25, values: vec![Some(3000.5), None, Some(0.25)] },
+	]
+}
+
+fn test_path(name: &str) -> PathBuf {
+	env::temp_dir().join(format!("libretuner-{}-{}", process::id(), name))
+}
+
+/// Checks that a loaded log has the channels and samples written
+fn assert_written(log: &Datalog) {
+	let names: Vec<(&str, &str)> = log.channels.iter().map(|channel| (channel.name.as_str(), channel.unit.as_str())).collect();
+	assert_eq!(names, vec![("Engine speed", "rpm"), ("Boost, \"gauge\"", "psi"), ("Split\nname", "")]);
+	assert_eq!(log.samples.len(), 3);
+	for (loaded, sample) in log.samples.iter().zip(samples()) {
+		assert!((loaded.time - sample.time).abs() < 1e-9);
+		assert_eq!(loaded.values, sample.values);
+	}
+}
+
+#[test]
+fn csv_round_trip() {
+	let path = test_path("round-trip.csv");
+	let channels = channels();
+	let mut writer = CsvWriter::create(&path, &channels).unwrap();
+	for sample in samples() {
+		writer.write(&sample).unwrap();
+	}
+	writer.finish().unwrap();
+
+	let log = Datalog::load(&path).unwrap();
+	assert_written(&log);
+	assert_eq!(log.channel("boost, \"GAUGE\"").unwrap(), 1);
+}
+
+#[test]
+fn csv_errors_report_the_line() {
+	let path = test_path("invalid.csv");
+	fs::write(&path, "Time [s],\"Split\nname []\"\n0.000,1\n0.100,x\n").unwrap();
+	match Datalog::load(&path) {
+		Err(libretuner::error::Error::InvalidLog(4)) => (),
+		Err(err) => panic!("unexpected error {}", err),
+		Ok(_) => panic!("invalid value was accepted"),
+	}
+}
+
+fn u16_at(data: &[u8], offset: u64) -> u16 {
+	let offset = offset as usize;
+	u16::from(data[offset]) | u16::from(data[offset + 1]) << 8
+}
+
+fn u32_at(data: &[u8], offset: u64) -> u32 {
+	(0..4).rev().fold(0, |value, i| value << 8 | u32::from(data[offset as usize + i]))
+}
+
+fn u64_at(data: &[u8], offset: u64) -> u64 {
+	(0..8).rev().fold(0, |value, i| value << 8 | u64::from(data[offset as usize + i]))
+}
+
+/// Checks the id and alignment of the block at `address`
+fn block(data: &[u8], address: u64, id: &str) -> u64 {
+	assert_eq!(address % 8, 0, "##{} block at {} is not aligned", id, address);
+	assert_eq!(&data[address as usize..address as usize + 4], format!("##{}", id).as_bytes());
+	address
+}
+
+/// Returns link `index` of the block at `address`
+fn link(data: &[u8], address: u64, index: u64) -> u64 {
+	assert!(index < u64_at(data, address + 16));
+	u64_at(data, address + 24 + index * 8)
+}
+
+/// Returns the text of a TX block
+fn text(data: &[u8], address: u64) -> String {
+	let start = block(data, address, "TX") as usize + 24;
+	let end = start + data[start..].iter().position(|&b| b == 0).unwrap();
+	String::from_utf8(data[start..end].to_vec()).unwrap()
+}
+
+#[test]
+fn mdf4_blocks() {
+	let path = test_path("blocks.mf4");
+	let channels = channels();
+	let mut writer = Mdf4Writer::create(&path, &channels, UNIX_EPOCH + Duration::from_secs(1_500_000_000)).unwrap();
+	for sample in samples() {
+		writer.write(&sample).unwrap();
+	}
+	writer.finish().unwrap();
+	let data = fs::read(&path).unwrap();
+
+	// Identification block of a finished 4.10 file
+	assert_eq!(&data[..8], b"MDF     ");
+	assert_eq!(&data[8..16], b"4.10    ");
+	assert_eq!(u16_at(&data, 28), 410);
+	assert_eq!(u16_at(&data, 60), 0);
+
+	let hd = block(&data, 64, "HD");
+	assert_eq!(u64_at(&data, hd + 24 + 6 * 8), 1_500_000_000_000_000_000);
+	let fh = block(&data, link(&data, hd, 1), "FH");
+	block(&data, link(&data, fh, 1), "MD");
+	let dg = block(&data, link(&data, hd, 0), "DG");
+	let cg = block(&data, link(&data, dg, 1), "CG");
+	let dt = block(&data, link(&data, dg, 2), "DT");
+
+	// Cycle count, data bytes and invalidation bytes of the records
+	let record_size = 4 * 8 + 1;
+	assert_eq!(u64_at(&data, cg + 24 + 6 * 8 + 8), 3);
+	assert_eq!(u32_at(&data, cg + 24 + 6 * 8 + 24), 4 * 8);
+	assert_eq!(u32_at(&data, cg + 24 + 6 * 8 + 28), 1);
+
+	let mut names = Vec::new();
+	let mut cn = link(&data, cg, 1);
+	while cn != 0 {
+		block(&data, cn, "CN");
+		let unit = link(&data, cn, 6);
+		names.push((text(&data, link(&data, cn, 2)), if unit == 0 { String::new() } else { text(&data, unit) }));
+		cn = link(&data, cn, 0);
+	}
+	assert_eq!(names, vec![
+		("time".to_owned(), "s".to_owned()),
+		("Engine speed".to_owned(), "rpm".to_owned()),
+		("Boost, \"gauge\"".to_owned(), "psi".to_owned()),
+		("Split\nname".to_owned(), String::new()),
+	]);
+
+	// The data block ends the file
+	let length = u64_at(&data, dt + 8);
+	assert_eq!(length, 24 + 3 * record_size);
+	assert_eq!(dt + length, data.len() as u64);
+
+	// The second record has the first and last values invalidated
+	let second = (dt + 24 + record_size) as usize;
+	assert_eq!(&data[second..second + 8], &0.1f64.to_le_bytes());
+	assert_eq!(&data[second + 16..second + 24], &(-1.25f64).to_le_bytes());
+	assert_eq!(data[second + 32], 0b101);
+}
+
+#[test]
+fn mdf4_round_trip() {
+	let path = test_path("round-trip.mf4");
+	let mut writer = Mdf4Writer::create(&path, &channels(), UNIX_EPOCH).unwrap();
+	for sample in samples() {
+		writer.write(&sample).unwrap();
+	}
+	writer.finish().unwrap();
+
+	let log = Datalog::load(&path).unwrap();
+	assert_eq!(log.name, format!("libretuner-{}-round-trip", process::id()));
+	assert_written(&log);
+}
+
+#[test]
+fn unfinished_mdf4_is_read_to_the_last_record() {
+	let path = test_path("unfinished.mf4");
+	{
+		let mut writer = Mdf4Writer::create(&path, &channels(), UNIX_EPOCH).unwrap();
+		for sample in samples() {
+			writer.write(&sample).unwrap();
+		}
+	}
+	// Half a record was written when logging stopped
+	let mut data = fs::read(&path).unwrap();
+	assert_eq!(&data[..8], b"UnFinMF ");
+	data.extend_from_slice(&[0; 12]);
+	fs::write(&path, &data).unwrap();
+
+	assert_written(&Datalog::load(&path).unwrap());
+}
+
+#[test]
+fn logs_fall_back_to_mdf4() {
+	let dir = test_path("logs");
+	fs::create_dir_all(&dir).unwrap();
+	fs::write(dir.join("run.1.mf4"), b"").unwrap();
+	fs::write(dir.join("run.2.mf4"), b"").unwrap();
+	fs::write(dir.join("run.2.csv"), b"").unwrap();
+
+	assert_eq!(logfile::log_file(&dir, "run.1"), dir.join("run.1.mf4"));
+	assert_eq!(logfile::log_file(&dir, "run.2"), dir.join("run.2.csv"));
+	assert_eq!(logfile::log_file(&dir, "other.mf4"), PathBuf::from("other.mf4"));
+}
@@ -0,0 +1,36 @@
+//! Dependencies of math channels
+
+use libretuner::{
+	error::Error,
+	math::{self, MathChannel},
+};
+
+fn channel(name: &str, expression: &str) -> MathChannel {
+	MathChannel { name: name.to_owned(), unit: String::new(), expression: expression.to_owned() }
+}
+
+#[test]
+fn time_is_not_an_input() {
+	let channels = vec![
+		channel("Boost", "[Manifold pressure] - [Barometric pressure]"),
+		channel("Boost rate", "[Boost] / [Time]"),
+	];
+	let (needed, inputs) = math::resolve(&channels, &["boost rate"]).unwrap();
+	assert_eq!(needed.len(), 2);
+	assert_eq!(inputs, vec!["Manifold pressure".to_owned(), "Barometric pressure".to_owned()]);
+}
+
+#[test]
+fn later_math_channels_cannot_be_used() {
+	let channels = vec![
+		channel("Boost psi", "[Boost] * 0.145"),
+		channel("Boost", "[Manifold pressure] - [Barometric pressure]"),
+	];
+	match math::resolve(&channels, &["Boost psi"]) {
+		Err(Error::MathOrder { ref channel, ref name }) => {
+			assert_eq!(channel, "Boost psi");
+			assert_eq!(name, "Boost");
+		},
+		_ => panic!("expected a math order error"),
+	}
+}
@@ -0,0 +1,81 @@
+//! Network datalink against a cannelloni peer on the loopback interface
+
+use std::{
+	io::{Read, Write},
+	net::{TcpListener, UdpSocket},
+	thread,
+	time::Duration,
+};
+
+use libretuner::datalink::net::{NetDataLinkEntry, Protocol};
+use tuneutils::link::DataLinkEntry;
+
+/// A remote frame, a CAN FD frame and a classic frame from 0x7E8 in
+/// cannelloni encoding. Only the classic frame can be received.
+const FRAMES: &[u8] = &[
+	// Remote frame with a requested length of 8
+	0x40, 0x00, 0x07, 0xE8, 0x08,
+	// CAN FD frame with the bitrate switch flag
+	0x00, 0x00, 0x07, 0xE8, 0x83, 0x01, 0x01, 0x02, 0x03,
+	// Classic frame
+	0x00, 0x00, 0x07, 0xE8, 0x04, 0x03, 0x41, 0x0C, 0x1A,
+];
+
+/// Request to 0x7E0 in cannelloni encoding
+const REQUEST: &[u8] = &[0x00, 0x00, 0x07, 0xE0, 0x03, 0x02, 0x01, 0x0C];
+
+#[test]
+fn udp_loopback() {
+	let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+	peer.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+	let datalink = NetDataLinkEntry {
+		remote: peer.local_addr().unwrap().to_string(),
+		local: Some("127.0.0.1:0".to_owned()),
+		protocol: Protocol::Udp,
+	}.create().unwrap();
+	let can = datalink.can().unwrap();
+
+	can.send(0x7E0, &[0x02, 0x01, 0x0C]).unwrap();
+	let mut packet = [0u8; 1500];
+	let (len, source) = peer.recv_from(&mut packet).unwrap();
+	assert_eq!(&packet[..5], &[2, 0, 0, 0, 1]);
+	assert_eq!(&packet[5..len], REQUEST);
+
+	let mut response = vec![2, 0, 0, 0, 3];
+	response.extend_from_slice(FRAMES);
+	peer.send_to(&response, source).unwrap();
+
+	let frame = can.recv(Duration::from_secs(1)).unwrap();
+	assert_eq!(frame.id, 0x7E8);
+	assert_eq!(&frame.data[..frame.len as usize], &[0x03, 0x41, 0x0C, 0x1A]);
+	assert!(can.recv(Duration::from_millis(100)).is_err());
+}
+
+#[test]
+fn tcp_loopback() {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let remote = listener.local_addr().unwrap().to_string();
+	let peer = thread::spawn(move || {
+		let (mut stream, _) = listener.accept().unwrap();
+		let mut greeting = [0u8; 12];
+		stream.read_exact(&mut greeting).unwrap();
+		assert_eq!(&greeting, b"CANNELLONIv1");
+		stream.write_all(b"CANNELLONIv1").unwrap();
+
+		let mut request = [0u8; 8];
+		stream.read_exact(&mut request).unwrap();
+		assert_eq!(&request[..], REQUEST);
+		stream.write_all(FRAMES).unwrap();
+		// Keep the connection open until the frames are read
+		thread::sleep(Duration::from_millis(500));
+	});
+
+	let datalink = NetDataLinkEntry { remote, local: None, protocol: Protocol::Tcp }.create().unwrap();
+	let can = datalink.can().unwrap();
+	can.send(0x7E0, &[0x02, 0x01, 0x0C]).unwrap();
+
+	let frame = can.recv(Duration::from_secs(1)).unwrap();
+	assert_eq!(frame.id, 0x7E8);
+	assert_eq!(&frame.data[..frame.len as usize], &[0x03, 0x41, 0x0C, 0x1A]);
+	peer.join().unwrap();
+}
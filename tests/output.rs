@@ -0,0 +1,84 @@
+//! CSV quoting and table output
+
+#![cfg(feature = "cli")]
+
+use libretuner::output::{csv_records, parse_csv_row, write_csv_row, Format, Table};
+use serde_json::json;
+
+fn csv_row(cells: &[&str]) -> String {
+	let mut out = Vec::new();
+	write_csv_row(&mut out, cells).unwrap();
+	String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn plain_cells_are_not_quoted() {
+	assert_eq!(csv_row(&["time", "Engine speed [rpm]", ""]), "time,Engine speed [rpm],\n");
+}
+
+#[test]
+fn special_cells_are_quoted() {
+	assert_eq!(csv_row(&["a,b"]), "\"a,b\"\n");
+	assert_eq!(csv_row(&["say \"hi\""]), "\"say \"\"hi\"\"\"\n");
+	assert_eq!(csv_row(&["two\nlines", "cr\r"]), "\"two\nlines\",\"cr\r\"\n");
+}
+
+#[test]
+fn rows_round_trip() {
+	let cells = ["", "a,b", "say \"hi\"", "two\nlines", "\"", ",", "plain"];
+	let row = csv_row(&cells);
+	assert_eq!(parse_csv_row(row.trim_end_matches('\n')), cells.to_vec());
+}
+
+#[test]
+fn empty_cells_are_kept() {
+	assert_eq!(parse_csv_row(",,"), vec!["", "", ""]);
+	assert_eq!(parse_csv_row(""), vec![""]);
+	assert_eq!(parse_csv_row("\"\",x"), vec!["", "x"]);
+}
+
+#[test]
+fn records_split_outside_quotes() {
+	let text = "a,\"b\nc\"\r\nd,e\n\nf";
+	assert_eq!(csv_records(text), vec![(1, "a,\"b\nc\""), (3, "d,e"), (4, ""), (5, "f")]);
+	assert_eq!(csv_records("x\n"), vec![(1, "x")]);
+	assert!(csv_records("").is_empty());
+}
+
+fn table() -> Table {
+	let mut table = Table::new(&[("id", "ID"), ("name", "Name"), ("size", "Size")]);
+	table.add_row(vec![json!("rom1"), json!("Stock, v2"), json!(1024)]);
+	table.add_row(vec![json!("a"), json!(null), json!(1.5)]);
+	table
+}
+
+fn written(format: Format) -> String {
+	let mut out = Vec::new();
+	table().write(&mut out, format).unwrap();
+	String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn text_columns_are_aligned() {
+	assert_eq!(written(Format::Text), "ID     Name        Size\nrom1   Stock, v2   1024\na                  1.5\n");
+}
+
+#[test]
+fn csv_uses_keys_and_quotes_cells() {
+	assert_eq!(written(Format::Csv), "id,name,size\nrom1,\"Stock, v2\",1024\na,,1.5\n");
+}
+
+#[test]
+fn json_is_an_array_of_objects() {
+	let value: serde_json::Value = serde_json::from_str(&written(Format::Json)).unwrap();
+	assert_eq!(value, json!([
+		{"id": "rom1", "name": "Stock, v2", "size": 1024},
+		{"id": "a", "name": null, "size": 1.5},
+	]));
+}
+
+#[test]
+fn formats_parse_ignoring_case() {
+	assert_eq!("JSON".parse::<Format>().unwrap(), Format::Json);
+	assert!("xml".parse::<Format>().is_err());
+}
@@ -0,0 +1,51 @@
+//! Pseudo terminals used to emulate serial adapters
+
+use std::{
+	ffi::CStr,
+	fs::File,
+	io::{self, Read},
+	os::{raw::{c_char, c_int}, unix::io::FromRawFd},
+};
+
+extern "C" {
+	fn posix_openpt(flags: c_int) -> c_int;
+	fn grantpt(fd: c_int) -> c_int;
+	fn unlockpt(fd: c_int) -> c_int;
+	fn ptsname(fd: c_int) -> *mut c_char;
+}
+
+const O_RDWR: c_int = 2;
+
+/// Opens a pseudo terminal. Returns the master end and the path of the
+/// slave end, which is opened as a serial port.
+pub fn open() -> io::Result<(File, String)> {
+	unsafe {
+		let fd = posix_openpt(O_RDWR);
+		if fd < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		let master = File::from_raw_fd(fd);
+		if grantpt(fd) != 0 || unlockpt(fd) != 0 {
+			return Err(io::Error::last_os_error());
+		}
+		let name = ptsname(fd);
+		if name.is_null() {
+			return Err(io::Error::last_os_error());
+		}
+		Ok((master, CStr::from_ptr(name).to_string_lossy().into_owned()))
+	}
+}
+
+/// Reads from the master end up to a carriage return. Returns `None` when
+/// the slave end is closed.
+pub fn read_line(master: &mut File) -> Option<String> {
+	let mut line = String::new();
+	let mut byte = [0u8; 1];
+	loop {
+		match master.read(&mut byte) {
+			Ok(1) if byte[0] == b'\r' => return Some(line),
+			Ok(1) => line.push(byte[0] as char),
+			_ => return None,
+		}
+	}
+}
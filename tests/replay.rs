@@ -0,0 +1,159 @@
+//! Tests for loading traces and replaying them with request matching
+
+use std::{
+	env, fs,
+	path::PathBuf,
+	process,
+	time::Duration,
+};
+
+use libretuner::{
+	datalink::replay::{load_trace, ReplayDataLinkEntry},
+	error::Error,
+};
+use tuneutils::link::DataLinkEntry;
+
+/// Writes a trace to a temporary file
+fn write_trace(name: &str, contents: &str) -> PathBuf {
+	let path = env::temp_dir().join(format!("libretuner-{}-{}", name, process::id()));
+	fs::write(&path, contents).unwrap();
+	path
+}
+
+#[test]
+fn candump_skips_unusable_frames() {
+	let path = write_trace("candump.log", "\
+(1436509052.249713) can0 7E0#02010C0000000000
+(1436509052.250000) can0 7E8##1044102030405060708090A0B0C0D0E0F
+(1436509052.251000) can0 7E8#0102030405060708090A
+(1436509052.252000) can0 7E8#123
+(1436509052.253000) can0 7E8#R
+(1436509052.254000) can0 18DAF110#04410C1AF8
+");
+	let frames = load_trace(&path).unwrap();
+	assert_eq!(frames.len(), 3);
+	assert_eq!(frames[0].id, 0x7E0);
+	assert_eq!(frames[0].data, vec![0x02, 0x01, 0x0C, 0, 0, 0, 0, 0]);
+	assert!(frames[1].data.is_empty());
+	assert_eq!(frames[2].id, 0x18DAF110);
+	assert_eq!(frames[2].data, vec![0x04, 0x41, 0x0C, 0x1A, 0xF8]);
+	// Times are relative to the first frame
+	assert!(frames[0].time.abs() < 1e-9);
+	assert!((frames[2].time - 0.004287).abs() < 1e-6);
+}
+
+#[test]
+fn candump_rejects_lines_that_are_not_frames() {
+	let path = write_trace("candump-invalid.log", "\
+(1436509052.249713) can0 7E0#02010C0000000000
+(1436509052.250000) can0 7E8
+");
+	match load_trace(&path) {
+		Err(Error::InvalidTrace(2)) => (),
+		other => panic!("expected an invalid trace at line 2, got {:?}", other.map(|frames| frames.len())),
+	}
+
+	let path = write_trace("candump-hex.log", "(1436509052.249713) can0 7E0#02GG\n");
+	assert!(load_trace(&path).is_err());
+}
+
+#[test]
+fn asc_skips_headers_and_events() {
+	let path = write_trace("trace.asc", "\
+date Thu Jul 9 10:00:00 am 2015
+base hex  timestamps absolute
+Begin Triggerblock Thu Jul 9 10:00:00 am 2015
+   0.000000 Start of measurement
+   0.001000 1  7E0             Tx   d 8 02 01 0C 00 00 00 00 00
+   0.002000 1  18DAF110x       Rx   d 5 04 41 0C 1A F8
+   0.003000 CANFD   1 Rx   7E8  1 0 d 12 01 02 03 04 05 06 07 08 09 0A 0B 0C
+End TriggerBlock
+");
+	let frames = load_trace(&path).unwrap();
+	assert_eq!(frames.len(), 2);
+	assert_eq!(frames[0].id, 0x7E0);
+	assert_eq!(frames[0].data, vec![0x02, 0x01, 0x0C, 0, 0, 0, 0, 0]);
+	assert_eq!(frames[1].id, 0x18DAF110);
+	assert_eq!(frames[1].data, vec![0x04, 0x41, 0x0C, 0x1A, 0xF8]);
+	assert!((frames[1].time - 0.001).abs() < 1e-9);
+}
+
+#[test]
+fn requests_match_by_payload() {
+	let path = write_trace("matching.log", "\
+(0.000) can0 7E0#02010C0000000000
+(0.010) can0 7E8#04410C1AF8AAAAAA
+(0.020) can0 7E0#0209020000000000
+(0.030) can0 7E8#1014490201574155
+(0.031) can0 7E0#3000000000000000
+(0.040) can0 7E8#215A5A5A38453735
+(0.041) can0 7E8#2238303030303130
+(0.050) can0 7E0#0210030000000000
+(0.060) can0 7E8#065003003201F4AA
+");
+	let datalink = ReplayDataLinkEntry::new(path, false, true).unwrap().create().unwrap();
+	let can = datalink.can().unwrap();
+	let timeout = Duration::from_millis(10);
+
+	// Sent without padding
+	can.send(0x7E0, &[0x02, 0x01, 0x0C]).unwrap();
+	let frame = can.recv(timeout).unwrap();
+	assert_eq!(frame.id, 0x7E8);
+	assert_eq!(&frame.data[..frame.len as usize], &[0x04, 0x41, 0x0C, 0x1A, 0xF8, 0xAA, 0xAA, 0xAA]);
+	// The next request ends the response
+	assert!(can.recv(timeout).is_err());
+
+	// Sent with different padding and with flow control interleaved
+	can.send(0x7E0, &[0x02, 0x09, 0x02, 0x55, 0x55, 0x55, 0x55, 0x55]).unwrap();
+	let frame = can.recv(timeout).unwrap();
+	assert_eq!(frame.data[0], 0x10);
+	can.send(0x7E0, &[0x30, 0x00, 0x00]).unwrap();
+	assert_eq!(can.recv(timeout).unwrap().data[0], 0x21);
+	assert_eq!(can.recv(timeout).unwrap().data[0], 0x22);
+	assert!(can.recv(timeout).is_err());
+
+	// Requests can be repeated out of order
+	can.send(0x7E0, &[0x02, 0x01, 0x0C]).unwrap();
+	assert_eq!(can.recv(timeout).unwrap().data[1], 0x41);
+
+	// Unknown requests are errors
+	assert!(can.send(0x7E0, &[0x02, 0x01, 0x0D]).is_err());
+}
+
+#[test]
+fn recorded_flow_control_is_skipped() {
+	let path = write_trace("flow-control.log", "\
+(0.000) can0 7E0#0209020000000000
+(0.010) can0 7E8#1014490201574155
+(0.011) can0 7E0#3000000000000000
+(0.020) can0 7E8#215A5A5A38453735
+");
+	let datalink = ReplayDataLinkEntry::new(path, false, true).unwrap().create().unwrap();
+	let can = datalink.can().unwrap();
+	let timeout = Duration::from_millis(10);
+
+	// A tester that does not send flow control still gets the whole response
+	can.send(0x7E0, &[0x02, 0x09, 0x02]).unwrap();
+	assert_eq!(can.recv(timeout).unwrap().data[0], 0x10);
+	assert_eq!(can.recv(timeout).unwrap().data[0], 0x21);
+	assert!(can.recv(timeout).is_err());
+}
+
+#[test]
+fn multi_frame_requests_match_by_payload() {
+	let path = write_trace("multi-frame.log", "\
+(0.000) can0 7E0#100A2E0101020304
+(0.010) can0 7E8#3000000000000000
+(0.020) can0 7E0#2105060708CCCCCC
+(0.030) can0 7E8#036E0101AAAAAAAA
+");
+	let datalink = ReplayDataLinkEntry::new(path, false, true).unwrap().create().unwrap();
+	let can = datalink.can().unwrap();
+	let timeout = Duration::from_millis(10);
+
+	can.send(0x7E0, &[0x10, 0x0A, 0x2E, 0x01, 0x01, 0x02, 0x03, 0x04]).unwrap();
+	assert_eq!(can.recv(timeout).unwrap().data[0], 0x30);
+	// The last consecutive frame is padded differently
+	can.send(0x7E0, &[0x21, 0x05, 0x06, 0x07, 0x08]).unwrap();
+	assert_eq!(can.recv(timeout).unwrap().data[1], 0x6E);
+}
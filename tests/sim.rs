@@ -0,0 +1,132 @@
+//! End-to-end tests against the simulated ECU datalink
+
+#![cfg(feature = "cli")]
+
+use std::{
+	env, fs,
+	path::PathBuf,
+	process,
+	rc::Rc,
+};
+
+use libretuner::{
+	app::App,
+	datalink::{self, LinkConfig, SavedLink, net::Protocol, sim::{Ecu, SimConfig, SimDataLinkEntry}},
+	info::EcuInfo,
+	obd,
+	uds,
+};
+use tuneutils::{
+	diagnostics::UdsScanner,
+	download::DownloadCallback,
+	link::DataLinkEntry,
+	protocols::{isotp, uds::{UdsInterface, UdsIsotp}},
+};
+
+/// Creates an empty directory for a test
+fn test_dir(name: &str) -> PathBuf {
+	let dir = env::temp_dir().join(format!("libretuner-{}-{}", name, process::id()));
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(dir.join("config").join("definitions")).unwrap();
+	dir
+}
+
+/// Opens a UDS interface to a simulated ECU with the default configuration
+fn sim_interface() -> Rc<UdsInterface> {
+	let datalink = SimDataLinkEntry::new(None).unwrap().create().unwrap();
+	let isotp = Rc::new(isotp::IsotpCan::new(datalink.can().unwrap(), isotp::Options::default()));
+	Rc::new(UdsIsotp::new(isotp))
+}
+
+fn stored_codes(interface: &Rc<UdsInterface>) -> Vec<String> {
+	UdsScanner::new(interface.clone()).scan().unwrap().iter().map(|code| code.to_string()).collect()
+}
+
+#[test]
+fn scanner_reads_and_clears_codes() {
+	let interface = sim_interface();
+	let codes = stored_codes(&interface);
+	assert!(codes.contains(&"P0301".to_owned()), "codes: {:?}", codes);
+	assert!(codes.contains(&"P0420".to_owned()), "codes: {:?}", codes);
+
+	uds::request(&*interface, 0x14, &[0xFF, 0xFF, 0xFF]).unwrap();
+	assert!(stored_codes(&interface).is_empty());
+}
+
+#[test]
+fn multi_frame_responses_are_reassembled() {
+	let interface = sim_interface();
+	// ReadMemoryByAddress with a 4 byte address and 2 byte size
+	let response = uds::request(&*interface, 0x23, &[0x24, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00]).unwrap();
+	assert_eq!(response, vec![0; 0x100]);
+}
+
+#[test]
+fn responses_too_long_for_isotp_are_rejected() {
+	let mut ecu = Ecu::new(SimConfig::default(), vec![0; 0x2000]);
+	// The response repeats the service, so 0xFFE bytes is the most that fits
+	assert_eq!(ecu.handle(&[0x23, 0x24, 0x00, 0x00, 0x00, 0x00, 0x0F, 0xFE]).len(), 0xFFF);
+	assert_eq!(ecu.handle(&[0x23, 0x24, 0x00, 0x00, 0x00, 0x00, 0x0F, 0xFF]), vec![0x7F, 0x23, 0x14]);
+}
+
+#[test]
+fn links_without_aliases_round_trip() {
+	let dir = test_dir("links");
+	let path = dir.join("config").join("links.toml");
+	let links = vec![
+		SavedLink { name: "sim".to_owned(), config: LinkConfig::Sim { config: None } },
+		SavedLink {
+			name: "gateway".to_owned(),
+			config: LinkConfig::Net { remote: "gateway:20000".to_owned(), local: None, protocol: Protocol::Tcp },
+		},
+	];
+	datalink::save_links(&path, &links, &[]).unwrap();
+
+	let file = datalink::load_links(&path).unwrap();
+	assert!(file.alias.is_empty());
+	let loaded: Vec<(&str, &LinkConfig)> = file.link.iter().map(|link| (link.name.as_str(), &link.config)).collect();
+	assert_eq!(loaded, vec![("sim", &links[0].config), ("gateway", &links[1].config)]);
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn app_reads_identification_through_added_link() {
+	let dir = test_dir("identification");
+	let mut app = App::open(dir.join("config"), dir.join("data")).unwrap();
+	app.add_link("sim", LinkConfig::Sim { config: None }).unwrap();
+
+	let interface = app.create_uds("sim", obd::PLATFORM_ID).unwrap();
+	let info = EcuInfo::read(&*interface).unwrap();
+	assert_eq!(info.vin, Some("LIBRETUNERSIM0001".to_owned()));
+
+	// The link is saved and loaded again
+	let app = App::open(dir.join("config"), dir.join("data")).unwrap();
+	assert!(app.avail_links.iter().any(|link| link.name == "sim" && link.saved()));
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Downloads a ROM from the simulator through `App::download`. Needs the
+/// platform definitions, which are not part of this repository: set
+/// `LIBRETUNER_DEFINITIONS` to the definitions directory and
+/// `LIBRETUNER_SIM_PLATFORM` to a platform that downloads with
+/// ReadMemoryByAddress, then run with `--ignored`.
+#[cfg(unix)]
+#[test]
+#[ignore]
+fn app_downloads_rom() {
+	let definitions = env::var("LIBRETUNER_DEFINITIONS").expect("LIBRETUNER_DEFINITIONS is not set");
+	let platform = env::var("LIBRETUNER_SIM_PLATFORM").expect("LIBRETUNER_SIM_PLATFORM is not set");
+
+	let dir = test_dir("download");
+	fs::remove_dir(dir.join("config").join("definitions")).unwrap();
+	::std::os::unix::fs::symlink(definitions, dir.join("config").join("definitions")).unwrap();
+
+	let mut app = App::open(dir.join("config"), dir.join("data")).unwrap();
+	app.add_link("sim", LinkConfig::Sim { config: None }).unwrap();
+	let link = app.create_platform_link("sim", &platform).unwrap();
+	let info = app.download(&link, "simrom", "Simulated ROM", &DownloadCallback::with(|_| {})).unwrap();
+
+	assert_eq!(info.and_then(|info| info.vin), Some("LIBRETUNERSIM0001".to_owned()));
+	assert!(app.rom_info_path("simrom").exists());
+	fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,67 @@
+//! SLCAN datalink against an adapter emulated on a pseudo terminal
+
+#![cfg(all(unix, feature = "serial"))]
+
+mod pty;
+
+use std::{
+	io::Write,
+	sync::{Arc, Mutex},
+	thread,
+	time::Duration,
+};
+
+use libretuner::datalink::slcan::{SlcanDataLinkEntry, DEFAULT_BAUD, DEFAULT_BITRATE};
+use tuneutils::link::DataLinkEntry;
+
+/// Starts an emulated adapter. Acknowledges commands and answers frames
+/// sent to 0x7E0 with a frame from 0x7E8, preceded by lines that are neither
+/// frames nor acknowledgements. Frames to other ids are rejected with BEL.
+/// Returns the lines received.
+fn emulate() -> (String, Arc<Mutex<Vec<String>>>) {
+	let (mut master, path) = pty::open().unwrap();
+	let lines = Arc::new(Mutex::new(Vec::new()));
+	let received = lines.clone();
+
+	thread::spawn(move || {
+		while let Some(line) = pty::read_line(&mut master) {
+			received.lock().unwrap().push(line.clone());
+			let response = if line.starts_with("t7E0") {
+				"z\rV1013\rr7E80\rt7E8504410C1AF81234\r"
+			} else if line.starts_with('t') {
+				"V1013\r\x07"
+			} else {
+				"\r"
+			};
+			if master.write_all(response.as_bytes()).is_err() {
+				break;
+			}
+		}
+	});
+	(path, lines)
+}
+
+#[test]
+fn sends_and_receives_frames() {
+	let (port, lines) = emulate();
+	let datalink = SlcanDataLinkEntry { port, baud: DEFAULT_BAUD, bitrate: DEFAULT_BITRATE }.create().unwrap();
+	let can = datalink.can().unwrap();
+
+	can.send(0x7E0, &[0x02, 0x01, 0x0C]).unwrap();
+	let frame = can.recv(Duration::from_secs(1)).unwrap();
+	assert_eq!(frame.id, 0x7E8);
+	assert_eq!(&frame.data[..frame.len as usize], &[0x04, 0x41, 0x0C, 0x1A, 0xF8]);
+
+	let lines = lines.lock().unwrap();
+	assert_eq!(*lines, vec!["C", "S6", "Z1", "O", "t7E0302010C"]);
+}
+
+#[test]
+fn other_lines_do_not_acknowledge() {
+	let (port, _) = emulate();
+	let datalink = SlcanDataLinkEntry { port, baud: DEFAULT_BAUD, bitrate: DEFAULT_BITRATE }.create().unwrap();
+	let can = datalink.can().unwrap();
+
+	assert!(can.send(0x123, &[0x01]).is_err());
+	assert!(can.recv(Duration::from_millis(100)).is_err());
+}